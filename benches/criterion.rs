@@ -2,7 +2,10 @@ use std::{hint::black_box, io::Cursor};
 
 use criterion::{criterion_group, criterion_main, Criterion};
 use smoke::{
-    messages::{Drain, EmbMessage, Source},
+    messages::{
+        vlink::{Signal, SignalRef},
+        Drain, EmbMessage, MessageStream, Source,
+    },
     User,
 };
 use tokio::io::BufReader;
@@ -147,6 +150,96 @@ async fn encode_decode_multiple_fragmented_setup(&quantity: &u64) -> Builder {
     builder
 }
 
+const VLINK_DATA_SIZE: usize = 2048;
+
+async fn vlink_encode_owned(data: &[u8]) {
+    let mut msg_bytes = Vec::<u8>::with_capacity(VLINK_DATA_SIZE * 2);
+    let mut ser_buf = [0u8; VLINK_DATA_SIZE * 2];
+    let action = vlink::Action::Data(80, data);
+    Signal::from_vlink(&action)
+        .serialize_to(&mut msg_bytes, &mut ser_buf)
+        .expect("could not serialize")
+        .await
+        .expect("could not send");
+    black_box(msg_bytes);
+}
+
+async fn vlink_encode_borrowed(data: &[u8]) {
+    let mut msg_bytes = Vec::<u8>::with_capacity(VLINK_DATA_SIZE * 2);
+    let mut ser_buf = [0u8; VLINK_DATA_SIZE * 2];
+    let action = vlink::Action::Data(80, data);
+    SignalRef::from_vlink(&action)
+        .serialize_to(&mut msg_bytes, &mut ser_buf)
+        .expect("could not serialize")
+        .await
+        .expect("could not send");
+    black_box(msg_bytes);
+}
+
+async fn vlink_decode_owned(bytes: &[u8], quantity: u64) {
+    let mut reader = BufReader::new(bytes);
+
+    for _ in 0..quantity {
+        let _signal = black_box(reader.read_message::<Signal>().await);
+    }
+}
+
+async fn vlink_decode_borrowed(bytes: &[u8], quantity: u64) {
+    let mut reader = BufReader::new(bytes);
+    let mut buf = Vec::with_capacity(VLINK_DATA_SIZE * 2);
+
+    for _ in 0..quantity {
+        let _signal = black_box(reader.read_message_ref::<SignalRef>(&mut buf).await);
+    }
+}
+
+async fn vlink_decode_batched(bytes: &[u8], quantity: u64) {
+    let mut reader = BufReader::new(bytes);
+    let mut messages = Vec::with_capacity(quantity as usize);
+
+    while (messages.len() as u64) < quantity {
+        let _count = black_box(reader.read_messages_into::<Signal>(&mut messages).await);
+    }
+    black_box(messages);
+}
+
+async fn vlink_decode_stream(bytes: &[u8], quantity: u64) {
+    let mut stream = MessageStream::<_, Signal>::new(BufReader::new(bytes));
+
+    for _ in 0..quantity {
+        let _signal = black_box(stream.next().await);
+    }
+}
+
+async fn vlink_data_setup(quantity: u64) -> Vec<u8> {
+    let data = vec![0xA5u8; VLINK_DATA_SIZE];
+    let mut msg_bytes = Vec::<u8>::new();
+    let mut ser_buf = [0u8; VLINK_DATA_SIZE * 2];
+    for _ in 0..quantity {
+        SignalRef::Data(80, &data)
+            .serialize_to(&mut msg_bytes, &mut ser_buf)
+            .expect("could not serialize")
+            .await
+            .unwrap();
+    }
+    msg_bytes
+}
+
+const BURST: u64 = 100;
+
+async fn vlink_burst_setup(signal: SignalRef<'_>, quantity: u64) -> Vec<u8> {
+    let mut msg_bytes = Vec::<u8>::new();
+    let mut ser_buf = [0u8; VLINK_DATA_SIZE * 2];
+    for _ in 0..quantity {
+        signal
+            .serialize_to(&mut msg_bytes, &mut ser_buf)
+            .expect("could not serialize")
+            .await
+            .unwrap();
+    }
+    msg_bytes
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .build()
@@ -197,6 +290,55 @@ fn criterion_benchmark(c: &mut Criterion) {
         b.to_async(&runtime)
             .iter(|| encode_decode_multiple_fragmented(quantity))
     });
+
+    group.finish();
+
+    let mut group = c.benchmark_group("vlink_data");
+    let data = vec![0xA5u8; VLINK_DATA_SIZE];
+    let msg_bytes = runtime.block_on(vlink_data_setup(QUANTITY));
+
+    group.bench_with_input("encode_owned", &data, |b, data| {
+        b.to_async(&runtime).iter(|| vlink_encode_owned(data))
+    });
+    group.bench_with_input("encode_borrowed", &data, |b, data| {
+        b.to_async(&runtime).iter(|| vlink_encode_borrowed(data))
+    });
+    group.bench_with_input("decode_owned", &msg_bytes, |b, bytes| {
+        b.to_async(&runtime)
+            .iter(|| vlink_decode_owned(bytes, QUANTITY))
+    });
+    group.bench_with_input("decode_borrowed", &msg_bytes, |b, bytes| {
+        b.to_async(&runtime)
+            .iter(|| vlink_decode_borrowed(bytes, QUANTITY))
+    });
+
+    group.finish();
+
+    // small payloads so a single buffer fill holds the whole burst
+    let data = vec![0xA5u8; 64];
+    let bursts = [
+        ("batch_decode_data", SignalRef::Data(80, &data)),
+        ("batch_decode_window", SignalRef::WindowUpdate(80, 64)),
+    ];
+    for (name, signal) in bursts {
+        let mut group = c.benchmark_group(name);
+        let msg_bytes = runtime.block_on(vlink_burst_setup(signal, BURST));
+
+        group.bench_with_input("single", &msg_bytes, |b, bytes| {
+            b.to_async(&runtime)
+                .iter(|| vlink_decode_owned(bytes, BURST))
+        });
+        group.bench_with_input("batched", &msg_bytes, |b, bytes| {
+            b.to_async(&runtime)
+                .iter(|| vlink_decode_batched(bytes, BURST))
+        });
+        group.bench_with_input("stream", &msg_bytes, |b, bytes| {
+            b.to_async(&runtime)
+                .iter(|| vlink_decode_stream(bytes, BURST))
+        });
+
+        group.finish();
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
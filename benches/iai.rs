@@ -58,7 +58,72 @@ async fn encode_decode_multiple_fragmented_setup(&quantity: &u64) -> Builder {
     builder
 }
 
-iai::main!(cursor_single_test, fragmented_test, setup, combined_setup);
+async fn decode_burst(bytes: &[u8], quantity: u64) {
+    let mut reader = BufReader::new(bytes);
+
+    for _ in 0..quantity {
+        let _signal = black_box(reader.read_message::<EmbMessage>().await);
+    }
+}
+
+async fn decode_burst_batched(bytes: &[u8], quantity: u64) {
+    let mut reader = BufReader::new(bytes);
+    let mut messages = Vec::with_capacity(quantity as usize);
+
+    while (messages.len() as u64) < quantity {
+        let _count = black_box(reader.read_messages_into::<EmbMessage>(&mut messages).await);
+    }
+    black_box(messages);
+}
+
+async fn burst_setup(quantity: u64) -> Vec<u8> {
+    let msg = EmbMessage::Room(User {
+        cert_data: b"Aurelia".to_vec(),
+    });
+    let mut msg_bytes = Vec::<u8>::new();
+    let mut ser_buf = [0u8; smoke::messages::EMB_MESSAGE_BUF_SIZE];
+    for _ in 0..quantity {
+        msg.clone()
+            .serialize_to(&mut msg_bytes, &mut ser_buf)
+            .expect("could not serialize")
+            .await
+            .unwrap();
+    }
+    msg_bytes
+}
+
+iai::main!(
+    cursor_single_test,
+    fragmented_test,
+    burst_single_test,
+    burst_batched_test,
+    setup,
+    combined_setup,
+    burst_setup_test
+);
+
+fn burst_single_test() {
+    let runtime = setup();
+
+    runtime.block_on(async {
+        let bytes = burst_setup(50).await;
+        decode_burst(black_box(&bytes), 50).await
+    });
+}
+
+fn burst_batched_test() {
+    let runtime = setup();
+
+    runtime.block_on(async {
+        let bytes = burst_setup(50).await;
+        decode_burst_batched(black_box(&bytes), 50).await
+    });
+}
+
+fn burst_setup_test() {
+    let runtime = setup();
+    runtime.block_on(burst_setup(50));
+}
 
 fn fragmented_test() {
     let runtime = setup();
@@ -0,0 +1,117 @@
+//! Server side enforcement of [EmbMessage::Block], [EmbMessage::Unblock] and [EmbMessage::DoNotDisturb]
+
+use std::collections::{HashMap, HashSet};
+
+use crate::messages::{EmbMessage, ErrorCode, RhizMessage, ServerError};
+use crate::room_requests::RoomRequests;
+use crate::User;
+
+/// Block lists and do not disturb state of all [User]s connected to Rhizome
+///
+/// Rhizome feeds every received [EmbMessage] into [BlockList::apply] and
+/// turns every [EmbMessage::Room] request into the [RhizMessage::WantsRoom]
+/// for its target with [BlockList::forward_room].
+#[derive(Debug)]
+pub struct BlockList {
+    max_blocked: usize,
+    blocked: HashMap<User, HashSet<User>>,
+    dnd: HashSet<User>,
+}
+
+impl BlockList {
+    /// `max_blocked` is the amount of [User]s that a single [User] may block at once
+    pub fn new(max_blocked: usize) -> Self {
+        BlockList {
+            max_blocked,
+            blocked: HashMap::new(),
+            dnd: HashSet::new(),
+        }
+    }
+
+    /// Updates the lists of `user` if `msg` is a block list control message
+    ///
+    /// Returns `Ok(true)` if `msg` was consumed and `Ok(false)` if it has to be handled elsewhere.
+    ///
+    /// # Errors
+    /// A [ServerError] with [ErrorCode::ProtocolViolation] when `user` tries to block more than `max_blocked` [User]s
+    pub fn apply(&mut self, user: &User, msg: &EmbMessage) -> Result<bool, ServerError> {
+        match msg {
+            EmbMessage::Block(target) => {
+                let blocked = self.blocked.entry(user.clone()).or_default();
+                if blocked.len() >= self.max_blocked && !blocked.contains(target) {
+                    return Err(ServerError::new(
+                        ErrorCode::ProtocolViolation,
+                        format!("cannot block more than {} users", self.max_blocked),
+                    ));
+                }
+                blocked.insert(target.clone());
+            }
+            EmbMessage::Unblock(target) => {
+                if let Some(blocked) = self.blocked.get_mut(user) {
+                    blocked.remove(target);
+                    if blocked.is_empty() {
+                        self.blocked.remove(user);
+                    }
+                }
+            }
+            EmbMessage::DoNotDisturb(true) => {
+                self.dnd.insert(user.clone());
+            }
+            EmbMessage::DoNotDisturb(false) => {
+                self.dnd.remove(user);
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Returns true if a [EmbMessage::Room] request from `from` may reach `to`
+    pub fn permits_room(&self, from: &User, to: &User) -> bool {
+        if self.dnd.contains(to) {
+            return false;
+        }
+
+        !self.is_blocked(to, from)
+    }
+
+    /// Registers the [EmbMessage::Room] request of `from` to `to` in `requests` and returns
+    /// the [RhizMessage::WantsRoom] that Rhizome forwards to `to`
+    ///
+    /// Returns `None` without registering the request when `to` blocked `from` or enabled
+    /// do not disturb mode. The request is dropped silently, to `from` it looks like an
+    /// unanswered one.
+    pub fn forward_room(
+        &self,
+        from: &User,
+        to: &User,
+        requests: &mut RoomRequests,
+    ) -> Option<RhizMessage> {
+        if !self.permits_room(from, to) {
+            tracing::debug!("dropped room request to a user that does not accept it");
+            return None;
+        }
+
+        Some(requests.request(from, to))
+    }
+
+    /// Returns true if `user` enabled do not disturb mode
+    pub fn is_dnd(&self, user: &User) -> bool {
+        self.dnd.contains(user)
+    }
+
+    /// Returns true if `user` blocked `target`
+    pub fn is_blocked(&self, user: &User, target: &User) -> bool {
+        self.blocked
+            .get(user)
+            .is_some_and(|blocked| blocked.contains(target))
+    }
+
+    /// Forgets the do not disturb state of `user`
+    ///
+    /// Do not disturb is tied to a connection, Rhizome calls this once `user` disconnects.
+    /// Block lists are kept.
+    pub fn disconnect(&mut self, user: &User) {
+        self.dnd.remove(user);
+    }
+}
@@ -0,0 +1,272 @@
+//! Credit based flow control for vlink tunnels
+//!
+//! Every vport has an independent window in each direction. The sender may only
+//! transfer as many [Signal::Data] payload bytes as the receiver granted, the receiver
+//! grants new credit with [Signal::WindowUpdate] once data was written to its local socket.
+//! A slow local reader therefore stalls the remote sender instead of making either side
+//! buffer without bound.
+
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::messages::vlink::{ErrorKindCode, Signal};
+use crate::policy::Throttle;
+use crate::stats::TrafficStats;
+
+/// Credit both sides of a vport start with
+pub const DEFAULT_WINDOW: u32 = 64 * 1024;
+/// Largest payload of a single [Signal::Data] created by [forward_outbound]
+pub const MAX_CHUNK: usize = 2048;
+
+/// Credit that the peer granted for sending on a vport
+///
+/// Clones share the same credit. The link reader calls [SendWindow::grant]
+/// for every [Signal::WindowUpdate] of the vport.
+#[derive(Clone, Debug)]
+pub struct SendWindow {
+    credit: Arc<Semaphore>,
+    stats: Option<Arc<TrafficStats>>,
+}
+
+impl SendWindow {
+    pub fn new(initial: u32) -> Self {
+        SendWindow {
+            credit: Arc::new(Semaphore::new(initial as usize)),
+            stats: None,
+        }
+    }
+
+    /// Accounts the data sent by [forward_outbound] and the error closing the vport in `stats`
+    pub fn with_stats(mut self, stats: Arc<TrafficStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Adds `credit` bytes to the window
+    pub fn grant(&self, credit: u32) {
+        self.credit.add_permits(credit as usize);
+    }
+
+    /// Amount of bytes that can be sent without waiting for a [Signal::WindowUpdate]
+    pub fn available(&self) -> usize {
+        self.credit.available_permits()
+    }
+}
+
+/// Reads from the local socket and sends its data as [Signal::Data] on `vport`
+///
+/// Waits for credit in `window` before reading, every chunk is limited to the available
+/// credit and [MAX_CHUNK]. Windows smaller than [MAX_CHUNK] therefore never stall.
+/// An error of `reader` is recorded as [Signal::Error] of `vport` in the stats of `window`.
+///
+/// # Cancel safety
+/// This method is not cancellation safe. Data that has been read from `reader`
+/// but not yet sent is lost.
+///
+/// # Errors
+/// This function will return:</br>
+/// The first error returned by `reader`<br>
+/// An [io::ErrorKind::BrokenPipe] when the `link` was closed
+pub async fn forward_outbound<R>(
+    vport: u16,
+    reader: &mut R,
+    window: &SendWindow,
+    link: &mpsc::Sender<Signal>,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buf = [0u8; MAX_CHUNK];
+    loop {
+        // reserve the credit before reading, a chunk never exceeds what the peer granted
+        window
+            .credit
+            .acquire()
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "send window was closed"))?
+            .forget();
+        // clones of the window may take the credit in the meantime, only count what was acquired
+        let wanted = window.available().min(MAX_CHUNK - 1) as u32;
+        let extra = match window.credit.try_acquire_many(wanted) {
+            Ok(permits) => {
+                permits.forget();
+                wanted
+            }
+            Err(_) => 0,
+        };
+        let credit = 1 + extra as usize;
+
+        let n = match reader.read(&mut buf[..credit]).await {
+            Ok(n) => n,
+            Err(err) => {
+                window.grant(credit as u32);
+                if let Some(stats) = &window.stats {
+                    record_error(stats, vport, &err);
+                }
+                return Err(err);
+            }
+        };
+        window.grant((credit - n) as u32);
+        if n == 0 {
+            return Ok(());
+        }
+
+        if let Some(stats) = &window.stats {
+            stats.add_bytes_out(vport, n as u64);
+        }
+
+        link.send(Signal::Data(vport, buf[..n].to_vec()))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "vlink was closed"))?;
+    }
+}
+
+/// Creates the receiving side of a vport with an initial credit of `window` bytes
+pub fn recv_window(window: u32) -> (RecvGate, RecvQueue) {
+    let outstanding = Arc::new(AtomicU32::new(window));
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let gate = RecvGate {
+        outstanding: outstanding.clone(),
+        queue: tx,
+        stats: None,
+    };
+    let queue = RecvQueue {
+        window,
+        outstanding,
+        queue: rx,
+        throttle: None,
+        stats: None,
+    };
+    (gate, queue)
+}
+
+/// Admits [Signal::Data] of the peer into the [RecvQueue] of a vport
+///
+/// Used by the link reader, admitting never blocks.
+#[derive(Clone, Debug)]
+pub struct RecvGate {
+    outstanding: Arc<AtomicU32>,
+    queue: mpsc::UnboundedSender<Vec<u8>>,
+    stats: Option<(u16, Arc<TrafficStats>)>,
+}
+
+impl RecvGate {
+    /// Accounts the data admitted for `vport` in `stats`
+    pub fn with_stats(mut self, vport: u16, stats: Arc<TrafficStats>) -> Self {
+        self.stats = Some((vport, stats));
+        self
+    }
+
+    /// Queues the payload of a [Signal::Data] for [forward_inbound]
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidData] when the peer sent more data than it was granted<br>
+    /// An [io::ErrorKind::BrokenPipe] when the [RecvQueue] was dropped
+    pub fn admit(&self, data: Vec<u8>) -> io::Result<()> {
+        let len = u32::try_from(data.len()).unwrap_or(u32::MAX);
+        self.outstanding
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |outstanding| {
+                outstanding.checked_sub(len)
+            })
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "peer exceeded its send window")
+            })?;
+
+        if let Some((vport, stats)) = &self.stats {
+            stats.add_bytes_in(*vport, len as u64);
+        }
+
+        self.queue
+            .send(data)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "vport was closed"))
+    }
+}
+
+/// Data of a vport that has been admitted but not yet written to the local socket
+#[derive(Debug)]
+pub struct RecvQueue {
+    window: u32,
+    outstanding: Arc<AtomicU32>,
+    queue: mpsc::UnboundedReceiver<Vec<u8>>,
+    throttle: Option<Throttle>,
+    stats: Option<Arc<TrafficStats>>,
+}
+
+impl RecvQueue {
+    /// Delays the credit granted by [forward_inbound] until `throttle` allows it
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Records the error closing the vport in [forward_inbound] in `stats`
+    pub fn with_stats(mut self, stats: Arc<TrafficStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+}
+
+/// Writes the data admitted by the [RecvGate]s of `queue` to the local socket
+///
+/// Once half of the window has been written a [Signal::WindowUpdate] is sent on `link`,
+/// after waiting for the [Throttle] of the queue if it has one.
+/// An error of `writer` is recorded as [Signal::Error] of `vport` in the stats of `queue`.
+/// Returns when all [RecvGate]s have been dropped and the queue is empty.
+///
+/// # Cancel safety
+/// This method is not cancellation safe. Data may have been partially written to `writer`.
+///
+/// # Errors
+/// This function will return:</br>
+/// The first error returned by `writer`<br>
+/// An [io::ErrorKind::BrokenPipe] when the `link` was closed
+pub async fn forward_inbound<W>(
+    vport: u16,
+    writer: &mut W,
+    queue: &mut RecvQueue,
+    link: &mpsc::Sender<Signal>,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let threshold = (queue.window / 2).max(1);
+    let mut consumed = 0u32;
+
+    while let Some(data) = queue.queue.recv().await {
+        if let Err(err) = writer.write_all(&data).await {
+            if let Some(stats) = &queue.stats {
+                record_error(stats, vport, &err);
+            }
+            return Err(err);
+        }
+        consumed += data.len() as u32;
+
+        if consumed >= threshold {
+            if let Some(throttle) = &queue.throttle {
+                throttle.delay(consumed).await;
+            }
+            queue.outstanding.fetch_add(consumed, Ordering::AcqRel);
+            link.send(Signal::WindowUpdate(vport, consumed))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "vlink was closed"))?;
+            consumed = 0;
+        }
+    }
+
+    writer.flush().await
+}
+
+/// Records the [Signal::Error] that closes `vport` after its local socket failed
+fn record_error(stats: &TrafficStats, vport: u16, err: &io::Error) {
+    stats.record(&Signal::Error(
+        vport,
+        ErrorKindCode::from(err.kind()),
+        err.to_string(),
+    ));
+}
@@ -0,0 +1,222 @@
+//! Full-duplex message connections
+//!
+//! A [Connection] owns a stream together with all of its framing state. It can be
+//! split into an owned [Reader] and [Writer] that are moved to different tasks and
+//! reunited later without losing buffered data.
+
+use std::fmt;
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, ReadHalf, WriteHalf};
+
+use crate::messages::{MessageReader, MessageWriter};
+
+/// Stream `S` that reads messages of type `I` and writes messages of type `O`
+///
+/// For a client peer connection this is `Connection<TlsStream<TcpStream>, Signal, Signal>`.
+pub struct Connection<S, I, O> {
+    reader: Reader<S, I>,
+    writer: Writer<S, O>,
+}
+
+impl<S, I, O> Connection<S, I, O>
+where
+    S: AsyncRead + AsyncWrite,
+    I: DeserializeOwned,
+    O: Serialize,
+{
+    pub fn new(stream: S) -> Self {
+        let (read, write) = tokio::io::split(stream);
+        Connection {
+            reader: Reader {
+                inner: MessageReader::new(BufReader::new(read)),
+            },
+            writer: Writer {
+                inner: MessageWriter::new(write),
+            },
+        }
+    }
+
+    /// Receives the next message, refer to [Reader::recv]
+    pub async fn recv(&mut self) -> io::Result<I> {
+        self.reader.recv().await
+    }
+
+    /// Sends `message`, refer to [Writer::send]
+    pub async fn send(&mut self, message: O) -> io::Result<()> {
+        self.writer.send(message).await
+    }
+
+    /// Splits the connection into halves that can be used from different tasks
+    pub fn into_split(self) -> (Reader<S, I>, Writer<S, O>) {
+        (self.reader, self.writer)
+    }
+
+    /// Returns the stream, data that was read but not yet received as a message is lost
+    pub fn into_inner(self) -> S
+    where
+        S: Unpin,
+    {
+        let read = self.reader.inner.into_inner().into_inner();
+        read.unsplit(self.writer.inner.into_inner())
+    }
+}
+
+impl<S, I, O> fmt::Debug for Connection<S, I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection").finish_non_exhaustive()
+    }
+}
+
+/// Owned read half of a [Connection]
+pub struct Reader<S, I> {
+    inner: MessageReader<BufReader<ReadHalf<S>>, I>,
+}
+
+impl<S, I> Reader<S, I>
+where
+    S: AsyncRead + AsyncWrite,
+    I: DeserializeOwned,
+{
+    /// Receives the next message
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe. Partially read messages are kept and
+    /// completed by the next call.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by the stream<br>
+    /// An [io::ErrorKind::ConnectionReset] when the stream reached EOF<br>
+    /// An [io::ErrorKind::Other] when the data is not a valid [I]
+    pub async fn recv(&mut self) -> io::Result<I> {
+        poll_fn(|cx| Pin::new(&mut self.inner).poll_next(cx))
+            .await
+            .unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "EOF reached",
+                ))
+            })
+    }
+
+    /// Reunites the halves of the same [Connection]
+    ///
+    /// # Errors
+    /// Both halves are returned when they do not belong to the same [Connection]
+    #[allow(clippy::result_large_err)]
+    pub fn reunite<O>(
+        self,
+        writer: Writer<S, O>,
+    ) -> Result<Connection<S, I, O>, ReuniteError<S, I, O>> {
+        let read = self.inner.get_ref().get_ref();
+        if read.is_pair_of(writer.inner.get_ref()) {
+            Ok(Connection {
+                reader: self,
+                writer,
+            })
+        } else {
+            Err(ReuniteError(self, writer))
+        }
+    }
+}
+
+impl<S, I> Stream for Reader<S, I>
+where
+    S: AsyncRead + AsyncWrite,
+    I: DeserializeOwned,
+{
+    type Item = io::Result<I>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl<S, I> fmt::Debug for Reader<S, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reader").finish_non_exhaustive()
+    }
+}
+
+/// Owned write half of a [Connection]
+pub struct Writer<S, O> {
+    inner: MessageWriter<WriteHalf<S>, O>,
+}
+
+impl<S, O> Writer<S, O>
+where
+    S: AsyncRead + AsyncWrite,
+    O: Serialize,
+{
+    /// Sends `message` and flushes the stream
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. The message may have been partially
+    /// written, the rest is written before the next message.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when `message` was unable to be serialized<br>
+    /// The first error returned by the stream
+    pub async fn send(&mut self, message: O) -> io::Result<()> {
+        let mut sink = Pin::new(&mut self.inner);
+        poll_fn(|cx| sink.as_mut().poll_ready(cx)).await?;
+        sink.as_mut().start_send(message)?;
+        poll_fn(|cx| sink.as_mut().poll_flush(cx)).await
+    }
+}
+
+impl<S, O> Sink<O> for Writer<S, O>
+where
+    S: AsyncRead + AsyncWrite,
+    O: Serialize,
+{
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: O) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl<S, O> fmt::Debug for Writer<S, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Writer").finish_non_exhaustive()
+    }
+}
+
+/// Error of [Reader::reunite] with the halves of two different [Connection]s
+pub struct ReuniteError<S, I, O>(pub Reader<S, I>, pub Writer<S, O>);
+
+impl<S, I, O> fmt::Debug for ReuniteError<S, I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReuniteError").finish()
+    }
+}
+
+impl<S, I, O> fmt::Display for ReuniteError<S, I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tried to reunite halves of different connections")
+    }
+}
+
+impl<S, I, O> std::error::Error for ReuniteError<S, I, O> {}
@@ -0,0 +1,354 @@
+//! UDP forwarding for vlink tunnels
+//!
+//! Datagrams are transferred as [Signal::Datagram]. Every UDP peer of the listening
+//! side gets its own `flow` id so answers of the service reach the right peer.
+//! Flows that have been idle for longer than the idle timeout are closed on
+//! both sides with [Signal::FlowClosed].
+//! A socket error that only concerns a single flow, like an ICMP port unreachable
+//! of its UDP peer, closes that flow the same way instead of stopping the forwarding.
+//! Every vport has at most [MAX_FLOWS] flows, further flows are refused.
+//! With [TrafficStats] the drivers account every datagram and flow they forward.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant, MissedTickBehavior};
+
+use crate::messages::vlink::Signal;
+use crate::stats::TrafficStats;
+
+/// Time after which a flow without any datagrams is closed
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest datagram payload that is forwarded, larger datagrams are dropped
+pub const MAX_DATAGRAM_SIZE: usize = 2048;
+/// Amount of flows a vport may have open at the same time
+pub const MAX_FLOWS: usize = 256;
+
+/// Forwards the datagrams that local UDP peers send to `socket` over the vlink
+///
+/// This is the side of the tunnel that local applications connect to.
+/// Answers of the service ([Signal::Datagram] on `link_in`) are sent back to the
+/// UDP peer of their flow. Every forwarded signal is recorded in `stats`.
+/// Returns once `link_in` was closed.
+///
+/// # Cancel safety
+/// This method is not cancellation safe. All flows are forgotten when it is cancelled.
+///
+/// # Errors
+/// This function will return:</br>
+/// The first error returned by receiving on `socket` that does not concern a single flow<br>
+/// An [io::ErrorKind::BrokenPipe] when `link_out` was closed
+pub async fn forward_listener(
+    vport: u16,
+    socket: &UdpSocket,
+    idle_timeout: Duration,
+    link_in: &mut mpsc::Receiver<Signal>,
+    link_out: &mpsc::Sender<Signal>,
+    stats: Option<Arc<TrafficStats>>,
+) -> io::Result<()> {
+    let mut flows: HashMap<u32, (SocketAddr, Instant)> = HashMap::new();
+    let mut by_addr: HashMap<SocketAddr, u32> = HashMap::new();
+    let mut next_flow = 0u32;
+
+    let mut sweep = sweep_interval(idle_timeout);
+    // one additional byte to detect oversized datagrams
+    let mut buf = [0u8; MAX_DATAGRAM_SIZE + 1];
+
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (n, addr) = match received {
+                    Ok(received) => received,
+                    // reported for an earlier datagram of any flow, the flow is closed once it is idle
+                    Err(err) if is_flow_error(&err) => {
+                        tracing::debug!("udp peer of vport {} failed: {}", vport, err);
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                if n > MAX_DATAGRAM_SIZE {
+                    tracing::warn!("dropped datagram from {} exceeding {} bytes", addr, MAX_DATAGRAM_SIZE);
+                    continue;
+                }
+
+                let flow = match by_addr.entry(addr) {
+                    Entry::Occupied(entry) => *entry.get(),
+                    Entry::Vacant(_) if flows.len() >= MAX_FLOWS => {
+                        tracing::warn!("dropped datagram from {}, vport {} has {} flows", addr, vport, MAX_FLOWS);
+                        continue;
+                    }
+                    Entry::Vacant(entry) => {
+                        let flow = next_flow;
+                        next_flow = next_flow.wrapping_add(1);
+                        *entry.insert(flow)
+                    }
+                };
+                flows.insert(flow, (addr, Instant::now()));
+
+                send(link_out, stats.as_deref(), Signal::Datagram(vport, flow, buf[..n].to_vec())).await?;
+            }
+            signal = link_in.recv() => match signal {
+                None => return Ok(()),
+                Some(Signal::Datagram(v, flow, data)) if v == vport => {
+                    let Some((addr, last_seen)) = flows.get_mut(&flow) else {
+                        continue;
+                    };
+                    *last_seen = Instant::now();
+                    let addr = *addr;
+                    received(stats.as_deref(), vport, flow, &data);
+
+                    if let Err(err) = socket.send_to(&data, addr).await {
+                        tracing::debug!("closed flow {} of vport {}: {}", flow, vport, err);
+                        flows.remove(&flow);
+                        by_addr.remove(&addr);
+                        send(link_out, stats.as_deref(), Signal::FlowClosed(vport, flow)).await?;
+                    }
+                }
+                Some(Signal::FlowClosed(v, flow)) if v == vport => {
+                    if let Some((addr, _)) = flows.remove(&flow) {
+                        by_addr.remove(&addr);
+                    }
+                    if let Some(stats) = &stats {
+                        stats.record(&Signal::FlowClosed(vport, flow));
+                    }
+                }
+                Some(_) => {}
+            },
+            _ = sweep.tick() => {
+                let now = Instant::now();
+                let idle: Vec<u32> = flows
+                    .iter()
+                    .filter(|(_, (_, last_seen))| now.duration_since(*last_seen) >= idle_timeout)
+                    .map(|(flow, _)| *flow)
+                    .collect();
+
+                for flow in idle {
+                    if let Some((addr, _)) = flows.remove(&flow) {
+                        by_addr.remove(&addr);
+                    }
+                    send(link_out, stats.as_deref(), Signal::FlowClosed(vport, flow)).await?;
+                }
+            }
+        }
+    }
+}
+
+struct ServiceFlow {
+    socket: Arc<UdpSocket>,
+    last_seen: Arc<Mutex<Instant>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for ServiceFlow {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Forwards the datagrams received over the vlink to the UDP service at `target`
+///
+/// This is the side of the tunnel that hosts the service. Every flow uses its
+/// own local UDP socket so the service can tell the UDP peers apart.
+/// Flows beyond [MAX_FLOWS] are answered with [Signal::FlowClosed].
+/// Every forwarded signal is recorded in `stats`. Returns once `link_in` was closed.
+///
+/// # Cancel safety
+/// This method is not cancellation safe. All flows are closed when it is cancelled.
+///
+/// # Errors
+/// This function will return:</br>
+/// An [io::ErrorKind::BrokenPipe] when `link_out` was closed
+pub async fn forward_service(
+    vport: u16,
+    target: SocketAddr,
+    idle_timeout: Duration,
+    link_in: &mut mpsc::Receiver<Signal>,
+    link_out: &mpsc::Sender<Signal>,
+    stats: Option<Arc<TrafficStats>>,
+) -> io::Result<()> {
+    let mut flows: HashMap<u32, ServiceFlow> = HashMap::new();
+    let mut sweep = sweep_interval(idle_timeout);
+    // flows whose socket failed while receiving
+    let (failed_tx, mut failed_rx) = mpsc::unbounded_channel();
+
+    loop {
+        tokio::select! {
+            signal = link_in.recv() => match signal {
+                None => return Ok(()),
+                Some(Signal::Datagram(v, flow, data)) if v == vport => {
+                    let full = flows.len() >= MAX_FLOWS;
+                    let service_flow = match flows.entry(flow) {
+                        Entry::Occupied(entry) => {
+                            received(stats.as_deref(), vport, flow, &data);
+                            entry.into_mut()
+                        }
+                        Entry::Vacant(_) if full => {
+                            tracing::debug!("refused flow {} of vport {}, it has {} flows", flow, vport, MAX_FLOWS);
+                            send(link_out, stats.as_deref(), Signal::FlowClosed(vport, flow)).await?;
+                            continue;
+                        }
+                        // the first datagram of a flow opens it
+                        Entry::Vacant(entry) => {
+                            received(stats.as_deref(), vport, flow, &data);
+                            match open_flow(vport, flow, target, link_out.clone(), failed_tx.clone(), stats.clone()).await {
+                                Ok(service_flow) => entry.insert(service_flow),
+                                Err(err) => {
+                                    tracing::debug!("failed to open flow {} of vport {}: {}", flow, vport, err);
+                                    send(link_out, stats.as_deref(), Signal::FlowClosed(vport, flow)).await?;
+                                    continue;
+                                }
+                            }
+                        }
+                    };
+
+                    *service_flow.last_seen.lock().expect("poisoned flow") = Instant::now();
+                    if let Err(err) = service_flow.socket.send(&data).await {
+                        tracing::debug!("closed flow {} of vport {}: {}", flow, vport, err);
+                        flows.remove(&flow);
+                        send(link_out, stats.as_deref(), Signal::FlowClosed(vport, flow)).await?;
+                    }
+                }
+                Some(Signal::FlowClosed(v, flow)) if v == vport => {
+                    flows.remove(&flow);
+                    if let Some(stats) = &stats {
+                        stats.record(&Signal::FlowClosed(vport, flow));
+                    }
+                }
+                Some(_) => {}
+            },
+            Some((flow, socket)) = failed_rx.recv() => {
+                // the flow may have been closed and reopened in the meantime
+                if flows.get(&flow).is_some_and(|service_flow| Arc::ptr_eq(&service_flow.socket, &socket)) {
+                    flows.remove(&flow);
+                    send(link_out, stats.as_deref(), Signal::FlowClosed(vport, flow)).await?;
+                }
+            }
+            _ = sweep.tick() => {
+                let now = Instant::now();
+                let idle: Vec<u32> = flows
+                    .iter()
+                    .filter(|(_, service_flow)| {
+                        let last_seen = *service_flow.last_seen.lock().expect("poisoned flow");
+                        now.duration_since(last_seen) >= idle_timeout
+                    })
+                    .map(|(flow, _)| *flow)
+                    .collect();
+
+                for flow in idle {
+                    flows.remove(&flow);
+                    send(link_out, stats.as_deref(), Signal::FlowClosed(vport, flow)).await?;
+                }
+            }
+        }
+    }
+}
+
+async fn open_flow(
+    vport: u16,
+    flow: u32,
+    target: SocketAddr,
+    link_out: mpsc::Sender<Signal>,
+    failed: mpsc::UnboundedSender<(u32, Arc<UdpSocket>)>,
+    stats: Option<Arc<TrafficStats>>,
+) -> io::Result<ServiceFlow> {
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+
+    let socket = Arc::new(socket);
+    let last_seen = Arc::new(Mutex::new(Instant::now()));
+
+    let task = {
+        let socket = socket.clone();
+        let last_seen = last_seen.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DATAGRAM_SIZE + 1];
+            loop {
+                let n = match socket.recv(&mut buf).await {
+                    Ok(n) => n,
+                    Err(err) => {
+                        tracing::debug!("flow {} of vport {} failed: {}", flow, vport, err);
+                        let _ = failed.send((flow, socket));
+                        return;
+                    }
+                };
+                if n > MAX_DATAGRAM_SIZE {
+                    tracing::warn!(
+                        "dropped datagram of flow {} exceeding {} bytes",
+                        flow,
+                        MAX_DATAGRAM_SIZE
+                    );
+                    continue;
+                }
+
+                *last_seen.lock().expect("poisoned flow") = Instant::now();
+                if send(
+                    &link_out,
+                    stats.as_deref(),
+                    Signal::Datagram(vport, flow, buf[..n].to_vec()),
+                )
+                .await
+                .is_err()
+                {
+                    return;
+                }
+            }
+        })
+    };
+
+    Ok(ServiceFlow {
+        socket,
+        last_seen,
+        task,
+    })
+}
+
+/// Errors of a UDP socket caused by a single unreachable peer
+fn is_flow_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset
+    )
+}
+
+fn sweep_interval(idle_timeout: Duration) -> time::Interval {
+    let period = (idle_timeout / 2).max(Duration::from_millis(1));
+    let mut sweep = time::interval_at(Instant::now() + period, period);
+    sweep.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    sweep
+}
+
+/// Accounts a [Signal::Datagram] of `flow` received from the peer
+fn received(stats: Option<&TrafficStats>, vport: u16, flow: u32, data: &[u8]) {
+    if let Some(stats) = stats {
+        stats.add_datagram_in(vport, flow, data.len() as u64);
+    }
+}
+
+async fn send(
+    link: &mpsc::Sender<Signal>,
+    stats: Option<&TrafficStats>,
+    signal: Signal,
+) -> io::Result<()> {
+    if let Some(stats) = stats {
+        match &signal {
+            Signal::Datagram(vport, flow, data) => {
+                stats.add_datagram_out(*vport, *flow, data.len() as u64)
+            }
+            signal => stats.record(signal),
+        }
+    }
+    link.send(signal)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "vlink was closed"))
+}
@@ -0,0 +1,149 @@
+//! Server side bookkeeping of [EmbMessage::PublishDevice] and [EmbMessage::RevokeDevice]
+
+use std::collections::{HashMap, HashSet};
+
+use crate::messages::device::PersonId;
+use crate::messages::{EmbMessage, ErrorCode, ServerError};
+use crate::User;
+
+/// Amount of revoked devices that are remembered per person
+pub const MAX_REVOKED_DEVICES: usize = 256;
+
+/// Devices of all persons known to Rhizome
+///
+/// Rhizome feeds every received [EmbMessage] into [DeviceRegistry::apply] and
+/// forwards a [EmbMessage::Room] request for a [User] as
+/// [RhizMessage::WantsRoom](crate::messages::RhizMessage::WantsRoom) to every
+/// [User] returned by [DeviceRegistry::devices_of].
+#[derive(Debug)]
+pub struct DeviceRegistry {
+    max_devices: usize,
+    persons: HashMap<User, PersonId>,
+    devices: HashMap<PersonId, HashSet<User>>,
+    revoked: HashMap<PersonId, HashSet<User>>,
+}
+
+impl DeviceRegistry {
+    /// `max_devices` is the amount of devices a single person may have at once
+    pub fn new(max_devices: usize) -> Self {
+        DeviceRegistry {
+            max_devices,
+            persons: HashMap::new(),
+            devices: HashMap::new(),
+            revoked: HashMap::new(),
+        }
+    }
+
+    /// Updates the devices if `msg` is a device control message sent by `user`
+    ///
+    /// Returns `Ok(true)` if `msg` was consumed and `Ok(false)` if it has to be handled elsewhere.
+    ///
+    /// # Errors
+    /// A [ServerError] with [ErrorCode::ProtocolViolation] when:</br>
+    /// The signature of the certificate or revocation is invalid<br>
+    /// `user` revokes a device of a person it is not a device of<br>
+    /// `user` publishes a certificate for a different [User]<br>
+    /// `user` already is a device of a different person or was revoked<br>
+    /// The person would have more than `max_devices` devices<br>
+    /// The person would have more than [MAX_REVOKED_DEVICES] revoked devices
+    pub fn apply(&mut self, user: &User, msg: &EmbMessage) -> Result<bool, ServerError> {
+        match msg {
+            EmbMessage::PublishDevice(cert) => {
+                if &cert.device != user {
+                    return Err(violation("device certificate of a different user"));
+                }
+                cert.verify()
+                    .map_err(|_| violation("invalid device certificate"))?;
+                if self.is_revoked(&cert.person, user) {
+                    return Err(violation("device was revoked"));
+                }
+                if self
+                    .persons
+                    .get(user)
+                    .is_some_and(|person| person != &cert.person)
+                {
+                    return Err(violation("device belongs to a different person"));
+                }
+
+                let devices = self.devices.entry(cert.person.clone()).or_default();
+                if devices.len() >= self.max_devices && !devices.contains(user) {
+                    return Err(violation(format!(
+                        "cannot have more than {} devices",
+                        self.max_devices
+                    )));
+                }
+                devices.insert(user.clone());
+                self.persons.insert(user.clone(), cert.person.clone());
+            }
+            EmbMessage::RevokeDevice(revocation) => {
+                revocation
+                    .verify()
+                    .map_err(|_| violation("invalid device revocation"))?;
+                let person = &revocation.person;
+                let device = &revocation.device;
+
+                // only persons with a published device are remembered, which bounds the revocations
+                if self.persons.get(user) != Some(person) {
+                    return Err(violation("revocation of a device of a different person"));
+                }
+
+                // the device may be offline, so the revocation is remembered even if it was never published
+                let revoked = self.revoked.entry(person.clone()).or_default();
+                if revoked.len() >= MAX_REVOKED_DEVICES && !revoked.contains(device) {
+                    return Err(violation(format!(
+                        "cannot revoke more than {} devices",
+                        MAX_REVOKED_DEVICES
+                    )));
+                }
+                revoked.insert(device.clone());
+
+                // a person can only remove its own devices
+                if self.persons.get(device) == Some(person) {
+                    self.persons.remove(device);
+                    if let Some(devices) = self.devices.get_mut(person) {
+                        devices.remove(device);
+                        if devices.is_empty() {
+                            self.devices.remove(person);
+                        }
+                    }
+                }
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the person `user` is a device of
+    pub fn person_of(&self, user: &User) -> Option<&PersonId> {
+        self.persons.get(user)
+    }
+
+    /// Returns all devices of the person `user` belongs to
+    ///
+    /// A [User] without a published [DeviceCert](crate::messages::device::DeviceCert) is its only device.
+    pub fn devices_of<'a>(&'a self, user: &'a User) -> Vec<&'a User> {
+        match self
+            .persons
+            .get(user)
+            .and_then(|person| self.devices.get(person))
+        {
+            Some(devices) => devices.iter().collect(),
+            None => vec![user],
+        }
+    }
+
+    /// Returns true if `person` revoked `device`
+    ///
+    /// Revocations are remembered whether or not the device was published before,
+    /// as long as they were sent by another device of the same person.
+    pub fn is_revoked(&self, person: &PersonId, device: &User) -> bool {
+        self.revoked
+            .get(person)
+            .is_some_and(|revoked| revoked.contains(device))
+    }
+}
+
+fn violation(message: impl Into<String>) -> ServerError {
+    ServerError::new(ErrorCode::ProtocolViolation, message)
+}
@@ -0,0 +1,370 @@
+//! End-to-end encryption of p2p messages
+//!
+//! After holepunching both peers run a [Handshake]: an ephemeral X25519 key exchange whose
+//! transcript is signed with the key of each peer's [User] certificate. The derived
+//! [SecureChannel] seals every message into a [Sealed] frame with ChaCha20-Poly1305.
+//!
+//! Frames carry their own counter, so [SecureChannel::seal] and [SecureChannel::open]
+//! work on any transport. Over a stream use [SecureChannel::send] and [SecureChannel::recv],
+//! over UDP send one [Sealed] per datagram; lost frames are tolerated, reordered
+//! and replayed frames are rejected.
+
+use std::fmt;
+use std::io;
+
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::digest::{self, SHA256};
+use ring::hkdf::{self, HKDF_SHA256};
+use ring::rand::SystemRandom;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt};
+
+use crate::identity::{Identity, SignatureScheme};
+use crate::messages::Source;
+use crate::User;
+
+const PROTOCOL: &[u8] = b"smoke e2e v1";
+
+/// First message of a [Handshake], carries the ephemeral X25519 public key
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Hello(pub [u8; 32]);
+
+/// Encrypted and authenticated message of a [SecureChannel]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Sealed {
+    /// Position of the frame, strictly increasing per direction
+    pub counter: u64,
+    /// Ciphertext of the serialized ([postcard]) message followed by the tag
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// Proof that the sender holds the key of its certificate, first frame of every direction
+#[derive(Serialize, Deserialize, Debug)]
+struct Auth {
+    scheme: SignatureScheme,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+/// Key exchange of a single peer
+pub struct Handshake<'a> {
+    identity: &'a Identity,
+    private_key: EphemeralPrivateKey,
+    hello: Hello,
+}
+
+impl<'a> Handshake<'a> {
+    /// Starts the key exchange, the returned [Hello] has to be sent to the peer
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::Error] when the system random number generator failed
+    pub fn new(identity: &'a Identity) -> io::Result<(Handshake<'a>, Hello)> {
+        let rng = SystemRandom::new();
+        let private_key = EphemeralPrivateKey::generate(&X25519, &rng).map_err(|_| {
+            io::Error::new(io::ErrorKind::Other, "failed to generate ephemeral key")
+        })?;
+        let mut hello = Hello([0; 32]);
+        hello.0.copy_from_slice(
+            private_key
+                .compute_public_key()
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::Other, "failed to compute ephemeral key")
+                })?
+                .as_ref(),
+        );
+
+        let handshake = Handshake {
+            identity,
+            private_key,
+            hello,
+        };
+        Ok((handshake, hello))
+    }
+
+    /// Derives the [SecureChannel] with `peer` from its [Hello]
+    ///
+    /// The returned [Sealed] authenticates this side and has to be sent to the peer,
+    /// which passes it to [SecureChannel::authenticate].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidData] when `peer_hello` is not a valid key or was reflected<br>
+    /// An [io::Error] when signing the transcript failed
+    pub fn finish(self, peer_hello: Hello, peer: &User) -> io::Result<(SecureChannel, Sealed)> {
+        if peer_hello == self.hello {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "peer reflected our hello",
+            ));
+        }
+
+        // the peer with the lower ephemeral key takes the role "low"
+        let is_low = self.hello.0 < peer_hello.0;
+        let own = (&self.hello, &self.identity.user().cert_data);
+        let other = (&peer_hello, &peer.cert_data);
+        let (low, high) = if is_low { (own, other) } else { (other, own) };
+
+        let mut ctx = digest::Context::new(&SHA256);
+        ctx.update(PROTOCOL);
+        ctx.update(&low.0 .0);
+        ctx.update(&high.0 .0);
+        for cert in [low.1, high.1] {
+            ctx.update(&(cert.len() as u64).to_be_bytes());
+            ctx.update(cert);
+        }
+        let transcript = ctx.finish();
+
+        let (low_key, high_key) = agreement::agree_ephemeral(
+            self.private_key,
+            &UnparsedPublicKey::new(&X25519, peer_hello.0),
+            invalid_hello(),
+            |shared_secret| {
+                let prk = hkdf::Salt::new(HKDF_SHA256, transcript.as_ref()).extract(shared_secret);
+                let derive = |info: &[u8]| -> io::Result<LessSafeKey> {
+                    let info = [info];
+                    let okm = prk
+                        .expand(&info, &CHACHA20_POLY1305)
+                        .map_err(|_| invalid_hello())?;
+                    Ok(LessSafeKey::new(UnboundKey::from(okm)))
+                };
+                Ok((derive(b"low")?, derive(b"high")?))
+            },
+        )?;
+        let (seal_key, open_key) = if is_low {
+            (low_key, high_key)
+        } else {
+            (high_key, low_key)
+        };
+
+        let mut channel = SecureChannel {
+            seal_key,
+            open_key,
+            transcript: transcript.as_ref().to_vec(),
+            peer: peer.clone(),
+            next_seal: 0,
+            next_open: 0,
+            authenticated: false,
+        };
+
+        let (scheme, signature) = self.identity.sign(&channel.transcript)?;
+        let auth = channel.seal_frame(&Auth { scheme, signature })?;
+        Ok((channel, auth))
+    }
+}
+
+impl fmt::Debug for Handshake<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handshake")
+            .field("identity", &self.identity)
+            .field("hello", &self.hello)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypted and mutually authenticated channel with a peer
+pub struct SecureChannel {
+    seal_key: LessSafeKey,
+    open_key: LessSafeKey,
+    transcript: Vec<u8>,
+    peer: User,
+    next_seal: u64,
+    next_open: u64,
+    authenticated: bool,
+}
+
+impl SecureChannel {
+    /// Runs the [Handshake] with `peer` over `stream` and waits for its authentication
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. The stream has to be dropped when it is cancelled.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by the stream<br>
+    /// An [io::ErrorKind::InvalidData] when the peer sent an invalid handshake<br>
+    /// An [io::ErrorKind::PermissionDenied] when the peer does not hold the key of `peer`
+    pub async fn handshake<S>(
+        stream: &mut S,
+        identity: &Identity,
+        peer: &User,
+    ) -> io::Result<SecureChannel>
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin,
+    {
+        let (handshake, hello) = Handshake::new(identity)?;
+        write_message(stream, &hello).await?;
+        let peer_hello = stream.read_message::<Hello>().await?;
+
+        let (mut channel, auth) = handshake.finish(peer_hello, peer)?;
+        write_message(stream, &auth).await?;
+        let peer_auth = stream.read_message::<Sealed>().await?;
+        channel.authenticate(peer_auth)?;
+        Ok(channel)
+    }
+
+    /// The [User] this channel is bound to
+    pub fn peer(&self) -> &User {
+        &self.peer
+    }
+
+    /// Verifies the authentication frame returned by the peer's [Handshake::finish]
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidData] when `frame` was not sealed for this channel<br>
+    /// An [io::ErrorKind::PermissionDenied] when the signature was not made with the peer's key
+    pub fn authenticate(&mut self, frame: Sealed) -> io::Result<()> {
+        let auth: Auth = self.open_frame(frame)?;
+        self.peer
+            .verify(auth.scheme, &self.transcript, &auth.signature)?;
+        self.authenticated = true;
+        Ok(())
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Serializes ([postcard]) and encrypts `message`
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when `message` was unable to be serialized<br>
+    /// An [io::Error] when the frame counter is exhausted
+    pub fn seal<M>(&mut self, message: &M) -> io::Result<Sealed>
+    where
+        M: Serialize + ?Sized,
+    {
+        self.seal_frame(message)
+    }
+
+    /// Decrypts and deserializes a frame sealed by the peer
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::PermissionDenied] when the peer has not been authenticated<br>
+    /// An [io::ErrorKind::InvalidData] when `frame` was modified, replayed or reordered<br>
+    /// An [io::ErrorKind::InvalidData] when the data is not a valid [M]
+    pub fn open<M>(&mut self, frame: Sealed) -> io::Result<M>
+    where
+        M: DeserializeOwned,
+    {
+        if !self.authenticated {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "peer is not authenticated",
+            ));
+        }
+        self.open_frame(frame)
+    }
+
+    /// Seals `message` and writes it to `writer`
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. The frame may have been partially written.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// Any error of [SecureChannel::seal]<br>
+    /// The first error returned by `writer`
+    pub async fn send<M, W>(&mut self, writer: &mut W, message: &M) -> io::Result<()>
+    where
+        M: Serialize + ?Sized,
+        W: AsyncWrite + Unpin,
+    {
+        let frame = self.seal(message)?;
+        write_message(writer, &frame).await
+    }
+
+    /// Reads the next frame from `reader` and opens it
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Data may have been read from `reader`.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// Any error of [Source::read_message]<br>
+    /// Any error of [SecureChannel::open]
+    pub async fn recv<M, R>(&mut self, reader: &mut R) -> io::Result<M>
+    where
+        M: DeserializeOwned,
+        R: AsyncBufRead + Unpin,
+    {
+        let frame = reader.read_message::<Sealed>().await?;
+        self.open(frame)
+    }
+
+    fn seal_frame<M>(&mut self, message: &M) -> io::Result<Sealed>
+    where
+        M: Serialize + ?Sized,
+    {
+        let counter = self.next_seal;
+        self.next_seal = counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "frame counter exhausted"))?;
+
+        let mut data = postcard::to_extend(message, Vec::new())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.seal_key
+            .seal_in_place_append_tag(nonce(counter), Aad::empty(), &mut data)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to seal frame"))?;
+        Ok(Sealed { counter, data })
+    }
+
+    fn open_frame<M>(&mut self, mut frame: Sealed) -> io::Result<M>
+    where
+        M: DeserializeOwned,
+    {
+        if frame.counter < self.next_open {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "replayed or reordered frame",
+            ));
+        }
+
+        let plain = self
+            .open_key
+            .open_in_place(nonce(frame.counter), Aad::empty(), &mut frame.data)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid frame"))?;
+        let message = postcard::from_bytes(plain)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        // only a frame that was successfully opened moves the window
+        self.next_open = frame.counter + 1;
+        Ok(message)
+    }
+}
+
+impl fmt::Debug for SecureChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecureChannel")
+            .field("peer", &self.peer)
+            .field("authenticated", &self.authenticated)
+            .finish_non_exhaustive()
+    }
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn invalid_hello() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid hello")
+}
+
+async fn write_message<M, W>(writer: &mut W, message: &M) -> io::Result<()>
+where
+    M: Serialize + ?Sized,
+    W: AsyncWrite + Unpin,
+{
+    let buf = postcard::to_extend(message, Vec::new())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    writer.write_all(&buf).await?;
+    writer.flush().await
+}
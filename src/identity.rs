@@ -0,0 +1,252 @@
+//! Proof of possession of the key behind a [User] certificate and of the [RootKey] of a person
+
+use std::fmt;
+use std::io;
+
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, VerificationAlgorithm, ECDSA_P256_SHA256_ASN1,
+    ECDSA_P256_SHA256_ASN1_SIGNING, ED25519,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::messages::device::{DeviceCert, DeviceRevocation, PersonId};
+use crate::User;
+
+/// Algorithm of a signature made by an [Identity]
+///
+/// The variants are serialized by index, new schemes must only ever be appended.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum SignatureScheme {
+    /// ECDSA on P-256 with SHA-256, ASN.1 DER encoded signature
+    EcdsaP256Sha256,
+    Ed25519,
+}
+
+impl SignatureScheme {
+    fn webpki(self) -> &'static webpki::SignatureAlgorithm {
+        match self {
+            SignatureScheme::EcdsaP256Sha256 => &webpki::ECDSA_P256_SHA256,
+            SignatureScheme::Ed25519 => &webpki::ED25519,
+        }
+    }
+
+    pub(crate) fn ring(self) -> &'static dyn VerificationAlgorithm {
+        match self {
+            SignatureScheme::EcdsaP256Sha256 => &ECDSA_P256_SHA256_ASN1,
+            SignatureScheme::Ed25519 => &ED25519,
+        }
+    }
+}
+
+enum SigningKey {
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl SigningKey {
+    fn from_pkcs8(pkcs8: &[u8]) -> io::Result<SigningKey> {
+        match EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8) {
+            Ok(key) => Ok(SigningKey::Ecdsa(key)),
+            Err(_) => Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+                .map(SigningKey::Ed25519)
+                .map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unsupported private key: {}", err),
+                    )
+                }),
+        }
+    }
+
+    fn sign(&self, rng: &SystemRandom, message: &[u8]) -> io::Result<(SignatureScheme, Vec<u8>)> {
+        match self {
+            SigningKey::Ecdsa(key) => {
+                let signature = key
+                    .sign(rng, message)
+                    .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to sign"))?;
+                Ok((
+                    SignatureScheme::EcdsaP256Sha256,
+                    signature.as_ref().to_vec(),
+                ))
+            }
+            SigningKey::Ed25519(key) => Ok((
+                SignatureScheme::Ed25519,
+                key.sign(message).as_ref().to_vec(),
+            )),
+        }
+    }
+
+    fn public_key(&self) -> &[u8] {
+        match self {
+            SigningKey::Ecdsa(key) => key.public_key().as_ref(),
+            SigningKey::Ed25519(key) => key.public_key().as_ref(),
+        }
+    }
+}
+
+/// The local [User] together with the private key of its certificate
+pub struct Identity {
+    user: User,
+    key: SigningKey,
+    rng: SystemRandom,
+}
+
+impl Identity {
+    /// `pkcs8` is the private key of the certificate in `user`, encoded as PKCS#8 DER
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when `pkcs8` is not a P-256 or Ed25519 key<br>
+    /// An [io::ErrorKind::InvalidInput] when the key does not belong to the certificate of `user`
+    pub fn from_pkcs8(user: User, pkcs8: &[u8]) -> io::Result<Identity> {
+        let identity = Identity {
+            user,
+            key: SigningKey::from_pkcs8(pkcs8)?,
+            rng: SystemRandom::new(),
+        };
+
+        // a signature that the certificate does not accept proves nothing
+        let (scheme, signature) = identity.sign(b"smoke identity check")?;
+        identity
+            .user
+            .verify(scheme, b"smoke identity check", &signature)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "private key does not belong to the certificate",
+                )
+            })?;
+        Ok(identity)
+    }
+
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    /// Signs `message` with the private key of the certificate
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::Error] when the system random number generator failed
+    pub fn sign(&self, message: &[u8]) -> io::Result<(SignatureScheme, Vec<u8>)> {
+        self.key.sign(&self.rng, message)
+    }
+
+    /// The public key of the certificate
+    pub fn public_key(&self) -> &[u8] {
+        self.key.public_key()
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("user", &self.user)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Long lived key of a person that certifies the [User] certificates of its devices
+///
+/// The root key never leaves the device it was generated on, ideally it is kept offline.
+/// Rotating a device key is done by certifying the new [User] and revoking the old one.
+pub struct RootKey {
+    key: SigningKey,
+    rng: SystemRandom,
+}
+
+impl RootKey {
+    /// `pkcs8` is a P-256 or Ed25519 private key encoded as PKCS#8 DER
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when `pkcs8` is not a P-256 or Ed25519 key
+    pub fn from_pkcs8(pkcs8: &[u8]) -> io::Result<RootKey> {
+        Ok(RootKey {
+            key: SigningKey::from_pkcs8(pkcs8)?,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// The identifier of the person that owns this key
+    pub fn person(&self) -> PersonId {
+        let scheme = match self.key {
+            SigningKey::Ecdsa(_) => SignatureScheme::EcdsaP256Sha256,
+            SigningKey::Ed25519(_) => SignatureScheme::Ed25519,
+        };
+        PersonId {
+            scheme,
+            public_key: self.key.public_key().to_vec(),
+        }
+    }
+
+    /// Certifies that `device` belongs to this person
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::Error] when signing failed
+    pub fn certify(&self, device: User) -> io::Result<DeviceCert> {
+        let (_, signature) = self
+            .key
+            .sign(&self.rng, &DeviceCert::signed_message(&device))?;
+        Ok(DeviceCert {
+            person: self.person(),
+            device,
+            signature,
+        })
+    }
+
+    /// Revokes a previously certified `device`
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::Error] when signing failed
+    pub fn revoke(&self, device: User) -> io::Result<DeviceRevocation> {
+        let (_, signature) = self
+            .key
+            .sign(&self.rng, &DeviceRevocation::signed_message(&device))?;
+        Ok(DeviceRevocation {
+            person: self.person(),
+            device,
+            signature,
+        })
+    }
+}
+
+impl fmt::Debug for RootKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RootKey")
+            .field("person", &self.person())
+            .finish_non_exhaustive()
+    }
+}
+
+impl User {
+    /// Verifies that `signature` of `message` was made with the key of this certificate
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidData] when the certificate cannot be parsed<br>
+    /// An [io::ErrorKind::PermissionDenied] when the signature is invalid
+    pub fn verify(
+        &self,
+        scheme: SignatureScheme,
+        message: &[u8],
+        signature: &[u8],
+    ) -> io::Result<()> {
+        let cert = webpki::EndEntityCert::try_from(self.cert_data.as_slice()).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid certificate: {:?}", err),
+            )
+        })?;
+        cert.verify_signature(scheme.webpki(), message, signature)
+            .map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("invalid signature: {:?}", err),
+                )
+            })
+    }
+}
@@ -1,4 +1,22 @@
+pub mod block_list;
+pub mod bridge;
+pub mod connection;
+pub mod datagram;
+pub mod device_registry;
+pub mod e2e;
+pub mod identity;
 pub mod messages;
+#[cfg(feature = "client")]
+pub mod peer_auth;
+pub mod policy;
+pub mod rate_limit;
+pub mod room_requests;
+#[cfg(feature = "client")]
+pub mod scheduler;
+pub mod session;
+pub mod stats;
+#[cfg(feature = "client")]
+pub mod tunnels;
 mod user;
 
 #[cfg(feature = "client")]
@@ -0,0 +1,185 @@
+use std::io;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::time::{self, Instant};
+
+/// Buffered bytes after which [BatchWriter] flushes regardless of the [FlushPolicy]
+///
+/// This is the largest payload of a single TLS record.
+pub const MAX_BATCH_SIZE: usize = 16 * 1024;
+
+/// Decides when a [BatchWriter] writes its buffered messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Every message is written as soon as it was sent
+    Immediate,
+    /// `Delay( ... ).0` - maximum `delay` of the oldest buffered message
+    ///
+    /// Messages are collected until the oldest one waited for `delay`,
+    /// similar to Nagle's algorithm. Use [BatchWriter::flush_due] to meet the deadline
+    /// when no further messages are sent.
+    Delay(Duration),
+    /// `Size( ... ).0` - `threshold` in bytes
+    ///
+    /// Messages are collected until at least `threshold` bytes are buffered
+    /// or [BatchWriter::flush] is called.
+    Size(usize),
+}
+
+/// Serializes ([postcard]) messages into a single buffer and writes them together
+///
+/// Writing multiple messages at once results in less syscalls and, on a TLS stream,
+/// less TLS records than a [Drain](super::Drain) call per message.
+#[derive(Debug)]
+pub struct BatchWriter<W> {
+    writer: W,
+    policy: FlushPolicy,
+    buf: Vec<u8>,
+    // bytes of `buf` that have already been written
+    written: usize,
+    oldest: Option<Instant>,
+}
+
+impl<W> BatchWriter<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(writer: W, policy: FlushPolicy) -> Self {
+        BatchWriter {
+            writer,
+            policy,
+            buf: Vec::new(),
+            written: 0,
+            oldest: None,
+        }
+    }
+
+    pub fn policy(&self) -> FlushPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: FlushPolicy) {
+        self.policy = policy;
+    }
+
+    /// Amount of bytes waiting to be written
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.written
+    }
+
+    /// Serializes `message` into the buffer and flushes if the [FlushPolicy] requires it
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe. If it is cancelled while flushing the message
+    /// stays buffered and the next flush continues where the cancelled one stopped.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when `message` was unable to be serialized, the buffer is left unchanged<br>
+    /// The first error returned by the writer
+    pub async fn send<M>(&mut self, message: &M) -> io::Result<()>
+    where
+        M: Serialize + ?Sized,
+    {
+        let len = self.buf.len();
+        if let Err(err) = postcard::to_extend(message, ExtendVec(&mut self.buf)) {
+            self.buf.truncate(len);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+        self.oldest.get_or_insert_with(Instant::now);
+
+        let due = match self.policy {
+            FlushPolicy::Immediate => true,
+            FlushPolicy::Delay(delay) => {
+                self.oldest.is_some_and(|oldest| oldest.elapsed() >= delay)
+            }
+            FlushPolicy::Size(threshold) => self.buffered() >= threshold,
+        };
+
+        if due || self.buffered() >= MAX_BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Point in time at which the buffered messages have to be flushed
+    ///
+    /// Only [FlushPolicy::Delay] has a deadline, `None` is returned for
+    /// the other policies or when nothing is buffered.
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.policy {
+            FlushPolicy::Delay(delay) => self.oldest.map(|oldest| oldest + delay),
+            _ => None,
+        }
+    }
+
+    /// Waits for the [BatchWriter::deadline] and flushes
+    ///
+    /// Never completes when there is no deadline, which makes it suitable
+    /// as a branch of a tokio::select statement next to [BatchWriter::send].
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by the writer
+    pub async fn flush_due(&mut self) -> io::Result<()> {
+        match self.deadline() {
+            Some(deadline) => {
+                time::sleep_until(deadline).await;
+                self.flush().await
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Writes all buffered messages and flushes the writer
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe. Calling it again continues with the
+    /// data that has not been written yet.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by the writer<br>
+    /// An [io::ErrorKind::WriteZero] when the writer does not accept any more data
+    pub async fn flush(&mut self) -> io::Result<()> {
+        while self.written < self.buf.len() {
+            let n = self.writer.write(&self.buf[self.written..]).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.written += n;
+        }
+        self.buf.clear();
+        self.written = 0;
+        self.oldest = None;
+
+        self.writer.flush().await
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the writer, buffered messages that have not been flushed are lost
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Lets [postcard::to_extend] append to a borrowed buffer
+struct ExtendVec<'a>(&'a mut Vec<u8>);
+
+impl Extend<u8> for ExtendVec<'_> {
+    fn extend<T: IntoIterator<Item = u8>>(&mut self, iter: T) {
+        self.0.extend(iter);
+    }
+}
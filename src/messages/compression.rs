@@ -0,0 +1,287 @@
+//! Optional compression of large p2p [Signal]s
+//!
+//! Compression is negotiated per connection and direction: a peer announces the
+//! [Algorithm]s it can decompress with [Signal::Compression], afterwards the other
+//! side may wrap any [Signal] in [Signal::Compressed]. Every [Signal::Compressed] is
+//! self describing, so both framings can be mixed freely and peers that never
+//! announced compression keep receiving raw [Signal]s.
+//!
+//! [compressed] wires this into a [Connection]: its [CompressedWriter] compresses every
+//! sent [Signal] with the algorithm the peer announced and its [CompressedReader] unwraps
+//! every [Signal::Compressed] and applies the [Signal::Compression] of the peer, so no
+//! call site handles [Signal::Compressed] itself.
+//!
+//! The algorithms are only available with the `compression` feature, without it
+//! [Compression] never compresses and rejects every [Signal::Compressed].
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::signal::{Signal, MAX_SIGNAL_BUF_SIZE};
+use crate::connection::{Connection, Reader, Writer};
+
+/// [Signal]s that serialize to less bytes are never compressed
+pub const DEFAULT_THRESHOLD: usize = 512;
+/// Largest serialized [Signal] that is accepted after decompression
+pub const MAX_DECOMPRESSED_SIZE: usize = 16 * MAX_SIGNAL_BUF_SIZE;
+
+/// Compression algorithm of a [Signal::Compressed]
+///
+/// The variants are serialized by index, new algorithms must only ever be appended.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// LZ4 block format, prefixed with the decompressed size as little endian u32
+    Lz4,
+}
+
+impl Algorithm {
+    /// Algorithms supported by this build in order of preference
+    pub fn supported() -> &'static [Algorithm] {
+        #[cfg(feature = "compression")]
+        return &[Algorithm::Lz4];
+        #[cfg(not(feature = "compression"))]
+        return &[];
+    }
+
+    #[cfg(feature = "compression")]
+    fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Lz4 => lz4_flex::block::compress_prepend_size(data),
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Algorithm::Lz4 => {
+                let size = data
+                    .get(..4)
+                    .map(|size| u32::from_le_bytes(size.try_into().expect("4 bytes")) as usize)
+                    .ok_or_else(|| invalid("truncated lz4 size"))?;
+                if size > MAX_DECOMPRESSED_SIZE {
+                    return Err(invalid("decompressed signal exceeds the maximum size"));
+                }
+                lz4_flex::block::decompress_size_prepended(data).map_err(invalid)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn decompress(self, _data: &[u8]) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "compression feature is disabled",
+        ))
+    }
+}
+
+/// Compression state of one peer connection
+///
+/// Clones share the negotiated algorithm, so the reading side of a connection
+/// can [Compression::accept] what the writing side compresses with.
+#[derive(Clone, Debug)]
+pub struct Compression {
+    threshold: usize,
+    peer: Arc<Mutex<Option<Algorithm>>>,
+}
+
+impl Compression {
+    /// Signals that serialize to less than `threshold` bytes stay raw
+    pub fn new(threshold: usize) -> Self {
+        Compression {
+            threshold,
+            peer: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The [Signal::Compression] announcing the algorithms of this build
+    pub fn offer() -> Signal {
+        Signal::Compression(Algorithm::supported().to_vec())
+    }
+
+    /// Handles the [Signal::Compression] of the peer
+    ///
+    /// Picks the first algorithm of this build that the peer is able to decompress.
+    pub fn accept(&self, algorithms: &[Algorithm]) {
+        *self.peer.lock().expect("poisoned compression") = Algorithm::supported()
+            .iter()
+            .find(|algorithm| algorithms.contains(algorithm))
+            .copied();
+    }
+
+    /// The algorithm used for [Compression::compress], `None` until the peer announced a supported one
+    pub fn algorithm(&self) -> Option<Algorithm> {
+        *self.peer.lock().expect("poisoned compression")
+    }
+
+    /// Wraps `signal` in a [Signal::Compressed] when that is allowed and saves space
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when `signal` serializes to more than [MAX_DECOMPRESSED_SIZE]
+    pub fn compress(&self, signal: Signal) -> io::Result<Signal> {
+        let Some(algorithm) = self.algorithm() else {
+            return Ok(signal);
+        };
+
+        let serialized = postcard::to_extend(&signal, Vec::new())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        if serialized.len() > MAX_DECOMPRESSED_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "signal exceeds the maximum size",
+            ));
+        }
+        if serialized.len() < self.threshold {
+            return Ok(signal);
+        }
+
+        self.compress_serialized(algorithm, signal, &serialized)
+    }
+
+    #[cfg(feature = "compression")]
+    fn compress_serialized(
+        &self,
+        algorithm: Algorithm,
+        signal: Signal,
+        serialized: &[u8],
+    ) -> io::Result<Signal> {
+        let compressed = algorithm.compress(serialized);
+        // incompressible data is sent raw
+        if compressed.len() >= serialized.len() {
+            return Ok(signal);
+        }
+        Ok(Signal::Compressed(algorithm, compressed))
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn compress_serialized(&self, _: Algorithm, signal: Signal, _: &[u8]) -> io::Result<Signal> {
+        Ok(signal)
+    }
+
+    /// Unwraps a [Signal::Compressed], every other [Signal] is returned as is
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidData] when the payload is corrupt, too large or contains another [Signal::Compressed]<br>
+    /// An [io::ErrorKind::Unsupported] when this build does not support the algorithm
+    pub fn decompress(signal: Signal) -> io::Result<Signal> {
+        let Signal::Compressed(algorithm, data) = signal else {
+            return Ok(signal);
+        };
+
+        let serialized = algorithm.decompress(&data)?;
+        match postcard::from_bytes(&serialized).map_err(invalid)? {
+            Signal::Compressed(..) => Err(invalid("nested compressed signal")),
+            signal => Ok(signal),
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new(DEFAULT_THRESHOLD)
+    }
+}
+
+/// Splits `connection` into halves that compress transparently
+///
+/// [Compression::offer] is not sent automatically, send it with the [CompressedWriter]
+/// to allow the peer to compress as well.
+pub fn compressed<S>(
+    connection: Connection<S, Signal, Signal>,
+    compression: Compression,
+) -> (CompressedReader<S>, CompressedWriter<S>)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, writer) = connection.into_split();
+    (
+        CompressedReader {
+            inner: reader,
+            compression: compression.clone(),
+        },
+        CompressedWriter {
+            inner: writer,
+            compression,
+        },
+    )
+}
+
+/// Read half of [compressed]
+#[derive(Debug)]
+pub struct CompressedReader<S> {
+    inner: Reader<S, Signal>,
+    compression: Compression,
+}
+
+impl<S> CompressedReader<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    /// Receives the next [Signal], unwrapping a [Signal::Compressed]
+    ///
+    /// A [Signal::Compression] of the peer is applied to the [CompressedWriter]
+    /// and returned as well.
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe. Refer to [Reader::recv].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [Reader::recv]<br>
+    /// The first error returned by [Compression::decompress]
+    pub async fn recv(&mut self) -> io::Result<Signal> {
+        match self.inner.recv().await? {
+            Signal::Compression(algorithms) => {
+                self.compression.accept(&algorithms);
+                Ok(Signal::Compression(algorithms))
+            }
+            signal => Compression::decompress(signal),
+        }
+    }
+
+    pub fn into_inner(self) -> Reader<S, Signal> {
+        self.inner
+    }
+}
+
+/// Write half of [compressed]
+#[derive(Debug)]
+pub struct CompressedWriter<S> {
+    inner: Writer<S, Signal>,
+    compression: Compression,
+}
+
+impl<S> CompressedWriter<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    /// Sends `signal`, compressed when the peer announced a supported algorithm
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [Writer::send].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [Compression::compress]<br>
+    /// The first error returned by [Writer::send]
+    pub async fn send(&mut self, signal: Signal) -> io::Result<()> {
+        let signal = self.compression.compress(signal)?;
+        self.inner.send(signal).await
+    }
+
+    pub fn into_inner(self) -> Writer<S, Signal> {
+        self.inner
+    }
+}
+
+fn invalid<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
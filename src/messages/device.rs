@@ -0,0 +1,96 @@
+//! Device certificates that tie multiple [User]s to a single person
+
+use std::io;
+
+use ring::signature::UnparsedPublicKey;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::SignatureScheme;
+use crate::User;
+
+const CERT_CONTEXT: &[u8] = b"smoke device cert v1";
+const REVOKE_CONTEXT: &[u8] = b"smoke device revoke v1";
+
+/// Public key of the [RootKey](crate::identity::RootKey) of a person
+///
+/// Unlike a [User], this stays the same for all devices of a person and across key rotations.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub struct PersonId {
+    pub scheme: SignatureScheme,
+    #[serde(with = "serde_bytes")]
+    pub public_key: Vec<u8>,
+}
+
+impl PersonId {
+    /// Verifies that `signature` of `message` was made with the root key of this person
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::PermissionDenied] when the signature is invalid
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> io::Result<()> {
+        UnparsedPublicKey::new(self.scheme.ring(), &self.public_key)
+            .verify(message, signature)
+            .map_err(|_| io::Error::new(io::ErrorKind::PermissionDenied, "invalid signature"))
+    }
+}
+
+/// Statement of a person that `device` is one of its devices, refer to [EmbMessage::PublishDevice](super::EmbMessage::PublishDevice)
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DeviceCert {
+    pub person: PersonId,
+    pub device: User,
+    /// Signature of the root key over `device`
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+impl DeviceCert {
+    /// Checks that the certificate was signed by the root key of `person`
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::PermissionDenied] when the signature is invalid
+    pub fn verify(&self) -> io::Result<()> {
+        self.person
+            .verify(&Self::signed_message(&self.device), &self.signature)
+    }
+
+    pub(crate) fn signed_message(device: &User) -> Vec<u8> {
+        signed_message(CERT_CONTEXT, device)
+    }
+}
+
+/// Statement of a person that `device` must no longer be trusted, refer to [EmbMessage::RevokeDevice](super::EmbMessage::RevokeDevice)
+///
+/// A revocation is permanent, the same [User] can not be certified again.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DeviceRevocation {
+    pub person: PersonId,
+    pub device: User,
+    /// Signature of the root key over `device`
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+impl DeviceRevocation {
+    /// Checks that the revocation was signed by the root key of `person`
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::PermissionDenied] when the signature is invalid
+    pub fn verify(&self) -> io::Result<()> {
+        self.person
+            .verify(&Self::signed_message(&self.device), &self.signature)
+    }
+
+    pub(crate) fn signed_message(device: &User) -> Vec<u8> {
+        signed_message(REVOKE_CONTEXT, device)
+    }
+}
+
+fn signed_message(context: &[u8], device: &User) -> Vec<u8> {
+    let mut message = Vec::with_capacity(context.len() + device.cert_data.len());
+    message.extend_from_slice(context);
+    message.extend_from_slice(&device.cert_data);
+    message
+}
@@ -1,7 +1,10 @@
+use std::io;
+
 use serde::Serialize;
 use tokio::io::AsyncWrite;
 
 use self::tokio_copy::*;
+use super::fragment::{serialize_fragmented, WriteFragmented};
 
 pub trait Drain {
     fn serialize_to<'a, T: AsyncWrite + Unpin>(
@@ -9,6 +12,11 @@ pub trait Drain {
         writer: &'a mut T,
         ser_buf: &'a mut [u8],
     ) -> Result<WriteAll<'a, T>, postcard::Error>;
+    fn serialize_fragmented_to<'a, T: AsyncWrite + Unpin>(
+        &self,
+        writer: &'a mut T,
+        fragment_size: usize,
+    ) -> io::Result<WriteFragmented<'a, T>>;
 }
 
 impl<M> Drain for M
@@ -45,6 +53,37 @@ where
 
         Ok(write_all(writer, bytes))
     }
+
+    /// Serializes ([postcard]) "self" and asyncronously sends the resulting binary data as
+    /// [Fragment](super::fragment::Fragment)s of at most `fragment_size` bytes using the supplied writer
+    ///
+    /// Unlike [Drain::serialize_to] there is no serialization buffer to size, messages of
+    /// any size are sent. The peer reads them with [Source::read_message_fragmented](super::Source::read_message_fragmented).
+    ///
+    /// After handleing the [io::Error] this is equivalent to
+    /// ```ignore
+    /// async fn serialize_fragmented_to(&self, writer: &mut T, fragment_size: usize) -> io::Result<()>
+    /// ```
+    ///
+    /// # Cancel safety
+    /// This Future is not cancellation safe. If it is used as the event
+    /// in a tokio::select statement and some other branch completes first,
+    /// then some fragments may have been sent, the peer has to discard the message.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when `self` was unable to be serialized or `fragment_size` is 0<br>
+    /// The first error returned by the writer
+    fn serialize_fragmented_to<'a, T>(
+        &self,
+        writer: &'a mut T,
+        fragment_size: usize,
+    ) -> io::Result<WriteFragmented<'a, T>>
+    where
+        T: AsyncWrite + Unpin,
+    {
+        serialize_fragmented(writer, self, fragment_size)
+    }
 }
 
 mod tokio_copy {
@@ -1,12 +1,16 @@
 use std::io;
 
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio_rustls::client::TlsStream as CTlsStream;
 use tokio_rustls::server::TlsStream as STlsStream;
 
 use crate::User;
 
+use super::device::{DeviceCert, DeviceRevocation};
+use super::sequence::{SequenceCheck, Sequenced, Sequencer, MAX_SEQUENCE_OVERHEAD};
+use super::{RequestId, SessionToken};
+
 pub const EMB_MESSAGE_BUF_SIZE: usize = 1024;
 
 /// Container for all possible messages that are being sent from Emberry (client) to Rhizome (server)
@@ -14,12 +18,24 @@ pub const EMB_MESSAGE_BUF_SIZE: usize = 1024;
 pub enum EmbMessage {
     /// Request Rhizome to inform "User" that <we> would like to initiate a P2P connection
     Room(User),
-    /// Accept/Deny a pending P2P connection request (true = Accept, false = Deny)
-    Accept(bool),
+    /// Accept/Deny the pending P2P connection request "RequestId" of a "WantsRoom" (true = Accept, false = Deny)
+    Accept(RequestId, bool),
     /// Message used for keepalive message activity if nessecary
     Heartbeat,
     /// Inform Rhizome about the termination of this connection. OR The connection has been closed (read yielded Ok(0))
     Shutdown,
+    /// Request Rhizome to drop all future "Room" requests that "User" sends to <us>
+    Block(User),
+    /// Revert a previous "Block" of "User"
+    Unblock(User),
+    /// Enable/Disable do not disturb mode (true = Enable, false = Disable). While enabled Rhizome drops all "Room" requests to <us>
+    DoNotDisturb(bool),
+    /// Request Rhizome to continue the session identified by "SessionToken" on this connection
+    Resume(SessionToken),
+    /// Announce that "DeviceCert" certifies <us> as a device of its person. Rhizome then routes "Room" requests for any device of the person to <us> as well
+    PublishDevice(DeviceCert),
+    /// Inform Rhizome that the device in "DeviceRevocation" must no longer receive "Room" requests of its person
+    RevokeDevice(DeviceRevocation),
 }
 
 impl EmbMessage {
@@ -41,6 +57,21 @@ impl EmbMessage {
     pub async fn send_with<T>(self, tls: &mut BufReader<CTlsStream<T>>) -> io::Result<()>
     where
         T: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.send_to(tls).await
+    }
+
+    /// Same as [EmbMessage::send_with] but for any kind of stream
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [EmbMessage::send_with].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by writing to the stream.
+    pub async fn send_to<S>(self, stream: &mut S) -> io::Result<()>
+    where
+        S: AsyncWrite + Unpin,
     {
         // Serialize and packetize the message
         let bytes = match postcard::to_vec_cobs::<Self, EMB_MESSAGE_BUF_SIZE>(&self) {
@@ -56,7 +87,7 @@ impl EmbMessage {
 
         #[cfg(feature = "debug")]
         println!("sent msg");
-        tls.write_all(&bytes).await
+        stream.write_all(&bytes).await
     }
 
     /// Reads a [EmbMessage] from the Tls Stream, depacketizing (COBS) and deserializing ([postcard]) the data.
@@ -82,14 +113,87 @@ impl EmbMessage {
     ) -> io::Result<EmbMessage>
     where
         T: AsyncRead + AsyncWrite + Unpin,
+    {
+        Self::recv_from(tls, buf).await
+    }
+
+    /// Same as [EmbMessage::recv_req] but for any kind of buffered stream
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [EmbMessage::recv_req].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by reading from the stream.<br>
+    /// An [io::Error] when the data in "buf" is not a valid [EmbMessage].
+    pub async fn recv_from<S>(stream: &mut S, buf: &mut Vec<u8>) -> io::Result<EmbMessage>
+    where
+        S: AsyncBufRead + Unpin,
     {
         buf.clear();
         // 0 means EOF so we shutdown the connection
-        if 0 == tls.read_until(0, buf).await? {
+        if 0 == stream.read_until(0, buf).await? {
             return Ok(EmbMessage::Shutdown);
         }
 
         // depacketize and deserialize the message
         postcard::from_bytes_cobs(buf).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
     }
+
+    /// Same as [EmbMessage::send_to] but prefixes the message with the next sequence number of `sequencer`
+    ///
+    /// Both sides have to agree on sequenced framing before connecting, refer to [Sequenced].
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [EmbMessage::send_with].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by writing to the stream.
+    pub async fn send_sequenced<S>(
+        self,
+        stream: &mut S,
+        sequencer: &mut Sequencer,
+    ) -> io::Result<()>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let sequenced = sequencer.peek(&self);
+        let bytes = postcard::to_vec_cobs::<_, { EMB_MESSAGE_BUF_SIZE + MAX_SEQUENCE_OVERHEAD }>(
+            &sequenced,
+        )
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        // a message that could not be serialized does not consume its sequence number
+        sequencer.advance();
+        stream.write_all(&bytes).await
+    }
+
+    /// Same as [EmbMessage::recv_from] but for messages sent with [EmbMessage::send_sequenced]
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [EmbMessage::recv_req].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by reading from the stream.<br>
+    /// An [io::Error] when the data in "buf" is not a valid sequenced [EmbMessage].<br>
+    /// An [io::ErrorKind::InvalidData] with a [SequenceError](super::SequenceError) when the message was duplicated or messages were lost.
+    pub async fn recv_sequenced<S>(
+        stream: &mut S,
+        buf: &mut Vec<u8>,
+        check: &mut SequenceCheck,
+    ) -> io::Result<EmbMessage>
+    where
+        S: AsyncBufRead + Unpin,
+    {
+        buf.clear();
+        // 0 means EOF so we shutdown the connection
+        if 0 == stream.read_until(0, buf).await? {
+            return Ok(EmbMessage::Shutdown);
+        }
+
+        let sequenced: Sequenced<EmbMessage> = postcard::from_bytes_cobs(buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        Ok(check.accept(sequenced)?)
+    }
 }
@@ -0,0 +1,346 @@
+//! Fragmentation of messages that are larger than a single frame
+//!
+//! [Drain::serialize_fragmented_to] serializes ([postcard]) a message of any size and
+//! sends it as a sequence of [Fragment]s, each carrying at most `fragment_size` bytes
+//! of the serialized message. [Source::read_message_fragmented] reads the [Fragment]s
+//! back with a [Reassembler] and deserializes the message once the last one arrived,
+//! so no call site has to size a serialization buffer for the largest possible message.
+//!
+//! [Drain::serialize_fragmented_to]: super::Drain::serialize_fragmented_to
+
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite};
+
+use super::drain::Drain;
+use super::source::{MessageRef, MessageTooLarge, Source};
+
+/// Payload bytes per [Fragment] used by [write_fragmented] when nothing else is configured
+pub const DEFAULT_FRAGMENT_SIZE: usize = 1024;
+/// Size of a reassembled message that [Reassembler::default] accepts
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// Largest amount of bytes a [Fragment] adds to its data, 1 byte for `last` and a usize varint for the length
+pub const MAX_FRAGMENT_OVERHEAD: usize = 11;
+
+/// Part of a serialized message
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Fragment<'a> {
+    /// true for the fragment that completes the message
+    pub last: bool,
+    #[serde(serialize_with = "serde_bytes::serialize")]
+    pub data: &'a [u8],
+}
+
+impl MessageRef for Fragment<'_> {
+    type Ref<'de> = Fragment<'de>;
+}
+
+/// Serializes ([postcard]) `message` and sends it as [Fragment]s of at most `fragment_size` bytes
+///
+/// Same as [Drain::serialize_fragmented_to].
+///
+/// # Cancel safety
+/// This method is not cancellation safe. Some fragments may have been sent,
+/// the peer has to discard the message.
+///
+/// # Errors
+/// This function will return:</br>
+/// An [io::ErrorKind::InvalidInput] when `message` was unable to be serialized or `fragment_size` is 0<br>
+/// The first error returned by the writer
+pub async fn write_fragmented<M, W>(
+    writer: &mut W,
+    message: &M,
+    fragment_size: usize,
+) -> io::Result<()>
+where
+    M: Serialize + ?Sized,
+    W: AsyncWrite + Unpin,
+{
+    message
+        .serialize_fragmented_to(writer, fragment_size)?
+        .await
+}
+
+/// Future of [Drain::serialize_fragmented_to]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct WriteFragmented<'a, W: ?Sized> {
+    writer: &'a mut W,
+    serialized: Vec<u8>,
+    fragment_size: usize,
+    // start and end of the data of the current fragment in `serialized`
+    pos: usize,
+    end: usize,
+    header: [u8; MAX_FRAGMENT_OVERHEAD],
+    header_pos: usize,
+    header_len: usize,
+    started: bool,
+}
+
+pub(crate) fn serialize_fragmented<'a, M, W>(
+    writer: &'a mut W,
+    message: &M,
+    fragment_size: usize,
+) -> io::Result<WriteFragmented<'a, W>>
+where
+    M: Serialize + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    if fragment_size == 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "fragment size must not be 0",
+        ));
+    }
+
+    let serialized = postcard::to_extend(message, Vec::new())
+        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+
+    Ok(WriteFragmented {
+        writer,
+        serialized,
+        fragment_size,
+        pos: 0,
+        end: 0,
+        header: [0; MAX_FRAGMENT_OVERHEAD],
+        header_pos: 0,
+        header_len: 0,
+        started: false,
+    })
+}
+
+impl<W> Future for WriteFragmented<'_, W>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+
+        loop {
+            let pending = if me.header_pos < me.header_len {
+                &me.header[me.header_pos..me.header_len]
+            } else if me.pos < me.end {
+                &me.serialized[me.pos..me.end]
+            } else if me.started && me.end == me.serialized.len() {
+                return Poll::Ready(Ok(()));
+            } else {
+                // an empty message still needs a fragment to be received
+                me.started = true;
+                me.end = (me.pos + me.fragment_size).min(me.serialized.len());
+                let last = me.end == me.serialized.len();
+                // same encoding as the `last` and the length prefix of `data` in a [Fragment]
+                me.header_len = postcard::to_slice(&(last, me.end - me.pos), &mut me.header)
+                    .expect("header fits MAX_FRAGMENT_OVERHEAD")
+                    .len();
+                me.header_pos = 0;
+                continue;
+            };
+
+            let n = ready!(Pin::new(&mut *me.writer).poll_write(cx, pending))?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            if me.header_pos < me.header_len {
+                me.header_pos += n;
+            } else {
+                me.pos += n;
+            }
+        }
+    }
+}
+
+/// Reassembles messages sent with [write_fragmented]
+///
+/// Used with [Source::read_message_fragmented]. The state of a partially received
+/// message is kept in the [Reassembler], so the buffers are reused for every message.
+#[derive(Debug)]
+pub struct Reassembler {
+    max_size: usize,
+    fragment_size: usize,
+    message: Vec<u8>,
+    // `last` and the length of `data` of the fragment being read
+    header: Vec<u8>,
+    // data bytes of the current fragment that were not read yet, None while reading its header
+    remaining: Option<usize>,
+    last: bool,
+    discarding: bool,
+}
+
+impl Reassembler {
+    /// `max_size` is the largest serialized message that is accepted
+    pub fn new(max_size: usize) -> Self {
+        Reassembler {
+            max_size,
+            fragment_size: DEFAULT_FRAGMENT_SIZE,
+            message: Vec::new(),
+            header: Vec::with_capacity(MAX_FRAGMENT_OVERHEAD),
+            remaining: None,
+            last: false,
+            discarding: false,
+        }
+    }
+
+    /// Sets the largest [Fragment] that is accepted, the `fragment_size` the peer sends with
+    pub fn fragment_size(mut self, fragment_size: usize) -> Self {
+        self.fragment_size = fragment_size;
+        self
+    }
+
+    /// Reads [Fragment]s from `reader` until a complete [M] was received
+    ///
+    /// Same as [Source::read_message_fragmented].
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe. A partially read message is kept in `self`
+    /// and completed by the next call with the same `reader`.
+    ///
+    /// # Errors
+    /// Refer to [Source::read_message_fragmented].
+    pub async fn read_message<M, R>(&mut self, reader: &mut R) -> io::Result<M>
+    where
+        M: DeserializeOwned,
+        R: AsyncBufRead + Unpin,
+    {
+        reader.read_message_fragmented(self).await
+    }
+
+    /// Starts skipping the rest of the current message
+    fn discard(&mut self, max_size: usize) -> io::Error {
+        self.message.clear();
+        self.discarding = true;
+        io::Error::new(ErrorKind::InvalidData, MessageTooLarge { max_size })
+    }
+
+    /// Parses the header of the next fragment from `data` and returns the used bytes
+    fn read_header(&mut self, data: &[u8]) -> (io::Result<()>, usize) {
+        for (i, byte) in data.iter().enumerate() {
+            self.header.push(*byte);
+            let len = match postcard::take_from_bytes::<(bool, usize)>(&self.header) {
+                Ok(((last, len), _)) => {
+                    self.last = last;
+                    len
+                }
+                Err(postcard::Error::DeserializeUnexpectedEnd)
+                    if self.header.len() < MAX_FRAGMENT_OVERHEAD =>
+                {
+                    continue;
+                }
+                Err(err) => {
+                    // the next message must not be reassembled onto this one
+                    self.header.clear();
+                    self.message.clear();
+                    self.discarding = false;
+                    return (Err(io::Error::new(ErrorKind::InvalidData, err)), i + 1);
+                }
+            };
+            self.header.clear();
+            self.remaining = Some(len);
+
+            let result = if self.discarding {
+                Ok(())
+            } else if len > self.fragment_size {
+                Err(self.discard(self.fragment_size))
+            } else if self.message.len() + len > self.max_size {
+                Err(self.discard(self.max_size))
+            } else {
+                Ok(())
+            };
+            return (result, i + 1);
+        }
+        (Ok(()), data.len())
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+}
+
+/// Future of [Source::read_message_fragmented]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ReadMsgFragmented<'a, R: ?Sized, M: DeserializeOwned> {
+    buf_reader: &'a mut R,
+    reassembler: &'a mut Reassembler,
+    // only produces a `M`, so the future is `Unpin` for every `M`
+    _message: PhantomData<fn() -> M>,
+}
+
+pub(crate) fn read_message_fragmented<'a, R, M: DeserializeOwned>(
+    buf_reader: &'a mut R,
+    reassembler: &'a mut Reassembler,
+) -> ReadMsgFragmented<'a, R, M>
+where
+    R: AsyncBufRead + Unpin + ?Sized,
+{
+    ReadMsgFragmented {
+        buf_reader,
+        reassembler,
+        _message: PhantomData,
+    }
+}
+
+impl<R, M: DeserializeOwned> Future for ReadMsgFragmented<'_, R, M>
+where
+    R: AsyncBufRead + Unpin + ?Sized,
+{
+    type Output = io::Result<M>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<M>> {
+        let me = self.get_mut();
+        let reassembler = &mut *me.reassembler;
+
+        loop {
+            let data = ready!(Pin::new(&mut *me.buf_reader).poll_fill_buf(cx))?;
+            if data.is_empty() {
+                return Poll::Ready(Err(io::Error::new(
+                    ErrorKind::ConnectionReset,
+                    "EOF reached",
+                )));
+            }
+
+            match reassembler.remaining {
+                None => {
+                    let (result, used) = reassembler.read_header(data);
+                    me.buf_reader.consume(used);
+                    result?;
+                }
+                Some(remaining) => {
+                    // the data is copied without buffering the whole fragment first
+                    let used = remaining.min(data.len());
+                    if !reassembler.discarding {
+                        reassembler.message.extend_from_slice(&data[..used]);
+                    }
+                    me.buf_reader.consume(used);
+                    reassembler.remaining = Some(remaining - used);
+                }
+            }
+
+            if reassembler.remaining != Some(0) {
+                continue;
+            }
+            reassembler.remaining = None;
+            if !reassembler.last {
+                continue;
+            }
+
+            if reassembler.discarding {
+                reassembler.discarding = false;
+                continue;
+            }
+            let message = postcard::from_bytes(&reassembler.message)
+                .map_err(|err| io::Error::new(ErrorKind::Other, err));
+            reassembler.message.clear();
+            return Poll::Ready(message);
+        }
+    }
+}
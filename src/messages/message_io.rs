@@ -0,0 +1,192 @@
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::pin::{pin, Pin};
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncBufRead, AsyncWrite};
+
+use super::source::{Source, DEFAULT_MAX_MESSAGE_SIZE};
+
+/// [Stream] of the messages read from `R`
+///
+/// Built on [Source::read_message_cancelable] with an owned aggregator, so dropping
+/// a pending `next()` never loses data. The stream ends when `R` reaches EOF between
+/// two messages, EOF inside of a message is yielded as an error.
+#[derive(Debug)]
+pub struct MessageReader<R, M> {
+    reader: R,
+    agg: Vec<u8>,
+    max_size: usize,
+    done: bool,
+    _message: PhantomData<fn() -> M>,
+}
+
+impl<R, M> MessageReader<R, M>
+where
+    R: AsyncBufRead + Unpin,
+    M: DeserializeOwned,
+{
+    pub fn new(reader: R) -> Self {
+        MessageReader {
+            reader,
+            agg: Vec::new(),
+            max_size: DEFAULT_MAX_MESSAGE_SIZE,
+            done: false,
+            _message: PhantomData,
+        }
+    }
+
+    /// Sets the largest serialized message that is accepted
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl<R, M> MessageReader<R, M> {
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the reader, the data of a partially read message is lost
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R, M> Stream for MessageReader<R, M>
+where
+    R: AsyncBufRead + Unpin,
+    M: DeserializeOwned,
+{
+    type Item = io::Result<M>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let me = self.get_mut();
+        if me.done {
+            return Poll::Ready(None);
+        }
+
+        // a clean EOF ends the stream instead of failing the read
+        let data = match ready!(Pin::new(&mut me.reader).poll_fill_buf(cx)) {
+            Ok(data) => data,
+            Err(err) => return Poll::Ready(Some(Err(err))),
+        };
+        if data.is_empty() && me.agg.is_empty() {
+            me.done = true;
+            return Poll::Ready(None);
+        }
+
+        // the future is cancellation safe, its progress is kept in `agg`
+        let read = me
+            .reader
+            .read_message_cancelable::<M>(&mut me.agg)
+            .max_size(me.max_size);
+        pin!(read).poll(cx).map(Some)
+    }
+}
+
+/// [Sink] that serializes ([postcard]) messages to `W`
+///
+/// Uses the same framing as [Drain](super::Drain) but owns a growing serialization
+/// buffer, so messages of any size can be sent. Every message is written on its own,
+/// use [BatchWriter](super::BatchWriter) to combine writes.
+#[derive(Debug)]
+pub struct MessageWriter<W, M> {
+    writer: W,
+    buf: Vec<u8>,
+    // bytes of `buf` that have already been written
+    written: usize,
+    _message: PhantomData<fn(M)>,
+}
+
+impl<W, M> MessageWriter<W, M>
+where
+    W: AsyncWrite + Unpin,
+    M: Serialize,
+{
+    pub fn new(writer: W) -> Self {
+        MessageWriter {
+            writer,
+            buf: Vec::new(),
+            written: 0,
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<W, M> MessageWriter<W, M> {
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the writer, a message that has not been flushed is lost
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W, M> MessageWriter<W, M>
+where
+    W: AsyncWrite + Unpin,
+{
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.buf.len() {
+            let n = ready!(Pin::new(&mut self.writer).poll_write(cx, &self.buf[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W, M> Sink<M> for MessageWriter<W, M>
+where
+    W: AsyncWrite + Unpin,
+    M: Serialize,
+{
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_write_buf(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: M) -> io::Result<()> {
+        let me = self.get_mut();
+        debug_assert!(me.buf.is_empty(), "start_send called without poll_ready");
+
+        me.buf = postcard::to_extend(&item, mem::take(&mut me.buf))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.poll_write_buf(cx))?;
+        Pin::new(&mut me.writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.poll_write_buf(cx))?;
+        Pin::new(&mut me.writer).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,101 @@
+use std::io;
+use std::vec;
+
+use serde::de::DeserializeOwned;
+use tokio::io::AsyncBufRead;
+
+use super::source::{Source, DEFAULT_MAX_MESSAGE_SIZE};
+
+/// Reads messages in batches of everything that is buffered by `R`
+///
+/// Every refill decodes all complete messages of the buffer with
+/// [Source::read_messages_into], the following calls of [MessageStream::next]
+/// return them without polling `R` again.
+#[derive(Debug)]
+pub struct MessageStream<R, M> {
+    reader: R,
+    pending: vec::IntoIter<M>,
+    last_batch: usize,
+    // error of the last batch, returned once its messages were taken
+    error: Option<io::Error>,
+    max_size: usize,
+}
+
+impl<R, M> MessageStream<R, M>
+where
+    R: AsyncBufRead + Unpin,
+    M: DeserializeOwned,
+{
+    pub fn new(reader: R) -> Self {
+        MessageStream {
+            reader,
+            pending: Vec::new().into_iter(),
+            last_batch: 0,
+            error: None,
+            max_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Sets the largest serialized message that is accepted
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Returns the next message, reading a new batch if none is pending
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe as long as a message is pending. Otherwise
+    /// some data may have been partially read, refer to [Source::read_messages_into].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [Source::read_messages_into], messages decoded before the error are returned first
+    pub async fn next(&mut self) -> io::Result<M> {
+        if let Some(message) = self.pending.next() {
+            return Ok(message);
+        }
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        // the last batch is a good estimate for the size of the next one
+        let mut batch = Vec::with_capacity(self.last_batch);
+        let read = self
+            .reader
+            .read_messages_into(&mut batch)
+            .max_size(self.max_size)
+            .await;
+        self.last_batch = batch.len();
+        self.pending = batch.into_iter();
+
+        if let Err(err) = read {
+            self.error = Some(err);
+        }
+        match self.pending.next() {
+            Some(message) => Ok(message),
+            None => Err(self
+                .error
+                .take()
+                .expect("read_messages_into reads at least one message")),
+        }
+    }
+
+    /// Amount of messages that are returned by [MessageStream::next] without reading
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the reader, pending messages are dropped
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
@@ -1,15 +1,35 @@
+mod batch_writer;
+#[cfg(feature = "client")]
+pub mod compression;
+pub mod device;
 mod drain;
 pub mod emb_message;
+pub mod fragment;
+mod message_io;
+mod message_stream;
+#[cfg(feature = "client")]
+pub mod profile;
+mod request_id;
 pub mod rhiz_message;
 mod room_id;
+mod sequence;
+pub mod server_error;
+mod session_token;
 #[cfg(feature = "client")]
 pub mod signal;
 mod source;
 pub mod vlink;
 
+pub use batch_writer::{BatchWriter, FlushPolicy, MAX_BATCH_SIZE};
 pub use drain::Drain;
 pub use emb_message::EmbMessage;
 pub use emb_message::EMB_MESSAGE_BUF_SIZE;
+pub use message_io::{MessageReader, MessageWriter};
+pub use message_stream::MessageStream;
+pub use request_id::RequestId;
 pub use rhiz_message::RhizMessage;
 pub use room_id::RoomId;
-pub use source::Source;
+pub use sequence::{SequenceCheck, SequenceError, Sequenced, Sequencer, MAX_SEQUENCE_OVERHEAD};
+pub use server_error::{ErrorCode, ServerError};
+pub use session_token::SessionToken;
+pub use source::{MessageRef, MessageTooLarge, Source, DEFAULT_MAX_MESSAGE_SIZE};
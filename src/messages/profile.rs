@@ -0,0 +1,131 @@
+//! Profiles that are signed by the certificate of their [User]
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::identity::{Identity, SignatureScheme};
+use crate::User;
+
+/// Longest accepted [Profile::display_name] in bytes
+pub const MAX_DISPLAY_NAME_LEN: usize = 64;
+/// Longest accepted [Profile::status] in bytes
+pub const MAX_STATUS_LEN: usize = 256;
+
+const CONTEXT: &[u8] = b"smoke profile v2";
+
+/// Public information a user shares with its peers
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub display_name: String,
+    /// SHA-256 of the avatar image, the image itself is transferred separately
+    pub avatar_hash: Option<[u8; 32]>,
+    /// Unsanitized UTF-8 user input
+    pub status: String,
+    /// Increased by the owner for every change, peers never go back to an older version
+    pub version: u64,
+}
+
+impl Profile {
+    fn check(&self) -> Result<(), &'static str> {
+        if self.display_name.len() > MAX_DISPLAY_NAME_LEN {
+            return Err("display name is too long");
+        }
+        if self.status.len() > MAX_STATUS_LEN {
+            return Err("status is too long");
+        }
+        Ok(())
+    }
+
+    fn signed_message(&self) -> io::Result<Vec<u8>> {
+        postcard::to_extend(self, CONTEXT.to_vec())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+}
+
+/// [Profile] together with a signature of the key in the announcing [User]'s certificate
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SignedProfile {
+    profile: Profile,
+    scheme: SignatureScheme,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+impl SignedProfile {
+    /// Signs `profile` with the key of `identity`
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when a field of `profile` is longer than allowed<br>
+    /// An [io::Error] when signing failed
+    pub fn sign(profile: Profile, identity: &Identity) -> io::Result<SignedProfile> {
+        profile
+            .check()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let (scheme, signature) = identity.sign(&profile.signed_message()?)?;
+        Ok(SignedProfile {
+            profile,
+            scheme,
+            signature,
+        })
+    }
+
+    /// Returns the [Profile] after verifying that it was signed by `user`
+    ///
+    /// `user` has to be the authenticated peer, refer to [PeerAuth](crate::peer_auth::PeerAuth).
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidData] when a field of the profile is longer than allowed<br>
+    /// An [io::ErrorKind::PermissionDenied] when the profile was not signed by `user`
+    pub fn verify(&self, user: &User) -> io::Result<&Profile> {
+        self.profile
+            .check()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        user.verify(
+            self.scheme,
+            &self.profile.signed_message()?,
+            &self.signature,
+        )?;
+        Ok(&self.profile)
+    }
+
+    /// Same as [SignedProfile::verify] but also rejects a profile that is older than `current`
+    ///
+    /// `current` is the last verified [Profile] of `user`. Resending `current` itself is accepted,
+    /// so a replayed profile can never replace a newer one.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// Any error of [SignedProfile::verify]<br>
+    /// An [io::ErrorKind::InvalidData] when the version is older than the one of `current`,
+    /// or equal to it with different content
+    pub fn verify_update(&self, user: &User, current: Option<&Profile>) -> io::Result<&Profile> {
+        let profile = self.verify(user)?;
+        match current {
+            Some(current) if profile.version < current.version => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "profile version {} is older than {}",
+                    profile.version, current.version
+                ),
+            )),
+            Some(current) if profile.version == current.version && profile != current => {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "profile version {} changed without an increase",
+                        profile.version
+                    ),
+                ))
+            }
+            _ => Ok(profile),
+        }
+    }
+
+    /// Returns the [Profile] without verifying it
+    pub fn unverified(&self) -> &Profile {
+        &self.profile
+    }
+}
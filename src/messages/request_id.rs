@@ -0,0 +1,6 @@
+use serde::{Deserialize, Serialize};
+
+/// Identifies a pending room request, assigned by Rhizome in [RhizMessage::WantsRoom](super::RhizMessage::WantsRoom)
+/// and echoed by the answering [EmbMessage::Accept](super::EmbMessage::Accept)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct RequestId(pub u64);
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio_rustls::client::TlsStream as CTlsStream;
 use tokio_rustls::server::TlsStream as STlsStream;
 
@@ -8,26 +8,45 @@ use crate::User;
 use std::io::{self, ErrorKind};
 pub const MAX_MESSAGE_BUF_SIZE: usize = 1088;
 
-use super::RoomId;
+use super::sequence::{SequenceCheck, Sequenced, Sequencer, MAX_SEQUENCE_OVERHEAD};
+use super::{ErrorCode, RequestId, RoomId, ServerError, SessionToken};
 
 /// Container for all possible messages that are being sent from Rhizome (server) to Emberry (client)
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum RhizMessage {
     /// "User" IS connected to the Rhizome network
     HasRoute(User),
-    /// "User" is NOT connected to the Rhizome network
+    /// "User" is NOT connected to the Rhizome network. Answers the "Room" request for "User"
     NoRoute(User),
-    /// "User" wants to establish a peer to peer connection
-    WantsRoom(User),
+    /// "User" wants to establish a peer to peer connection. "RequestId" has to be sent back with the "Accept" that answers this request
+    WantsRoom(RequestId, User),
     /// "User" is waiting for UDP Holepunching at "RoomId". "Option" is NONE when "User" denied P2P connection.
+    /// "User" is always the one named in the answered "Room" request, so the requester can match the answer to it
     AcceptedRoom(Option<RoomId>, User),
-    /// Rhizome internal server error. "String" is the error message. This is sent for debugability.
-    ServerError(String),
+    /// Rhizome failed to handle a request. "ErrorCode" is the reason, "String" is the error message. This is sent for debugability.
+    ServerError(ErrorCode, String),
     /// Rhizome wants to terminate the connection. OR The connection has been closed (read yielded Ok(0))
     Shutdown(),
+    /// "SessionToken" identifies the session of this connection. Rhizome sends this on connect and after a successful "Resume"
+    Session(SessionToken),
 }
 
 impl RhizMessage {
+    /// Creates a [RhizMessage::ServerError] from its parts
+    pub fn server_error(code: ErrorCode, message: impl Into<String>) -> Self {
+        RhizMessage::ServerError(code, message.into())
+    }
+
+    /// Converts a [RhizMessage::ServerError] into the typed [ServerError]
+    ///
+    /// Returns `None` for all other variants.
+    pub fn into_server_error(self) -> Option<ServerError> {
+        match self {
+            RhizMessage::ServerError(code, message) => Some(ServerError { code, message }),
+            _ => None,
+        }
+    }
+
     /// Serializes ([postcard]) and packetizes (COBS) "self" and sends the resulting binary data using the supplied Tls Stream
     ///
     /// # Cancel safety
@@ -43,18 +62,37 @@ impl RhizMessage {
     pub async fn send_with<T>(self, tls: &mut BufReader<STlsStream<T>>) -> io::Result<()>
     where
         T: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.send_to(tls).await
+    }
+
+    /// Same as [RhizMessage::send_with] but for any kind of stream
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [RhizMessage::send_with].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::Error] when "self" was to large to be serialized within [MAX_MESSAGE_BUF_SIZE].</br>
+    /// The first error returned by writing to the stream.
+    pub async fn send_to<S>(self, stream: &mut S) -> io::Result<()>
+    where
+        S: AsyncWrite + Unpin,
     {
         // Serialize and packetize the message
         let bytes = postcard::to_vec_cobs::<Self, MAX_MESSAGE_BUF_SIZE>(&self).map_err(|_| {
             io::Error::new(
                 ErrorKind::OutOfMemory,
-                "Unable to serialize RhizMessage, more then 64 byte",
+                format!(
+                    "Unable to serialize RhizMessage, more than {} bytes",
+                    MAX_MESSAGE_BUF_SIZE
+                ),
             )
         })?;
 
         #[cfg(feature = "debug")]
         println!("sent msg");
-        tls.write_all(&bytes).await
+        stream.write_all(&bytes).await
     }
 
     /// Reads a [RhizMessage] from the Tls Stream, depacketizing (COBS) and deserializing ([postcard]) the data.
@@ -80,14 +118,96 @@ impl RhizMessage {
     ) -> io::Result<RhizMessage>
     where
         T: AsyncRead + AsyncWrite + Unpin,
+    {
+        Self::recv_from(tls, buf).await
+    }
+
+    /// Same as [RhizMessage::recv_with] but for any kind of buffered stream
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [RhizMessage::recv_with].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by reading from the stream.<br>
+    /// An [io::Error] when the data in "buf" is not a valid [RhizMessage].
+    pub async fn recv_from<S>(stream: &mut S, buf: &mut Vec<u8>) -> io::Result<RhizMessage>
+    where
+        S: AsyncBufRead + Unpin,
     {
         buf.clear();
         // 0 means EOF so we shutdown the connection
-        if 0 == tls.read_until(0, buf).await? {
+        if 0 == stream.read_until(0, buf).await? {
             return Ok(RhizMessage::Shutdown());
         }
 
         // depacketize and deserialize the message
         postcard::from_bytes_cobs(buf).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
     }
+
+    /// Same as [RhizMessage::send_to] but prefixes the message with the next sequence number of `sequencer`
+    ///
+    /// Both sides have to agree on sequenced framing before connecting, refer to [Sequenced].
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [RhizMessage::send_with].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::Error] when "self" was to large to be serialized within [MAX_MESSAGE_BUF_SIZE].</br>
+    /// The first error returned by writing to the stream.
+    pub async fn send_sequenced<S>(
+        self,
+        stream: &mut S,
+        sequencer: &mut Sequencer,
+    ) -> io::Result<()>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let sequenced = sequencer.peek(&self);
+        let bytes = postcard::to_vec_cobs::<_, { MAX_MESSAGE_BUF_SIZE + MAX_SEQUENCE_OVERHEAD }>(
+            &sequenced,
+        )
+        .map_err(|_| {
+            io::Error::new(
+                ErrorKind::OutOfMemory,
+                format!(
+                    "Unable to serialize RhizMessage, more than {} bytes",
+                    MAX_MESSAGE_BUF_SIZE
+                ),
+            )
+        })?;
+        // a message that could not be serialized does not consume its sequence number
+        sequencer.advance();
+        stream.write_all(&bytes).await
+    }
+
+    /// Same as [RhizMessage::recv_from] but for messages sent with [RhizMessage::send_sequenced]
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [RhizMessage::recv_with].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by reading from the stream.<br>
+    /// An [io::Error] when the data in "buf" is not a valid sequenced [RhizMessage].<br>
+    /// An [io::ErrorKind::InvalidData] with a [SequenceError](super::SequenceError) when the message was duplicated or messages were lost.
+    pub async fn recv_sequenced<S>(
+        stream: &mut S,
+        buf: &mut Vec<u8>,
+        check: &mut SequenceCheck,
+    ) -> io::Result<RhizMessage>
+    where
+        S: AsyncBufRead + Unpin,
+    {
+        buf.clear();
+        // 0 means EOF so we shutdown the connection
+        if 0 == stream.read_until(0, buf).await? {
+            return Ok(RhizMessage::Shutdown());
+        }
+
+        let sequenced: Sequenced<RhizMessage> = postcard::from_bytes_cobs(buf)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        Ok(check.accept(sequenced)?)
+    }
 }
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ErrorCode, ServerError};
+
+/// Largest amount of bytes [Sequenced] adds to the serialized message, the size of a u64 varint
+pub const MAX_SEQUENCE_OVERHEAD: usize = 10;
+
+/// Message `M` together with its position on the connection
+///
+/// Both directions of a connection count separately, starting at 0 for the first message.
+/// The numbers are not carried over to a resumed session, every connection starts anew.
+///
+/// Sequenced framing is not negotiated on the wire, a [Sequenced] frame is not a valid
+/// plain frame and vice versa. Rhizome decides which framing it speaks and clients are
+/// configured to match it before connecting. A connection then uses the `*_sequenced`
+/// methods for every message in both directions, starting with the first
+/// [RhizMessage::Session](super::RhizMessage::Session) greeting, or never.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Sequenced<M> {
+    pub seq: u64,
+    pub message: M,
+}
+
+/// Assigns monotonically increasing sequence numbers to sent messages
+#[derive(Clone, Debug, Default)]
+pub struct Sequencer {
+    next: u64,
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Sequencer::default()
+    }
+
+    /// Wraps `message` with the next sequence number
+    pub fn wrap<M>(&mut self, message: M) -> Sequenced<M> {
+        let sequenced = self.peek(message);
+        self.advance();
+        sequenced
+    }
+
+    /// Wraps `message` with the next sequence number without consuming it
+    ///
+    /// Call [Sequencer::advance] once the message was serialized, so a message that
+    /// was never sent does not leave a gap.
+    pub fn peek<M>(&self, message: M) -> Sequenced<M> {
+        Sequenced {
+            seq: self.next,
+            message,
+        }
+    }
+
+    /// Consumes the sequence number returned by [Sequencer::peek]
+    pub fn advance(&mut self) {
+        self.next += 1;
+    }
+
+    /// Sequence number the next message will be sent with
+    pub fn next_seq(&self) -> u64 {
+        self.next
+    }
+}
+
+/// Detects duplicated and lost messages on the receiving side of a connection
+#[derive(Clone, Debug, Default)]
+pub struct SequenceCheck {
+    expected: u64,
+}
+
+impl SequenceCheck {
+    pub fn new() -> Self {
+        SequenceCheck::default()
+    }
+
+    /// Returns the message of `sequenced` if it is the next message of the connection
+    ///
+    /// A rejected message does not advance the expected sequence number, the
+    /// connection should be closed since its state can no longer be trusted.
+    ///
+    /// # Errors
+    /// A [SequenceError::Duplicate] when the message was already received<br>
+    /// A [SequenceError::Gap] when at least one message was lost
+    pub fn accept<M>(&mut self, sequenced: Sequenced<M>) -> Result<M, SequenceError> {
+        let expected = self.expected;
+        let received = sequenced.seq;
+        if received < expected {
+            return Err(SequenceError::Duplicate { expected, received });
+        }
+        if received > expected {
+            return Err(SequenceError::Gap { expected, received });
+        }
+
+        self.expected += 1;
+        Ok(sequenced.message)
+    }
+
+    /// Sequence number the next message has to carry
+    pub fn expected(&self) -> u64 {
+        self.expected
+    }
+}
+
+/// Violation of the message order detected by [SequenceCheck]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceError {
+    /// A message with an already received sequence number arrived
+    Duplicate { expected: u64, received: u64 },
+    /// The messages from `expected` up to, but excluding, `received` were lost
+    Gap { expected: u64, received: u64 },
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::Duplicate { expected, received } => {
+                write!(f, "duplicate message {}, expected {}", received, expected)
+            }
+            SequenceError::Gap { expected, received } => {
+                write!(f, "lost messages {} to {}", expected, received - 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SequenceError {}
+
+impl From<SequenceError> for io::Error {
+    /// Creates an [io::ErrorKind::InvalidData] with the [SequenceError] as its inner error
+    fn from(err: SequenceError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl From<SequenceError> for ServerError {
+    fn from(err: SequenceError) -> Self {
+        ServerError::new(ErrorCode::ProtocolViolation, err.to_string())
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+/// Machine readable reason for a [RhizMessage::ServerError](super::RhizMessage::ServerError)
+///
+/// The variants are serialized by index, new codes must only ever be appended.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The client sent more requests than Rhizome is willing to handle
+    RateLimited,
+    /// The "User" a request was refering to is not known to Rhizome
+    UnknownUser,
+    /// The room a request was refering to does not exist (anymore)
+    RoomExpired,
+    /// Rhizome failed for reasons unrelated to the client
+    Internal,
+    /// The client sent a message that is not valid in the current state
+    ProtocolViolation,
+    /// The client uses a protocol version that Rhizome does not support
+    VersionMismatch,
+    /// The session a client tried to resume does not exist (anymore)
+    SessionExpired,
+}
+
+impl ErrorCode {
+    /// Returns true when repeating the failed request at a later point can succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ErrorCode::RateLimited | ErrorCode::Internal)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorCode::RateLimited => "rate limited",
+            ErrorCode::UnknownUser => "unknown user",
+            ErrorCode::RoomExpired => "room expired",
+            ErrorCode::Internal => "internal server error",
+            ErrorCode::ProtocolViolation => "protocol violation",
+            ErrorCode::VersionMismatch => "version mismatch",
+            ErrorCode::SessionExpired => "session expired",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Client side representation of a [RhizMessage::ServerError](super::RhizMessage::ServerError)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerError {
+    pub code: ErrorCode,
+    /// Free text supplied by Rhizome for debugability
+    pub message: String,
+}
+
+impl ServerError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Refer to [ErrorCode::is_retryable]
+    pub fn is_retryable(&self) -> bool {
+        self.code.is_retryable()
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+impl From<ServerError> for super::RhizMessage {
+    fn from(err: ServerError) -> Self {
+        super::RhizMessage::ServerError(err.code, err.message)
+    }
+}
+
+impl From<ServerError> for io::Error {
+    fn from(err: ServerError) -> Self {
+        let kind = match err.code {
+            ErrorCode::RateLimited => io::ErrorKind::WouldBlock,
+            ErrorCode::UnknownUser => io::ErrorKind::NotFound,
+            ErrorCode::RoomExpired => io::ErrorKind::NotFound,
+            ErrorCode::Internal => io::ErrorKind::Other,
+            ErrorCode::ProtocolViolation => io::ErrorKind::InvalidData,
+            ErrorCode::VersionMismatch => io::ErrorKind::Unsupported,
+            ErrorCode::SessionExpired => io::ErrorKind::NotFound,
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Eq;
+use std::fmt;
+
+/// Secret that resumes a session, its bytes are never printed by [Debug]
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionToken(pub [u8; 32]);
+
+impl fmt::Debug for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SessionToken")
+            .field(&format_args!("<redacted>"))
+            .finish()
+    }
+}
@@ -2,28 +2,43 @@ use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
+use super::compression::Algorithm;
+use super::profile::SignedProfile;
 use super::vlink;
+use crate::identity::SignatureScheme;
 
 pub const MAX_SIGNAL_BUF_SIZE: usize = 4096;
 pub const KAP_TIMEOUT: Duration = Duration::from_secs(20);
 
+/// Identifies one of multiple vlinks that are multiplexed over a single peer connection
+pub type TunnelId = u16;
+
 /// Container for all possible messages that are being sent from Emberry (client) to Emberry (client) (p2p)
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum Signal {
     /// Keep alive message
     Kap,
-    /// `Username( ... ).0` - sets the `username` of the peer
-    Username(String),
-    /// `Vlink( ... ).0` - Transfers a wrapped [vlink::Action] to the currently active [vlink::TcpBridge]
-    Vlink(vlink::Signal),
-    /// `VlinkOpen( ... ).0` - `vlinkid`
+    /// `Profile( ... ).0` - sets the `profile` of the peer
+    ///
+    /// Only valid when it was signed by the authenticated peer and is not older than the
+    /// last one, refer to [SignedProfile::verify_update]
+    Profile(SignedProfile),
+    /// `Vlink( ... ).0` - `tunnel` id of the vlink
+    ///
+    /// `Vlink( ... ).1` - Transfers a wrapped [vlink::Action] to the [vlink::TcpBridge] of `tunnel`
+    Vlink(TunnelId, vlink::Signal),
+    /// `VlinkOpen( ... ).0` - `tunnel` id that adresses the vlink in all following [Signal]s
+    ///
+    /// `VlinkOpen( ... ).1` - `vlinkid`
     ///
     /// Informs the peer that a vlink with the name of `vlinkid` has been opened to accept connections
-    VlinkOpen(String),
-    /// Cuts the current Vlink connection
+    VlinkOpen(TunnelId, String),
+    /// `VlinkCut( ... ).0` - `tunnel` id of the vlink
+    ///
+    /// Cuts the Vlink connection of `tunnel`
     ///
-    /// This is only valid information when the client sending this previously sent VlinkOpen
-    VlinkCut,
+    /// This is only valid information when the client sending this previously sent VlinkOpen for `tunnel`
+    VlinkCut(TunnelId),
     /// `ChangeContext( ... ).0` - changes the `context` to which all following context sensitive [Signal]s are adressed
     ChangeContext(String),
     /// CONTEXT SENSITIVE
@@ -32,4 +47,24 @@ pub enum Signal {
     ///
     /// `message` is unsalitized UTF-8 user input
     Message(String),
+    /// `Compression( ... ).0` - `algorithms` the sender is able to decompress
+    ///
+    /// Allows the peer to send [Signal::Compressed] from now on, refer to [Compression](super::compression::Compression)
+    Compression(Vec<Algorithm>),
+    /// `Compressed( ... ).0` - `algorithm` that was used to compress the payload
+    ///
+    /// `Compressed( ... ).1` - serialized [Signal] compressed with `algorithm`
+    ///
+    /// Only sent to peers that announced `algorithm` with [Signal::Compression]
+    Compressed(Algorithm, #[serde(with = "serde_bytes")] Vec<u8>),
+    /// `AuthChallenge( ... ).0` - random `nonce` the peer has to sign
+    ///
+    /// Refer to [PeerAuth](crate::peer_auth::PeerAuth)
+    AuthChallenge([u8; 32]),
+    /// `AuthResponse( ... ).0` - `scheme` of the signature
+    ///
+    /// `AuthResponse( ... ).1` - `signature` of the received [Signal::AuthChallenge] and the room
+    ///
+    /// Refer to [PeerAuth](crate::peer_auth::PeerAuth)
+    AuthResponse(SignatureScheme, #[serde(with = "serde_bytes")] Vec<u8>),
 }
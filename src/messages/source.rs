@@ -1,7 +1,43 @@
-use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fmt;
+
+use serde::de::{Deserialize, DeserializeOwned};
 use tokio::io::AsyncBufRead;
 
 use self::partial_tokio_copy::*;
+use super::fragment::{read_message_fragmented, ReadMsgFragmented, Reassembler};
+
+/// Largest serialized message that is buffered by the [Source] futures unless configured otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Error of a [Source] future when the message being read exceeds its maximum size
+///
+/// Returned as the inner error of an [std::io::ErrorKind::InvalidData].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageTooLarge {
+    /// The configured maximum size
+    pub max_size: usize,
+}
+
+impl fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "message exceeds the maximum size of {} bytes",
+            self.max_size
+        )
+    }
+}
+
+impl Error for MessageTooLarge {}
+
+/// Message type that borrows from the data it is deserialized from
+///
+/// Implemented for every lifetime of the message so `M` can be named without
+/// a specific lifetime when calling [Source::read_message_ref].
+pub trait MessageRef {
+    type Ref<'de>: Deserialize<'de>;
+}
 
 pub trait Source {
     fn read_message<M: DeserializeOwned>(&mut self) -> ReadMsg<Self, M>;
@@ -9,6 +45,18 @@ pub trait Source {
         &'a mut self,
         agg: &'a mut Vec<u8>,
     ) -> ReadMsgCancel<Self, M>;
+    fn read_message_ref<'a, M: MessageRef>(
+        &'a mut self,
+        buf: &'a mut Vec<u8>,
+    ) -> ReadMsgRef<Self, M>;
+    fn read_messages_into<'a, M: DeserializeOwned>(
+        &'a mut self,
+        messages: &'a mut Vec<M>,
+    ) -> ReadMsgs<Self, M>;
+    fn read_message_fragmented<'a, M: DeserializeOwned>(
+        &'a mut self,
+        reassembler: &'a mut Reassembler,
+    ) -> ReadMsgFragmented<Self, M>;
 }
 
 impl<R> Source for R
@@ -35,6 +83,8 @@ where
     /// # Errors
     /// This function will return:</br>
     /// The first error returned by [self]<br>
+    /// An [ErrorKind::InvalidData] containing [MessageTooLarge] when more than the maximum size
+    /// ([DEFAULT_MAX_MESSAGE_SIZE] unless changed with `max_size`) has to be buffered<br>
     /// An [ErrorKind::Other] when buf_reader's buffer does not contain a valid [M]
     /// In this case calling the function again might repeatedly yield errors until a message
     /// is magically perfectly aligned.
@@ -67,6 +117,8 @@ where
     /// # Errors
     /// This function will return:</br>
     /// The first error returned by [self]<br>
+    /// An [ErrorKind::InvalidData] containing [MessageTooLarge] when more than the maximum size
+    /// ([DEFAULT_MAX_MESSAGE_SIZE] unless changed with `max_size`) has to be buffered<br>
     /// An [ErrorKind::Other] when buf_reader's buffer does not contain a valid [M]
     /// In this case calling the function again might repeatedly yield errors until a message
     /// is magically perfectly aligned.
@@ -79,6 +131,111 @@ where
     {
         read_message_cancelable(self, agg)
     }
+
+    /// Reads a [M] from the buf_reader, deserializing ([postcard]) the data
+    /// into a message that borrows from `buf`.
+    ///
+    /// Equivalent to
+    /// ```ignore
+    /// async fn read_message_ref<'a, M>(&'a mut self, buf: &'a mut Vec<u8>) -> io::Result<M::Ref<'a>>
+    /// ```
+    ///
+    /// The data of the message is copied into `buf`, which is cleared first.
+    /// Reusing `buf` for every message avoids allocating per message.
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. If the method is used as
+    /// the event in a tokio::select statement and some other branch
+    /// completes first, then some data may have been partially read to `buf`.
+    /// Calling this method again will clear `buf`, most likely resulting
+    /// in deserialization failure.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [self]<br>
+    /// An [ErrorKind::InvalidData] containing [MessageTooLarge] when more than the maximum size
+    /// ([DEFAULT_MAX_MESSAGE_SIZE] unless changed with `max_size`) has to be buffered<br>
+    /// An [ErrorKind::Other] when buf_reader's buffer does not contain a valid [M]
+    /// In this case calling the function again might repeatedly yield errors until a message
+    /// is magically perfectly aligned.
+    fn read_message_ref<'a, M: MessageRef>(
+        &'a mut self,
+        buf: &'a mut Vec<u8>,
+    ) -> ReadMsgRef<Self, M>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        read_message_ref(self, buf)
+    }
+
+    /// Reads every complete [M] that is buffered by the buf_reader, deserializing ([postcard]) the data
+    /// and appending the messages to `messages`.
+    ///
+    /// Equivalent to
+    /// ```ignore
+    /// async fn read_messages_into<M>(&mut self, messages: &mut Vec<M>) -> io::Result<usize>
+    /// ```
+    ///
+    /// Completes with the amount of appended messages, which is at least 1.
+    /// A trailing partial message is left in the buf_reader for the next call.
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. If the method is used as
+    /// the event in a tokio::select statement and some other branch
+    /// completes first, then some data may have been partially read.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [self]<br>
+    /// An [ErrorKind::InvalidData] containing [MessageTooLarge] when more than the maximum size
+    /// ([DEFAULT_MAX_MESSAGE_SIZE] unless changed with `max_size`) has to be buffered<br>
+    /// An [ErrorKind::Other] when buf_reader's buffer does not contain a valid [M]
+    /// In this case `messages` may have been extended by the messages preceding the invalid data.
+    fn read_messages_into<'a, M: DeserializeOwned>(
+        &'a mut self,
+        messages: &'a mut Vec<M>,
+    ) -> ReadMsgs<Self, M>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        read_messages_into(self, messages)
+    }
+
+    /// Reads a [M] sent with [Drain::serialize_fragmented_to](super::Drain::serialize_fragmented_to)
+    /// from the buf_reader, reassembling its [Fragment](super::fragment::Fragment)s in `reassembler`
+    /// and deserializing ([postcard]) the data.
+    ///
+    /// Equivalent to
+    /// ```ignore
+    /// async fn read_message_fragmented<M>(&mut self, reassembler: &mut Reassembler) -> io::Result<M>
+    /// ```
+    ///
+    /// The data of a fragment is copied to the message as it arrives, a fragment
+    /// is never buffered on its own.
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe. If the method is used as
+    /// the event in a tokio::select statement and some other branch
+    /// completes first, then the partially read message is kept in `reassembler`.
+    /// Calling this method again with the same `reassembler` completes the message.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [self]<br>
+    /// An [ErrorKind::InvalidData] containing [MessageTooLarge] when a fragment exceeds the fragment size
+    /// or the message exceeds the maximum size of `reassembler`, the remaining fragments of that message
+    /// are skipped by the next call<br>
+    /// An [ErrorKind::InvalidData] when the buf_reader does not contain a valid fragment<br>
+    /// An [ErrorKind::Other] when the reassembled data is not a valid [M]
+    fn read_message_fragmented<'a, M: DeserializeOwned>(
+        &'a mut self,
+        reassembler: &'a mut Reassembler,
+    ) -> ReadMsgFragmented<Self, M>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        read_message_fragmented(self, reassembler)
+    }
 }
 
 mod partial_tokio_copy {
@@ -93,12 +250,15 @@ mod partial_tokio_copy {
     use std::task::{ready, Context, Poll};
     use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 
+    use super::{MessageRef, MessageTooLarge, DEFAULT_MAX_MESSAGE_SIZE};
+
     pin_project! {
         #[derive(Debug)]
         #[must_use = "futures do nothing unless you `.await` or poll them"]
         pub struct ReadMsg<'a, R: ?Sized, M: DeserializeOwned> {
             buf_reader: &'a mut R,
             agg: Option<Vec<u8>>,
+            max_size: usize,
             // Make this future `!Unpin` for compatibility with async trait methods.
             #[pin]
             _pin: PhantomPinned,
@@ -116,11 +276,20 @@ mod partial_tokio_copy {
         ReadMsg {
             buf_reader,
             agg: None,
+            max_size: DEFAULT_MAX_MESSAGE_SIZE,
             _pin: PhantomPinned,
             _message: PhantomData,
         }
     }
 
+    impl<R: ?Sized, M: DeserializeOwned> ReadMsg<'_, R, M> {
+        /// Sets the largest serialized message that is accepted
+        pub fn max_size(mut self, max_size: usize) -> Self {
+            self.max_size = max_size;
+            self
+        }
+    }
+
     impl<'a, R, M: DeserializeOwned> Future for ReadMsg<'a, R, M>
     where
         R: AsyncBufRead + Unpin + ?Sized,
@@ -135,7 +304,7 @@ mod partial_tokio_copy {
                 let Some(agg) = me.agg else {
                     let data = ready!(Pin::new(&mut *me.buf_reader).poll_fill_buf(cx))?;
 
-                    let (message, used) = try_deser::<M>(data);
+                    let (message, used) = try_deser::<M>(data, *me.max_size);
 
                     // if we have no message push the data to the aggregator
                     if let Ok(None) = message {
@@ -167,7 +336,7 @@ mod partial_tokio_copy {
                 let agg_size_before = agg.len();
                 agg.extend_from_slice(data); //0001
 
-                let (message, used_total) = try_deser::<M>(agg);
+                let (message, used_total) = try_deser::<M>(agg, *me.max_size);
 
                 // subtract agg_size_before to get the "new" bytes that were used
                 let used_data = used_total - agg_size_before;
@@ -196,6 +365,7 @@ mod partial_tokio_copy {
         pub struct ReadMsgCancel<'a, R: ?Sized, M: DeserializeOwned> {
             buf_reader: &'a mut R,
             agg: &'a mut Vec<u8>,
+            max_size: usize,
             // Make this future `!Unpin` for compatibility with async trait methods.
             #[pin]
             _pin: PhantomPinned,
@@ -213,11 +383,20 @@ mod partial_tokio_copy {
         ReadMsgCancel {
             buf_reader,
             agg,
+            max_size: DEFAULT_MAX_MESSAGE_SIZE,
             _pin: PhantomPinned,
             _message: PhantomData,
         }
     }
 
+    impl<R: ?Sized, M: DeserializeOwned> ReadMsgCancel<'_, R, M> {
+        /// Sets the largest serialized message that is accepted
+        pub fn max_size(mut self, max_size: usize) -> Self {
+            self.max_size = max_size;
+            self
+        }
+    }
+
     impl<'a, R, M: DeserializeOwned> Future for ReadMsgCancel<'a, R, M>
     where
         R: AsyncBufRead + Unpin + ?Sized,
@@ -233,7 +412,7 @@ mod partial_tokio_copy {
                 if me.agg.is_empty() {
                     let data = ready!(Pin::new(&mut *me.buf_reader).poll_fill_buf(cx))?;
 
-                    let (message, used) = try_deser::<M>(data);
+                    let (message, used) = try_deser::<M>(data, *me.max_size);
 
                     // if we have no message push the data to the aggregator
                     if let Ok(None) = message {
@@ -278,7 +457,7 @@ mod partial_tokio_copy {
                     let agg_size_before = me.agg.len();
                     me.agg.extend_from_slice(data); //0001
 
-                    let (message, used_total) = try_deser::<M>(me.agg);
+                    let (message, used_total) = try_deser::<M>(me.agg, *me.max_size);
 
                     // subtract agg_size_before to get the "new" bytes that were used
                     let used_data = used_total - agg_size_before;
@@ -312,12 +491,258 @@ mod partial_tokio_copy {
         }
     }
 
+    pin_project! {
+        #[derive(Debug)]
+        #[must_use = "futures do nothing unless you `.await` or poll them"]
+        pub struct ReadMsgRef<'a, R: ?Sized, M: MessageRef> {
+            buf_reader: &'a mut R,
+            // taken when the message borrows the buffer for `'a`
+            buf: Option<&'a mut Vec<u8>>,
+            max_size: usize,
+            // Make this future `!Unpin` for compatibility with async trait methods.
+            #[pin]
+            _pin: PhantomPinned,
+            _message: PhantomData<M>,
+        }
+    }
+
+    pub(crate) fn read_message_ref<'a, R, M: MessageRef>(
+        buf_reader: &'a mut R,
+        buf: &'a mut Vec<u8>,
+    ) -> ReadMsgRef<'a, R, M>
+    where
+        R: AsyncBufRead + Unpin + ?Sized,
+    {
+        buf.clear();
+        ReadMsgRef {
+            buf_reader,
+            buf: Some(buf),
+            max_size: DEFAULT_MAX_MESSAGE_SIZE,
+            _pin: PhantomPinned,
+            _message: PhantomData,
+        }
+    }
+
+    impl<R: ?Sized, M: MessageRef> ReadMsgRef<'_, R, M> {
+        /// Sets the largest serialized message that is accepted
+        pub fn max_size(mut self, max_size: usize) -> Self {
+            self.max_size = max_size;
+            self
+        }
+    }
+
+    impl<'a, R, M: MessageRef> Future for ReadMsgRef<'a, R, M>
+    where
+        R: AsyncBufRead + Unpin + ?Sized,
+    {
+        type Output = io::Result<M::Ref<'a>>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<M::Ref<'a>>> {
+            let me = self.project();
+
+            loop {
+                let data = ready!(Pin::new(&mut *me.buf_reader).poll_fill_buf(cx))?;
+                let buf = me.buf.as_mut().expect("ReadMsgRef polled after completion");
+
+                let buf_size_before = buf.len();
+                buf.extend_from_slice(data);
+
+                // only determine the length of the message here as the message
+                // cannot borrow `buf` for `'a` while `buf` may still be extended
+                let used_total = match postcard::take_from_bytes::<M::Ref<'_>>(buf) {
+                    Err(postcard::Error::DeserializeUnexpectedEnd) if buf.len() > *me.max_size => {
+                        Err(too_large(*me.max_size))
+                    }
+                    Err(postcard::Error::DeserializeUnexpectedEnd) => Ok(None),
+                    Err(err) => Err(io::Error::new(ErrorKind::Other, err)),
+                    Ok((_, rest)) if buf.len() - rest.len() > *me.max_size => {
+                        Err(too_large(*me.max_size))
+                    }
+                    Ok((_, rest)) => Ok(Some(buf.len() - rest.len())),
+                };
+
+                let used_total = match used_total {
+                    Ok(used_total) => used_total,
+                    Err(err) => {
+                        let used_data = buf.len() - buf_size_before;
+                        me.buf_reader.consume(used_data);
+                        buf.clear();
+                        return Poll::Ready(Err(err));
+                    }
+                };
+
+                let Some(used_total) = used_total else {
+                    let used_data = buf.len() - buf_size_before;
+                    me.buf_reader.consume(used_data);
+
+                    if used_data == 0 {
+                        return Poll::Ready(Err(io::Error::new(
+                            ErrorKind::ConnectionReset,
+                            "EOF reached",
+                        )));
+                    }
+                    continue;
+                };
+
+                // subtract buf_size_before to get the "new" bytes that were used
+                me.buf_reader.consume(used_total - buf_size_before);
+                buf.truncate(used_total);
+
+                let buf: &'a [u8] = me.buf.take().expect("checked above");
+                let message = postcard::from_bytes::<M::Ref<'a>>(buf)
+                    .map_err(|err| io::Error::new(ErrorKind::Other, err));
+                return Poll::Ready(message);
+            }
+        }
+    }
+
+    pin_project! {
+        #[derive(Debug)]
+        #[must_use = "futures do nothing unless you `.await` or poll them"]
+        pub struct ReadMsgs<'a, R: ?Sized, M: DeserializeOwned> {
+            buf_reader: &'a mut R,
+            messages: &'a mut Vec<M>,
+            agg: Option<Vec<u8>>,
+            max_size: usize,
+            // Make this future `!Unpin` for compatibility with async trait methods.
+            #[pin]
+            _pin: PhantomPinned,
+        }
+    }
+
+    pub(crate) fn read_messages_into<'a, R, M: DeserializeOwned>(
+        buf_reader: &'a mut R,
+        messages: &'a mut Vec<M>,
+    ) -> ReadMsgs<'a, R, M>
+    where
+        R: AsyncBufRead + Unpin + ?Sized,
+    {
+        ReadMsgs {
+            buf_reader,
+            messages,
+            agg: None,
+            max_size: DEFAULT_MAX_MESSAGE_SIZE,
+            _pin: PhantomPinned,
+        }
+    }
+
+    impl<R: ?Sized, M: DeserializeOwned> ReadMsgs<'_, R, M> {
+        /// Sets the largest serialized message that is accepted
+        pub fn max_size(mut self, max_size: usize) -> Self {
+            self.max_size = max_size;
+            self
+        }
+    }
+
+    impl<'a, R, M: DeserializeOwned> Future for ReadMsgs<'a, R, M>
+    where
+        R: AsyncBufRead + Unpin + ?Sized,
+    {
+        type Output = io::Result<usize>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+            let me = self.project();
+
+            loop {
+                let data = ready!(Pin::new(&mut *me.buf_reader).poll_fill_buf(cx))?;
+
+                // a message that spans multiple reads is completed before draining the buffer
+                if let Some(agg) = me.agg {
+                    let agg_size_before = agg.len();
+                    agg.extend_from_slice(data);
+
+                    let (message, used_total) = try_deser::<M>(agg, *me.max_size);
+                    let used_data = used_total - agg_size_before;
+                    me.buf_reader.consume(used_data);
+
+                    if let Some(message) = message? {
+                        me.messages.push(message);
+                        return Poll::Ready(Ok(1));
+                    }
+
+                    if used_data == 0 {
+                        return Poll::Ready(Err(io::Error::new(
+                            ErrorKind::ConnectionReset,
+                            "EOF reached",
+                        )));
+                    }
+                    continue;
+                }
+
+                // decode every complete message without copying the data
+                let mut rest = data;
+                let mut count = 0;
+                let mut error = None;
+                while !rest.is_empty() || count == 0 {
+                    let (message, used) = try_deser::<M>(rest, *me.max_size);
+                    match message {
+                        Ok(Some(message)) => {
+                            me.messages.push(message);
+                            count += 1;
+                            rest = &rest[used..];
+                            // zero sized messages would never drain the buffer
+                            if used == 0 {
+                                break;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(err) => {
+                            error = Some(err);
+                            rest = &rest[used..];
+                            break;
+                        }
+                    }
+                }
+
+                let used_total = data.len() - rest.len();
+                let partial = rest.len();
+
+                if let Some(err) = error {
+                    me.buf_reader.consume(used_total);
+                    return Poll::Ready(Err(err));
+                }
+
+                if count > 0 {
+                    // leave the partial message in the buf_reader
+                    me.buf_reader.consume(used_total);
+                    return Poll::Ready(Ok(count));
+                }
+
+                if partial == 0 {
+                    return Poll::Ready(Err(io::Error::new(
+                        ErrorKind::ConnectionReset,
+                        "EOF reached",
+                    )));
+                }
+
+                // the buffer only holds the beginning of a message
+                let mut vec = Vec::with_capacity(partial * 2);
+                vec.extend_from_slice(rest);
+                *me.agg = Some(vec);
+                me.buf_reader.consume(partial);
+            }
+        }
+    }
+
     #[inline]
-    fn try_deser<M: DeserializeOwned>(data: &[u8]) -> (io::Result<Option<M>>, usize) {
+    fn try_deser<M: DeserializeOwned>(
+        data: &[u8],
+        max_size: usize,
+    ) -> (io::Result<Option<M>>, usize) {
         match postcard::take_from_bytes::<M>(data) {
+            Err(postcard::Error::DeserializeUnexpectedEnd) if data.len() > max_size => {
+                (Err(too_large(max_size)), data.len())
+            }
             Err(postcard::Error::DeserializeUnexpectedEnd) => (Ok(None), data.len()),
+            Ok((_, rest)) if data.len() - rest.len() > max_size => {
+                (Err(too_large(max_size)), data.len() - rest.len())
+            }
             Err(err) => (Err(io::Error::new(ErrorKind::Other, err)), data.len()),
             Ok((msg, rest)) => (Ok(Some(msg)), data.len() - rest.len()),
         }
     }
+
+    fn too_large(max_size: usize) -> io::Error {
+        io::Error::new(ErrorKind::InvalidData, MessageTooLarge { max_size })
+    }
 }
@@ -1,35 +1,65 @@
+use std::borrow::Cow;
+use std::io;
+
 use serde::{Deserialize, Serialize};
 use vlink::Action;
 
+use super::source::MessageRef;
+
 /// wraps [Action] for usage with smoke
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum Signal {
     /// refer to [Action::Connect]
     Connect(u16),
     /// refer to [Action::Data]
-    Data(u16, Vec<u8>),
+    Data(u16, #[serde(with = "serde_bytes")] Vec<u8>),
     /// refer to [Action::Error]
-    /// The error is stingified for serialization
-    Error(u16, String),
+    /// The error is stingified for serialization, its kind is preserved as [ErrorKindCode]
+    Error(u16, ErrorKindCode, String),
     /// refer to [Action::AcceptError]
-    /// The error is stingified for serialization
-    AcceptError(String),
+    /// The error is stingified for serialization, its kind is preserved as [ErrorKindCode]
+    AcceptError(ErrorKindCode, String),
+    /// `WindowUpdate( ... ).0` - `vport`
+    ///
+    /// `WindowUpdate( ... ).1` - `credit` amount of additional [Signal::Data] payload bytes the sender may transfer on `vport`
+    ///
+    /// This has no [Action] equivalent and is handled by the [crate::bridge] driver
+    WindowUpdate(u16, u32),
+    /// `Datagram( ... ).0` - `vport`
+    ///
+    /// `Datagram( ... ).1` - `flow` id of the UDP peer the datagram belongs to
+    ///
+    /// `Datagram( ... ).2` - `payload` of a single UDP datagram
+    ///
+    /// This has no [Action] equivalent and is handled by the [crate::datagram] driver
+    Datagram(u16, u32, #[serde(with = "serde_bytes")] Vec<u8>),
+    /// `FlowClosed( ... ).0` - `vport`
+    ///
+    /// `FlowClosed( ... ).1` - `flow` id that was closed after being idle
+    ///
+    /// This has no [Action] equivalent and is handled by the [crate::datagram] driver
+    FlowClosed(u16, u32),
 }
 
 impl Signal {
     /// Converts [Signal] to [Action] using borrowing for the Data variant
-    pub fn as_vlink(&self) -> Action<'_> {
-        match self {
+    ///
+    /// Returns `None` for flow control signals that have no [Action] equivalent
+    pub fn as_vlink(&self) -> Option<Action<'_>> {
+        let action = match self {
             Signal::Data(vport, data) => Action::Data(*vport, data),
             Signal::Connect(vport) => Action::Connect(*vport),
-            Signal::Error(vport, err) => Action::Error(
-                *vport,
-                std::io::Error::new(std::io::ErrorKind::Other, err.clone()),
-            ),
-            Signal::AcceptError(err) => {
-                Action::AcceptError(std::io::Error::new(std::io::ErrorKind::Other, err.clone()))
+            Signal::Error(vport, kind, err) => {
+                Action::Error(*vport, io::Error::new((*kind).into(), err.clone()))
             }
-        }
+            Signal::AcceptError(kind, err) => {
+                Action::AcceptError(io::Error::new((*kind).into(), err.clone()))
+            }
+            Signal::WindowUpdate(..) | Signal::Datagram(..) | Signal::FlowClosed(..) => {
+                return None
+            }
+        };
+        Some(action)
     }
 
     /// Converts [Action] to [Signal] cloning all the contained data
@@ -37,8 +67,189 @@ impl Signal {
         match vlink {
             Action::Data(vport, data) => Signal::Data(*vport, Vec::from(*data)),
             Action::Connect(vport) => Signal::Connect(*vport),
-            Action::Error(vport, err) => Signal::Error(*vport, err.to_string()),
-            Action::AcceptError(err) => Signal::AcceptError(err.to_string()),
+            Action::Error(vport, err) => Signal::Error(*vport, err.kind().into(), err.to_string()),
+            Action::AcceptError(err) => Signal::AcceptError(err.kind().into(), err.to_string()),
+        }
+    }
+
+    /// Borrows [Signal] as [SignalRef] without copying the data
+    pub fn as_signal_ref(&self) -> SignalRef<'_> {
+        match self {
+            Signal::Connect(vport) => SignalRef::Connect(*vport),
+            Signal::Data(vport, data) => SignalRef::Data(*vport, data),
+            Signal::Error(vport, kind, err) => SignalRef::Error(*vport, *kind, Cow::Borrowed(err)),
+            Signal::AcceptError(kind, err) => SignalRef::AcceptError(*kind, Cow::Borrowed(err)),
+            Signal::WindowUpdate(vport, credit) => SignalRef::WindowUpdate(*vport, *credit),
+            Signal::Datagram(vport, flow, data) => SignalRef::Datagram(*vport, *flow, data),
+            Signal::FlowClosed(vport, flow) => SignalRef::FlowClosed(*vport, *flow),
+        }
+    }
+}
+
+/// Borrowing equivalent of [Signal]
+///
+/// [SignalRef] has the same binary representation as [Signal] and can be sent
+/// with [Drain](super::Drain) or read with [Source::read_message_ref](super::Source::read_message_ref)
+/// on one side while the other side uses [Signal]. This avoids copying the payload
+/// of [SignalRef::Data] on the hot path of TCP tunneling.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum SignalRef<'a> {
+    /// refer to [Signal::Connect]
+    Connect(u16),
+    /// refer to [Signal::Data]
+    Data(
+        u16,
+        #[serde(serialize_with = "serde_bytes::serialize")] &'a [u8],
+    ),
+    /// refer to [Signal::Error]
+    Error(u16, ErrorKindCode, #[serde(borrow)] Cow<'a, str>),
+    /// refer to [Signal::AcceptError]
+    AcceptError(ErrorKindCode, #[serde(borrow)] Cow<'a, str>),
+    /// refer to [Signal::WindowUpdate]
+    WindowUpdate(u16, u32),
+    /// refer to [Signal::Datagram]
+    Datagram(
+        u16,
+        u32,
+        #[serde(serialize_with = "serde_bytes::serialize")] &'a [u8],
+    ),
+    /// refer to [Signal::FlowClosed]
+    FlowClosed(u16, u32),
+}
+
+impl<'a> SignalRef<'a> {
+    /// Converts [SignalRef] to [Action] using borrowing for the Data variant
+    ///
+    /// Returns `None` for flow control signals that have no [Action] equivalent
+    pub fn as_vlink(&self) -> Option<Action<'a>> {
+        let action = match self {
+            SignalRef::Data(vport, data) => Action::Data(*vport, data),
+            SignalRef::Connect(vport) => Action::Connect(*vport),
+            SignalRef::Error(vport, kind, err) => {
+                Action::Error(*vport, io::Error::new((*kind).into(), err.to_string()))
+            }
+            SignalRef::AcceptError(kind, err) => {
+                Action::AcceptError(io::Error::new((*kind).into(), err.to_string()))
+            }
+            SignalRef::WindowUpdate(..) | SignalRef::Datagram(..) | SignalRef::FlowClosed(..) => {
+                return None
+            }
+        };
+        Some(action)
+    }
+
+    /// Converts [Action] to [SignalRef] borrowing the data, only errors are stringified
+    pub fn from_vlink(vlink: &Action<'a>) -> SignalRef<'a> {
+        match vlink {
+            Action::Data(vport, data) => SignalRef::Data(*vport, data),
+            Action::Connect(vport) => SignalRef::Connect(*vport),
+            Action::Error(vport, err) => {
+                SignalRef::Error(*vport, err.kind().into(), Cow::Owned(err.to_string()))
+            }
+            Action::AcceptError(err) => {
+                SignalRef::AcceptError(err.kind().into(), Cow::Owned(err.to_string()))
+            }
+        }
+    }
+
+    /// Converts [SignalRef] to [Signal] cloning all the contained data
+    pub fn to_signal(&self) -> Signal {
+        match self {
+            SignalRef::Connect(vport) => Signal::Connect(*vport),
+            SignalRef::Data(vport, data) => Signal::Data(*vport, data.to_vec()),
+            SignalRef::Error(vport, kind, err) => Signal::Error(*vport, *kind, err.to_string()),
+            SignalRef::AcceptError(kind, err) => Signal::AcceptError(*kind, err.to_string()),
+            SignalRef::WindowUpdate(vport, credit) => Signal::WindowUpdate(*vport, *credit),
+            SignalRef::Datagram(vport, flow, data) => {
+                Signal::Datagram(*vport, *flow, data.to_vec())
+            }
+            SignalRef::FlowClosed(vport, flow) => Signal::FlowClosed(*vport, *flow),
+        }
+    }
+}
+
+impl MessageRef for SignalRef<'_> {
+    type Ref<'de> = SignalRef<'de>;
+}
+
+/// Serializable equivalent of [io::ErrorKind]
+///
+/// The variants are serialized by index, new kinds must only ever be appended.
+/// Kinds without an equivalent are transferred as [ErrorKindCode::Other].
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKindCode {
+    NotFound,
+    PermissionDenied,
+    ConnectionRefused,
+    ConnectionReset,
+    ConnectionAborted,
+    NotConnected,
+    AddrInUse,
+    AddrNotAvailable,
+    BrokenPipe,
+    AlreadyExists,
+    WouldBlock,
+    InvalidInput,
+    InvalidData,
+    TimedOut,
+    WriteZero,
+    Interrupted,
+    Unsupported,
+    UnexpectedEof,
+    OutOfMemory,
+    Other,
+}
+
+impl From<io::ErrorKind> for ErrorKindCode {
+    fn from(kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => ErrorKindCode::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorKindCode::PermissionDenied,
+            io::ErrorKind::ConnectionRefused => ErrorKindCode::ConnectionRefused,
+            io::ErrorKind::ConnectionReset => ErrorKindCode::ConnectionReset,
+            io::ErrorKind::ConnectionAborted => ErrorKindCode::ConnectionAborted,
+            io::ErrorKind::NotConnected => ErrorKindCode::NotConnected,
+            io::ErrorKind::AddrInUse => ErrorKindCode::AddrInUse,
+            io::ErrorKind::AddrNotAvailable => ErrorKindCode::AddrNotAvailable,
+            io::ErrorKind::BrokenPipe => ErrorKindCode::BrokenPipe,
+            io::ErrorKind::AlreadyExists => ErrorKindCode::AlreadyExists,
+            io::ErrorKind::WouldBlock => ErrorKindCode::WouldBlock,
+            io::ErrorKind::InvalidInput => ErrorKindCode::InvalidInput,
+            io::ErrorKind::InvalidData => ErrorKindCode::InvalidData,
+            io::ErrorKind::TimedOut => ErrorKindCode::TimedOut,
+            io::ErrorKind::WriteZero => ErrorKindCode::WriteZero,
+            io::ErrorKind::Interrupted => ErrorKindCode::Interrupted,
+            io::ErrorKind::Unsupported => ErrorKindCode::Unsupported,
+            io::ErrorKind::UnexpectedEof => ErrorKindCode::UnexpectedEof,
+            io::ErrorKind::OutOfMemory => ErrorKindCode::OutOfMemory,
+            _ => ErrorKindCode::Other,
+        }
+    }
+}
+
+impl From<ErrorKindCode> for io::ErrorKind {
+    fn from(code: ErrorKindCode) -> Self {
+        match code {
+            ErrorKindCode::NotFound => io::ErrorKind::NotFound,
+            ErrorKindCode::PermissionDenied => io::ErrorKind::PermissionDenied,
+            ErrorKindCode::ConnectionRefused => io::ErrorKind::ConnectionRefused,
+            ErrorKindCode::ConnectionReset => io::ErrorKind::ConnectionReset,
+            ErrorKindCode::ConnectionAborted => io::ErrorKind::ConnectionAborted,
+            ErrorKindCode::NotConnected => io::ErrorKind::NotConnected,
+            ErrorKindCode::AddrInUse => io::ErrorKind::AddrInUse,
+            ErrorKindCode::AddrNotAvailable => io::ErrorKind::AddrNotAvailable,
+            ErrorKindCode::BrokenPipe => io::ErrorKind::BrokenPipe,
+            ErrorKindCode::AlreadyExists => io::ErrorKind::AlreadyExists,
+            ErrorKindCode::WouldBlock => io::ErrorKind::WouldBlock,
+            ErrorKindCode::InvalidInput => io::ErrorKind::InvalidInput,
+            ErrorKindCode::InvalidData => io::ErrorKind::InvalidData,
+            ErrorKindCode::TimedOut => io::ErrorKind::TimedOut,
+            ErrorKindCode::WriteZero => io::ErrorKind::WriteZero,
+            ErrorKindCode::Interrupted => io::ErrorKind::Interrupted,
+            ErrorKindCode::Unsupported => io::ErrorKind::Unsupported,
+            ErrorKindCode::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+            ErrorKindCode::OutOfMemory => io::ErrorKind::OutOfMemory,
+            ErrorKindCode::Other => io::ErrorKind::Other,
         }
     }
 }
@@ -0,0 +1,179 @@
+//! Mutual authentication of the peer reached through a [RoomId]
+//!
+//! [RhizMessage::AcceptedRoom](crate::messages::RhizMessage::AcceptedRoom) names the [User]
+//! waiting in a room, but whoever shows up at the holepunched address could be anyone.
+//! Both peers send a [Signal::AuthChallenge] with a random nonce and answer the peer's
+//! challenge with a [Signal::AuthResponse], a signature over the nonce, the [RoomId] and
+//! their own certificate made with the key of their [User] certificate.
+//!
+//! Until the peer is verified only keepalives are admitted, so no [Signal::Profile]
+//! or [Signal::Message] of an impostor is ever accepted.
+
+use std::fmt;
+use std::io;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::connection::Connection;
+use crate::identity::Identity;
+use crate::messages::RoomId;
+use crate::{Signal, User};
+
+const CONTEXT: &[u8] = b"smoke peer auth v1";
+
+/// Result of [PeerAuth::receive]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received {
+    /// `Reply( ... ).0` - [Signal::AuthResponse] that has to be sent to the peer
+    Reply(Signal),
+    /// The peer proved that it holds the key of its [User] certificate
+    Verified,
+    /// `Signal( ... ).0` - [Signal] of the peer that may be processed
+    Signal(Signal),
+}
+
+/// Challenge-response authentication of a single peer connection
+pub struct PeerAuth<'a> {
+    identity: &'a Identity,
+    room: RoomId,
+    peer: User,
+    nonce: [u8; 32],
+    answered: bool,
+    verified: bool,
+}
+
+impl<'a> PeerAuth<'a> {
+    /// Starts authenticating `peer` in `room`, the returned [Signal::AuthChallenge] has to be sent first
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::Error] when the system random number generator failed
+    pub fn new(identity: &'a Identity, room: RoomId, peer: User) -> io::Result<(Self, Signal)> {
+        let mut nonce = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to generate nonce"))?;
+
+        let auth = PeerAuth {
+            identity,
+            room,
+            peer,
+            nonce,
+            answered: false,
+            verified: false,
+        };
+        Ok((auth, Signal::AuthChallenge(nonce)))
+    }
+
+    pub fn peer(&self) -> &User {
+        &self.peer
+    }
+
+    /// True once the peer answered our challenge correctly
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Processes a [Signal] of the peer
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::PermissionDenied] when the peer sent a [Signal] other than [Signal::Kap] before it was verified<br>
+    /// An [io::ErrorKind::PermissionDenied] when the signature was not made by the key of the peer's [User]<br>
+    /// An [io::ErrorKind::InvalidData] when the peer repeated a challenge or response<br>
+    /// An [io::Error] when signing the challenge failed
+    pub fn receive(&mut self, signal: Signal) -> io::Result<Received> {
+        match signal {
+            Signal::AuthChallenge(nonce) => {
+                if self.answered {
+                    return Err(repeated());
+                }
+                if nonce == self.nonce {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "peer reflected our challenge",
+                    ));
+                }
+                self.answered = true;
+
+                let message = self.signed_message(&nonce, self.identity.user());
+                let (scheme, signature) = self.identity.sign(&message)?;
+                Ok(Received::Reply(Signal::AuthResponse(scheme, signature)))
+            }
+            Signal::AuthResponse(scheme, signature) => {
+                if self.verified {
+                    return Err(repeated());
+                }
+                let message = self.signed_message(&self.nonce, &self.peer);
+                self.peer.verify(scheme, &message, &signature)?;
+                self.verified = true;
+                Ok(Received::Verified)
+            }
+            Signal::Kap => Ok(Received::Signal(signal)),
+            signal if self.verified => Ok(Received::Signal(signal)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "peer is not authenticated",
+            )),
+        }
+    }
+
+    fn signed_message(&self, nonce: &[u8; 32], signer: &User) -> Vec<u8> {
+        let mut message = Vec::with_capacity(CONTEXT.len() + 72 + signer.cert_data.len());
+        message.extend_from_slice(CONTEXT);
+        message.extend_from_slice(&self.room.0);
+        message.extend_from_slice(nonce);
+        message.extend_from_slice(&(signer.cert_data.len() as u64).to_be_bytes());
+        message.extend_from_slice(&signer.cert_data);
+        message
+    }
+}
+
+impl fmt::Debug for PeerAuth<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerAuth")
+            .field("room", &self.room)
+            .field("peer", &self.peer)
+            .field("answered", &self.answered)
+            .field("verified", &self.verified)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs [PeerAuth] on `connection` until `peer` is verified
+///
+/// Returns once the peer answered our challenge, our answer to its challenge has
+/// been sent by then because the peer always sends its challenge first.
+///
+/// # Cancel safety
+/// This method is not cancellation safe. The connection has to be dropped when it is cancelled.
+///
+/// # Errors
+/// This function will return:</br>
+/// Any error of [PeerAuth::receive]<br>
+/// The first error returned by `connection`
+pub async fn authenticate<S>(
+    connection: &mut Connection<S, Signal, Signal>,
+    identity: &Identity,
+    room: RoomId,
+    peer: User,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (mut auth, challenge) = PeerAuth::new(identity, room, peer)?;
+    connection.send(challenge).await?;
+
+    loop {
+        match auth.receive(connection.recv().await?)? {
+            Received::Reply(response) => connection.send(response).await?,
+            Received::Verified => return Ok(()),
+            Received::Signal(_) => {}
+        }
+    }
+}
+
+fn repeated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "peer repeated authentication")
+}
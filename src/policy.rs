@@ -0,0 +1,370 @@
+//! Access control for the connections of a vlink that was opened by the peer
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::bridge::DEFAULT_WINDOW;
+use crate::messages::vlink::{ErrorKindCode, Signal};
+
+/// Callback that decides about a single [Signal::Connect]
+///
+/// Called with the `vlinkid` of the tunnel and the `vport` that the peer wants to connect to,
+/// the returned future may e.g. wait for the user to answer a prompt. It runs in its own
+/// task, refer to [PolicyEnforcer::decided].
+pub type Approval =
+    Box<dyn Fn(String, u16) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+
+/// Restrictions for the connections of a vlink
+///
+/// The default policy allows everything.
+#[derive(Default)]
+pub struct TunnelPolicy {
+    /// vports the peer may connect to, `None` allows all vports
+    pub allowed_vports: Option<HashSet<u16>>,
+    /// Amount of connections that may be open at the same time
+    pub max_connections: Option<usize>,
+    /// Upper bound for the [Signal::Data] payload bytes per second of all connections,
+    /// enforced by delaying the window credit of the vports, refer to [PolicyEnforcer::throttle]
+    pub bandwidth: Option<u32>,
+    /// Asked for every [Signal::Connect] that passed all other checks
+    pub approval: Option<Approval>,
+}
+
+impl fmt::Debug for TunnelPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TunnelPolicy")
+            .field("allowed_vports", &self.allowed_vports)
+            .field("max_connections", &self.max_connections)
+            .field("bandwidth", &self.bandwidth)
+            .field("approval", &self.approval.is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Bandwidth {
+    rate: u32,
+    tokens: u64,
+    last_refill: Instant,
+}
+
+impl Bandwidth {
+    fn new(rate: u32) -> Self {
+        Bandwidth {
+            rate,
+            tokens: rate as u64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes `len` tokens, returns the time to wait if they have to be borrowed
+    ///
+    /// Borrowed tokens are repaid one after another, so the wait includes the debt
+    /// of earlier calls that is still outstanding.
+    fn take(&mut self, len: u64) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refilled = (elapsed.as_nanos() * self.rate as u128 / 1_000_000_000) as u64;
+        if refilled > 0 {
+            // the bucket holds at most one second of traffic
+            self.tokens = (self.tokens + refilled).min(self.rate as u64);
+            self.last_refill = now;
+        }
+
+        if self.tokens >= len {
+            self.tokens -= len;
+            return None;
+        }
+
+        let deficit = len - self.tokens;
+        self.tokens = 0;
+        // `last_refill` lies in the future while earlier debt is repaid
+        let repaid = self.last_refill.max(now)
+            + Duration::from_nanos(deficit * 1_000_000_000 / self.rate.max(1) as u64);
+        self.last_refill = repaid;
+        Some(repaid - now)
+    }
+}
+
+/// Bandwidth cap shared by all vports of a vlink
+///
+/// Passed to [RecvQueue::with_throttle](crate::bridge::RecvQueue::with_throttle), so
+/// [forward_inbound](crate::bridge::forward_inbound) holds back the [Signal::WindowUpdate]
+/// of a vport until the cap allows the credit. Only the sender of that vport waits, the
+/// link reader and all other [Signal]s are never delayed.
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    bandwidth: Arc<Mutex<Bandwidth>>,
+}
+
+impl Throttle {
+    /// Waits until `credit` bytes may be granted to the peer
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe, but the credit is accounted even if it is cancelled.
+    pub async fn delay(&self, credit: u32) {
+        let wait = self
+            .bandwidth
+            .lock()
+            .expect("poisoned bandwidth")
+            .take(credit as u64);
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Outcome of [PolicyEnforcer::admit] for a [Signal] that was not rejected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// The [Signal] is handed to the [vlink::TcpBridge]
+    Forward,
+    /// The [Signal] is not handed to the bridge now. Signals of a vport that waits for its
+    /// [Approval] are returned by [PolicyEnforcer::decided] once it was approved.
+    Held,
+}
+
+/// Connect of the peer whose [Approval] has not been answered yet
+#[derive(Debug)]
+struct Pending {
+    // identifies the answer of this approval if the vport is reused
+    id: u64,
+    signals: Vec<Signal>,
+    held_bytes: usize,
+    task: JoinHandle<()>,
+}
+
+/// Enforces a [TunnelPolicy] on the [Signal]s that the peer sends for one vlink
+///
+/// Every [Signal] of the peer is passed through [PolicyEnforcer::admit]
+/// before it is handed to the [vlink::TcpBridge]. The link reader also waits for
+/// [PolicyEnforcer::decided] to forward the connections that have been approved.
+#[derive(Debug)]
+pub struct PolicyEnforcer {
+    name: String,
+    policy: Arc<TunnelPolicy>,
+    active: HashSet<u16>,
+    pending: HashMap<u16, Pending>,
+    next_id: u64,
+    decisions_tx: mpsc::UnboundedSender<(u16, u64, bool)>,
+    decisions: mpsc::UnboundedReceiver<(u16, u64, bool)>,
+    throttle: Option<Throttle>,
+}
+
+impl PolicyEnforcer {
+    /// `name` is the `vlinkid` the vlink was opened with
+    pub fn new(name: String, policy: Arc<TunnelPolicy>) -> Self {
+        let throttle = policy.bandwidth.map(|rate| Throttle {
+            bandwidth: Arc::new(Mutex::new(Bandwidth::new(rate))),
+        });
+        let (decisions_tx, decisions) = mpsc::unbounded_channel();
+        PolicyEnforcer {
+            name,
+            policy,
+            active: HashSet::new(),
+            pending: HashMap::new(),
+            next_id: 0,
+            decisions_tx,
+            decisions,
+            throttle,
+        }
+    }
+
+    /// vports with an open connection
+    pub fn active(&self) -> &HashSet<u16> {
+        &self.active
+    }
+
+    /// The [Throttle] of the bandwidth cap, `None` if the policy has none
+    ///
+    /// Every vport of the vlink has to use it for its [RecvQueue](crate::bridge::RecvQueue).
+    pub fn throttle(&self) -> Option<Throttle> {
+        self.throttle.clone()
+    }
+
+    /// Checks `signal` against the policy
+    ///
+    /// Never waits. A [Signal::Connect] that needs an [Approval] is held back together with
+    /// the [Signal::Data] of its vport until [PolicyEnforcer::decided] returns the answer.
+    /// At most [DEFAULT_WINDOW] bytes of data are held per vport, the peer may not send more
+    /// before it got credit.
+    ///
+    /// # Panics
+    /// If an [Approval] has to be asked outside of a tokio runtime
+    ///
+    /// # Errors
+    /// The [Signal::Error] that has to be sent to the peer instead of handling `signal`.
+    /// Its kind is [io::ErrorKind::PermissionDenied] for rejected connects and
+    /// [io::ErrorKind::NotConnected] for data of connections that have not been admitted.
+    pub fn admit(&mut self, signal: &Signal) -> Result<Admission, Signal> {
+        match signal {
+            Signal::Connect(vport) => self.connect(*vport),
+            Signal::Data(vport, data) => {
+                if let Some(pending) = self.pending.get_mut(vport) {
+                    pending.held_bytes += data.len();
+                    if pending.held_bytes > DEFAULT_WINDOW as usize {
+                        self.cancel(*vport);
+                        return Err(deny(
+                            *vport,
+                            io::ErrorKind::InvalidData,
+                            "peer exceeded its send window",
+                        ));
+                    }
+                    pending.signals.push(signal.clone());
+                    return Ok(Admission::Held);
+                }
+                if !self.active.contains(vport) {
+                    return Err(deny(
+                        *vport,
+                        io::ErrorKind::NotConnected,
+                        "connection was not admitted",
+                    ));
+                }
+                Ok(Admission::Forward)
+            }
+            Signal::Error(vport, ..) => {
+                self.active.remove(vport);
+                // the bridge never saw the connect of a pending vport
+                if self.cancel(*vport) {
+                    return Ok(Admission::Held);
+                }
+                Ok(Admission::Forward)
+            }
+            _ => Ok(Admission::Forward),
+        }
+    }
+
+    /// Waits for the next answered [Approval]
+    ///
+    /// Returns the held [Signal]s of an approved vport, starting with its [Signal::Connect].
+    /// Waits forever while no approval is pending, so it is meant to be raced against
+    /// reading the link in a `tokio::select!`.
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe. No answer is lost when it is cancelled.
+    ///
+    /// # Errors
+    /// The [Signal::Error] of [io::ErrorKind::PermissionDenied] that has to be sent to the peer
+    /// when the connection was denied.
+    pub async fn decided(&mut self) -> Result<Vec<Signal>, Signal> {
+        loop {
+            let (vport, id, approved) = self
+                .decisions
+                .recv()
+                .await
+                .expect("the enforcer holds a sender");
+
+            // answers of cancelled approvals are ignored
+            if self.pending.get(&vport).map(|pending| pending.id) != Some(id) {
+                continue;
+            }
+            let pending = self.pending.remove(&vport).expect("checked above");
+
+            if !approved {
+                return Err(deny(
+                    vport,
+                    io::ErrorKind::PermissionDenied,
+                    "connection was denied",
+                ));
+            }
+            self.active.insert(vport);
+            return Ok(pending.signals);
+        }
+    }
+
+    /// Informs the enforcer that the connection of `vport` was closed locally
+    pub fn disconnect(&mut self, vport: u16) {
+        self.active.remove(&vport);
+        self.cancel(vport);
+    }
+
+    fn connect(&mut self, vport: u16) -> Result<Admission, Signal> {
+        if let Some(allowed) = &self.policy.allowed_vports {
+            if !allowed.contains(&vport) {
+                return Err(deny(
+                    vport,
+                    io::ErrorKind::PermissionDenied,
+                    "vport is not allowed",
+                ));
+            }
+        }
+
+        if self.pending.contains_key(&vport) {
+            return Err(deny(
+                vport,
+                io::ErrorKind::AlreadyExists,
+                "connection is waiting for approval",
+            ));
+        }
+
+        if let Some(max) = self.policy.max_connections {
+            let open = self.active.len() + self.pending.len();
+            if open >= max && !self.active.contains(&vport) {
+                return Err(deny(
+                    vport,
+                    io::ErrorKind::PermissionDenied,
+                    "too many connections",
+                ));
+            }
+        }
+
+        let Some(approval) = &self.policy.approval else {
+            self.active.insert(vport);
+            return Ok(Admission::Forward);
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let answer = approval(self.name.clone(), vport);
+        let decisions = self.decisions_tx.clone();
+        let task = tokio::spawn(async move {
+            let approved = answer.await;
+            let _ = decisions.send((vport, id, approved));
+        });
+
+        self.active.remove(&vport);
+        self.pending.insert(
+            vport,
+            Pending {
+                id,
+                signals: vec![Signal::Connect(vport)],
+                held_bytes: 0,
+                task,
+            },
+        );
+        Ok(Admission::Held)
+    }
+
+    /// Drops a pending approval of `vport`, returns true if there was one
+    fn cancel(&mut self, vport: u16) -> bool {
+        match self.pending.remove(&vport) {
+            Some(pending) => {
+                pending.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for PolicyEnforcer {
+    fn drop(&mut self) {
+        for pending in self.pending.values() {
+            pending.task.abort();
+        }
+    }
+}
+
+fn deny(vport: u16, kind: io::ErrorKind, reason: &str) -> Signal {
+    tracing::debug!("denied vport {}: {}", vport, reason);
+    Signal::Error(vport, ErrorKindCode::from(kind), reason.to_string())
+}
@@ -0,0 +1,257 @@
+//! Token bucket rate limiting for requests that Rhizome (server) receives from Emberry (client)
+
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, BufReader};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tokio_rustls::server::TlsStream as STlsStream;
+
+use crate::messages::{EmbMessage, ErrorCode, RhizMessage, ServerError};
+use crate::User;
+
+/// Amount of requests a single [User] may issue
+///
+/// A bucket holds at most `capacity` tokens and regains one token every `refill`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    capacity: u32,
+    refill: Duration,
+}
+
+impl Quota {
+    /// # Panics
+    /// If `refill` is zero
+    pub fn new(capacity: u32, refill: Duration) -> Self {
+        assert!(!refill.is_zero(), "Quota refill interval must not be zero");
+        Quota { capacity, refill }
+    }
+
+    /// Largest amount of tokens a bucket holds
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Time after which a bucket regains one token, never zero
+    pub fn refill(&self) -> Duration {
+        self.refill
+    }
+}
+
+/// Groups of [EmbMessage]s that are limited by the same [Quota]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RequestClass {
+    /// [EmbMessage::Room]
+    Room,
+    /// [EmbMessage::Heartbeat]
+    Heartbeat,
+    /// [EmbMessage::PublishDevice] and [EmbMessage::RevokeDevice], which are verified by signature
+    Device,
+}
+
+impl RequestClass {
+    /// Returns the [RequestClass] of `msg` or `None` if the message is not rate limited
+    pub fn of(msg: &EmbMessage) -> Option<RequestClass> {
+        match msg {
+            EmbMessage::Room(_) => Some(RequestClass::Room),
+            EmbMessage::Heartbeat => Some(RequestClass::Heartbeat),
+            EmbMessage::PublishDevice(_) | EmbMessage::RevokeDevice(_) => {
+                Some(RequestClass::Device)
+            }
+            EmbMessage::Accept(..)
+            | EmbMessage::Shutdown
+            | EmbMessage::Block(_)
+            | EmbMessage::Unblock(_)
+            | EmbMessage::DoNotDisturb(_)
+            | EmbMessage::Resume(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: u32,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn full(quota: &Quota, now: Instant) -> Self {
+        TokenBucket {
+            tokens: quota.capacity,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, quota: &Quota, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refilled = elapsed.as_nanos() / quota.refill.as_nanos();
+        if refilled == 0 {
+            return;
+        }
+
+        let missing = quota.capacity - self.tokens;
+        if refilled >= missing as u128 {
+            self.tokens = quota.capacity;
+            self.last_refill = now;
+        } else {
+            // keep the remainder so partial intervals are not lost
+            self.tokens += refilled as u32;
+            self.last_refill += quota.refill * refilled as u32;
+        }
+    }
+
+    /// Takes a token or returns the time until the next one becomes available
+    fn try_take(&mut self, quota: &Quota, now: Instant) -> Result<(), Duration> {
+        self.refill(quota, now);
+
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            return Ok(());
+        }
+
+        let since_refill = now.saturating_duration_since(self.last_refill);
+        Err(quota.refill.saturating_sub(since_refill))
+    }
+
+    fn is_full(&mut self, quota: &Quota, now: Instant) -> bool {
+        self.refill(quota, now);
+        self.tokens == quota.capacity
+    }
+}
+
+/// Per [User] and per [RequestClass] token bucket rate limiter
+///
+/// A Rhizome server shares one [RateLimiter] between all connections
+/// behind a [Mutex] and uses [RateLimiter::recv_req] in place of [EmbMessage::recv_req].
+#[derive(Debug)]
+pub struct RateLimiter {
+    room: Quota,
+    heartbeat: Quota,
+    device: Quota,
+    buckets: HashMap<(User, RequestClass), TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(room: Quota, heartbeat: Quota, device: Quota) -> Self {
+        RateLimiter {
+            room,
+            heartbeat,
+            device,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Returns the [Quota] that applies to `class`
+    pub fn quota(&self, class: RequestClass) -> &Quota {
+        match class {
+            RequestClass::Room => &self.room,
+            RequestClass::Heartbeat => &self.heartbeat,
+            RequestClass::Device => &self.device,
+        }
+    }
+
+    /// Accounts `msg` sent by `user` against its [Quota]
+    ///
+    /// # Errors
+    /// A [ServerError] with [ErrorCode::RateLimited] when `user` exceeded the quota for this kind of message.
+    /// The message of the error contains the time after which the request may be retried.
+    pub fn check(&mut self, user: &User, msg: &EmbMessage) -> Result<(), ServerError> {
+        let Some(class) = RequestClass::of(msg) else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let quota = *self.quota(class);
+        let bucket = self
+            .buckets
+            .entry((user.clone(), class))
+            .or_insert_with(|| TokenBucket::full(&quota, now));
+
+        bucket.try_take(&quota, now).map_err(|retry_after| {
+            ServerError::new(
+                ErrorCode::RateLimited,
+                format!(
+                    "{:?} requests exhausted, retry in {}ms",
+                    class,
+                    retry_after.as_millis()
+                ),
+            )
+        })
+    }
+
+    /// Removes all buckets that have been fully refilled
+    ///
+    /// Buckets are created lazily for every [User], servers should call this
+    /// periodically to keep the memory usage bounded.
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        let (room, heartbeat, device) = (self.room, self.heartbeat, self.device);
+        self.buckets.retain(|(_, class), bucket| {
+            let quota = match class {
+                RequestClass::Room => &room,
+                RequestClass::Heartbeat => &heartbeat,
+                RequestClass::Device => &device,
+            };
+            !bucket.is_full(quota, now)
+        });
+    }
+
+    /// Reads the next [EmbMessage] of `user` that is within its [Quota]
+    ///
+    /// Every message that exceeds the quota is answered with a [RhizMessage::ServerError]
+    /// of [ErrorCode::RateLimited] and dropped. `limiter` is only locked while a message
+    /// is accounted, never while reading or sending.
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [EmbMessage::recv_req] and [RhizMessage::send_with].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [EmbMessage::recv_req]<br>
+    /// The first error returned by sending the throttle response
+    pub async fn recv_req<T>(
+        limiter: &Mutex<RateLimiter>,
+        user: &User,
+        tls: &mut BufReader<STlsStream<T>>,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<EmbMessage>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        Self::recv_from(limiter, user, tls, buf).await
+    }
+
+    /// Same as [RateLimiter::recv_req] but for any kind of buffered stream
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [EmbMessage::recv_from] and [RhizMessage::send_to].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [EmbMessage::recv_from]<br>
+    /// The first error returned by sending the throttle response
+    pub async fn recv_from<S>(
+        limiter: &Mutex<RateLimiter>,
+        user: &User,
+        stream: &mut S,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<EmbMessage>
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin,
+    {
+        loop {
+            let msg = EmbMessage::recv_from(stream, buf).await?;
+
+            let checked = limiter.lock().await.check(user, &msg);
+            match checked {
+                Ok(()) => return Ok(msg),
+                Err(err) => {
+                    tracing::debug!("throttled request: {}", err);
+                    RhizMessage::from(err).send_to(stream).await?;
+                }
+            }
+        }
+    }
+}
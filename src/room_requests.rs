@@ -0,0 +1,171 @@
+//! Server side matching of [EmbMessage::Accept] to the [RhizMessage::WantsRoom] it answers
+//!
+//! [EmbMessage::Accept]: crate::messages::EmbMessage::Accept
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::messages::{ErrorCode, RequestId, RhizMessage, RoomId, ServerError};
+use crate::{User, ROOM_REQ_TIMEOUT};
+
+#[derive(Debug)]
+struct Pending {
+    from: User,
+    to: User,
+    deadline: Instant,
+}
+
+/// Room requests that were forwarded to their target but not answered yet
+///
+/// Every request gets its own [RequestId], so a [User] can have several incoming and
+/// outgoing requests in flight at once. Rhizome shares one [RoomRequests] between all
+/// connections, registers every [EmbMessage::Room] through
+/// [BlockList::forward_room](crate::block_list::BlockList::forward_room), which calls
+/// [RoomRequests::request] for the permitted ones, and calls [RoomRequests::answer]
+/// for every [EmbMessage::Accept].
+///
+/// [EmbMessage::Room]: crate::messages::EmbMessage::Room
+/// [EmbMessage::Accept]: crate::messages::EmbMessage::Accept
+#[derive(Debug)]
+pub struct RoomRequests {
+    timeout: Duration,
+    next_id: u64,
+    pending: HashMap<RequestId, Pending>,
+    by_pair: HashMap<(User, User), RequestId>,
+}
+
+impl Default for RoomRequests {
+    fn default() -> Self {
+        RoomRequests::new(ROOM_REQ_TIMEOUT)
+    }
+}
+
+impl RoomRequests {
+    /// Requests expire when they were not answered within `timeout`
+    pub fn new(timeout: Duration) -> Self {
+        RoomRequests {
+            timeout,
+            next_id: 0,
+            pending: HashMap::new(),
+            by_pair: HashMap::new(),
+        }
+    }
+
+    /// Registers a room request of `from` to `to` and returns the [RhizMessage::WantsRoom] for `to`
+    ///
+    /// A repeated request of the same pair, e.g. replayed after a reconnect,
+    /// keeps its [RequestId] and only extends the deadline.
+    pub fn request(&mut self, from: &User, to: &User) -> RhizMessage {
+        let deadline = Instant::now() + self.timeout;
+        let pair = (from.clone(), to.clone());
+
+        let id = match self.by_pair.get(&pair) {
+            Some(id) => *id,
+            None => {
+                let id = RequestId(self.next_id);
+                self.next_id += 1;
+                self.by_pair.insert(pair, id);
+                id
+            }
+        };
+        self.pending.insert(
+            id,
+            Pending {
+                from: from.clone(),
+                to: to.clone(),
+                deadline,
+            },
+        );
+
+        RhizMessage::WantsRoom(id, from.clone())
+    }
+
+    /// Removes the request `id` answered by `user` and returns the [User] that sent it
+    ///
+    /// # Errors
+    /// A [ServerError] with [ErrorCode::RoomExpired] when `id` is unknown, expired or was not sent to `user`
+    pub fn resolve(&mut self, user: &User, id: RequestId) -> Result<User, ServerError> {
+        self.take(user, id).map(|pending| pending.from)
+    }
+
+    /// Resolves the request `id` answered by `user` and returns the [User] that sent it
+    /// together with the [RhizMessage::AcceptedRoom] for that [User]
+    ///
+    /// `room` is `None` when `user` denied the request. The answer names the [User] of the
+    /// original [EmbMessage::Room], which the requester uses to match it to its request.
+    ///
+    /// # Errors
+    /// A [ServerError] with [ErrorCode::RoomExpired] when `id` is unknown, expired or was not sent to `user`
+    ///
+    /// [EmbMessage::Room]: crate::messages::EmbMessage::Room
+    pub fn answer(
+        &mut self,
+        user: &User,
+        id: RequestId,
+        room: Option<RoomId>,
+    ) -> Result<(User, RhizMessage), ServerError> {
+        let pending = self.take(user, id)?;
+        Ok((pending.from, RhizMessage::AcceptedRoom(room, pending.to)))
+    }
+
+    fn take(&mut self, user: &User, id: RequestId) -> Result<Pending, ServerError> {
+        let expired = || {
+            ServerError::new(
+                ErrorCode::RoomExpired,
+                format!("room request {} does not exist", id.0),
+            )
+        };
+
+        let pending = self.pending.get(&id).ok_or_else(expired)?;
+        if &pending.to != user {
+            return Err(expired());
+        }
+        let pending = self.remove(id).ok_or_else(expired)?;
+        if pending.deadline <= Instant::now() {
+            return Err(expired());
+        }
+        Ok(pending)
+    }
+
+    /// Pending requests that were sent to `user`
+    pub fn incoming<'a>(&'a self, user: &'a User) -> impl Iterator<Item = (RequestId, &'a User)> {
+        self.pending
+            .iter()
+            .filter(move |(_, pending)| &pending.to == user)
+            .map(|(id, pending)| (*id, &pending.from))
+    }
+
+    /// Amount of pending requests
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Drops all expired requests
+    ///
+    /// Rhizome should call this periodically to bound the memory usage.
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<RequestId> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.remove(id);
+        }
+    }
+
+    fn remove(&mut self, id: RequestId) -> Option<Pending> {
+        let pending = self.pending.remove(&id)?;
+        self.by_pair
+            .remove(&(pending.from.clone(), pending.to.clone()));
+        Some(pending)
+    }
+}
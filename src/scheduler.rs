@@ -0,0 +1,178 @@
+//! Prioritized sending of p2p [Signal]s
+//!
+//! All tasks of a peer connection send through clones of a [PrioritySender].
+//! The single writer task drains the [PriorityReceiver] with strict priority, so
+//! keepalives and chat messages overtake queued tunnel data instead of waiting
+//! behind it and tripping [KAP_TIMEOUT](crate::messages::signal::KAP_TIMEOUT) on the peer.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+
+use crate::messages::compression::CompressedWriter;
+use crate::messages::signal::MAX_SIGNAL_BUF_SIZE;
+use crate::messages::{vlink, Drain};
+use crate::Signal;
+
+/// Send priority of a [Signal], lower values are sent first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Keepalives, flow control credit and connection negotiation
+    Control,
+    /// Signals a user is waiting for, like chat messages
+    Interactive,
+    /// Tunnel payload together with all signals that open or close tunnels and vports
+    Bulk,
+}
+
+impl Priority {
+    pub fn of(signal: &Signal) -> Priority {
+        match signal {
+            Signal::Kap
+            | Signal::Vlink(_, vlink::Signal::WindowUpdate(..))
+            | Signal::Compression(..)
+            | Signal::AuthChallenge(..)
+            | Signal::AuthResponse(..) => Priority::Control,
+            Signal::Profile(..) | Signal::ChangeContext(..) | Signal::Message(..) => {
+                Priority::Interactive
+            }
+            // everything that belongs to a tunnel or vport shares the FIFO of its data,
+            // a VlinkCut or FlowClosed must never overtake the data it ends
+            Signal::VlinkOpen(..) | Signal::VlinkCut(..) | Signal::Vlink(..) => Priority::Bulk,
+            // only large signals are compressed
+            Signal::Compressed(..) => Priority::Bulk,
+        }
+    }
+}
+
+/// Creates a priority queue, every class buffers up to `capacity` [Signal]s
+pub fn priority_queue(capacity: usize) -> (PrioritySender, PriorityReceiver) {
+    let (control_tx, control_rx) = mpsc::channel(capacity);
+    let (interactive_tx, interactive_rx) = mpsc::channel(capacity);
+    let (bulk_tx, bulk_rx) = mpsc::channel(capacity);
+
+    let sender = PrioritySender {
+        control: control_tx,
+        interactive: interactive_tx,
+        bulk: bulk_tx,
+    };
+    let receiver = PriorityReceiver {
+        control: control_rx,
+        interactive: interactive_rx,
+        bulk: bulk_rx,
+    };
+    (sender, receiver)
+}
+
+/// Sending side of [priority_queue]
+#[derive(Clone, Debug)]
+pub struct PrioritySender {
+    control: mpsc::Sender<Signal>,
+    interactive: mpsc::Sender<Signal>,
+    bulk: mpsc::Sender<Signal>,
+}
+
+impl PrioritySender {
+    /// Queues `signal` with its [Priority::of]
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::BrokenPipe] when the [PriorityReceiver] was dropped
+    pub async fn send(&self, signal: Signal) -> io::Result<()> {
+        let priority = Priority::of(&signal);
+        self.send_with(priority, signal).await
+    }
+
+    /// Queues `signal` with an explicit `priority`
+    ///
+    /// Waits while the queue of `priority` is full, the other classes are not affected.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::BrokenPipe] when the [PriorityReceiver] was dropped
+    pub async fn send_with(&self, priority: Priority, signal: Signal) -> io::Result<()> {
+        let queue = match priority {
+            Priority::Control => &self.control,
+            Priority::Interactive => &self.interactive,
+            Priority::Bulk => &self.bulk,
+        };
+        queue
+            .send(signal)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "send queue was closed"))
+    }
+}
+
+/// Receiving side of [priority_queue]
+#[derive(Debug)]
+pub struct PriorityReceiver {
+    control: mpsc::Receiver<Signal>,
+    interactive: mpsc::Receiver<Signal>,
+    bulk: mpsc::Receiver<Signal>,
+}
+
+impl PriorityReceiver {
+    /// Returns the queued [Signal] with the highest priority
+    ///
+    /// Returns `None` once all [PrioritySender]s were dropped and every queue is empty.
+    ///
+    /// # Cancel safety
+    /// This method is cancellation safe.
+    pub async fn recv(&mut self) -> Option<Signal> {
+        // `biased` polls the queues in order of their priority, closed queues are skipped
+        tokio::select! {
+            biased;
+            Some(signal) = self.control.recv() => Some(signal),
+            Some(signal) = self.interactive.recv() => Some(signal),
+            Some(signal) = self.bulk.recv() => Some(signal),
+            else => None,
+        }
+    }
+
+    /// Serializes ([postcard]) every queued [Signal] to `writer` until all [PrioritySender]s were dropped
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. A [Signal] may have been partially written.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// An [io::ErrorKind::InvalidInput] when a [Signal] does not fit [MAX_SIGNAL_BUF_SIZE]<br>
+    /// The first error returned by `writer`
+    pub async fn forward_to<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut ser_buf = [0u8; MAX_SIGNAL_BUF_SIZE];
+        while let Some(signal) = self.recv().await {
+            signal
+                .serialize_to(writer, &mut ser_buf)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends every queued [Signal] with `writer` until all [PrioritySender]s were dropped
+    ///
+    /// Signals are compressed with the algorithm the peer announced, refer to [CompressedWriter::send].
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. A [Signal] may have been partially written.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The first error returned by [CompressedWriter::send]
+    pub async fn forward_compressed<S>(
+        &mut self,
+        writer: &mut CompressedWriter<S>,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite,
+    {
+        while let Some(signal) = self.recv().await {
+            writer.send(signal).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,454 @@
+//! Resumable sessions for the connection between Emberry (client) and Rhizome (server)
+//!
+//! Rhizome greets every new connection with a [RhizMessage::Session].
+//! A client that lost its connection reconnects and sends [EmbMessage::Resume]
+//! with the token of its previous session. Rhizome answers with
+//! [RhizMessage::Session] containing the resumed token or with a
+//! [RhizMessage::ServerError] of [ErrorCode::SessionExpired], in which case
+//! the fresh session of the new connection is used.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, BufReader};
+use tokio::time::Instant;
+
+use crate::messages::{
+    EmbMessage, ErrorCode, RhizMessage, SequenceCheck, Sequencer, ServerError, SessionToken,
+};
+use crate::User;
+
+/// Exponential backoff between reconnection attempts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay after the first failed attempt
+    pub initial: Duration,
+    /// Upper bound for the delay between two attempts
+    pub max: Duration,
+    /// Amount of attempts after which reconnecting is given up. `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    /// Returns the delay after the failed attempt number `attempt` (starting at 0)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(1 << attempt.min(31))
+            .min(self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(250),
+            max: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    user: User,
+    /// `None` while the session is attached to a connection
+    expires: Option<Instant>,
+}
+
+/// Rhizome side bookkeeping of all [SessionToken]s
+#[derive(Debug)]
+pub struct SessionStore {
+    ttl: Duration,
+    rng: SystemRandom,
+    sessions: HashMap<SessionToken, Session>,
+}
+
+impl SessionStore {
+    /// `ttl` is the time a session can be resumed after its connection was lost
+    pub fn new(ttl: Duration) -> Self {
+        SessionStore {
+            ttl,
+            rng: SystemRandom::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Creates a new session for `user` that is attached to the current connection
+    ///
+    /// The returned token is sent to the client as [RhizMessage::Session].
+    ///
+    /// # Errors
+    /// An [io::Error] when the system random number generator failed
+    pub fn issue(&mut self, user: User) -> io::Result<SessionToken> {
+        let mut token = [0u8; 32];
+        self.rng.fill(&mut token).map_err(|_| {
+            io::Error::new(io::ErrorKind::Other, "unable to generate session token")
+        })?;
+        let token = SessionToken(token);
+
+        self.sessions.insert(
+            token.clone(),
+            Session {
+                user,
+                expires: None,
+            },
+        );
+        Ok(token)
+    }
+
+    /// Handles [EmbMessage::Resume] of `user` on the connection of session `current`
+    ///
+    /// On success `current` is discarded and `resumed` is attached to the connection.
+    ///
+    /// # Errors
+    /// A [ServerError] with [ErrorCode::SessionExpired] when `resumed` does not exist,
+    /// expired, is still attached to a connection or belongs to a different [User]<br>
+    /// A [ServerError] with [ErrorCode::ProtocolViolation] when `current` belongs to a different [User]
+    pub fn resume(
+        &mut self,
+        current: &SessionToken,
+        resumed: SessionToken,
+        user: &User,
+    ) -> Result<SessionToken, ServerError> {
+        // only the owner may give up its current session
+        if self
+            .sessions
+            .get(current)
+            .is_some_and(|session| &session.user != user)
+        {
+            return Err(ServerError::new(
+                ErrorCode::ProtocolViolation,
+                "session belongs to a different user",
+            ));
+        }
+
+        let now = Instant::now();
+        match self.sessions.get_mut(&resumed) {
+            Some(session)
+                if &session.user == user
+                    && session.expires.is_some_and(|expires| expires > now) =>
+            {
+                session.expires = None;
+            }
+            _ => {
+                return Err(ServerError::new(
+                    ErrorCode::SessionExpired,
+                    "unable to resume session",
+                ))
+            }
+        }
+
+        if current != &resumed {
+            self.sessions.remove(current);
+        }
+        Ok(resumed)
+    }
+
+    /// Detaches `token` from its connection, Rhizome calls this once the connection was lost
+    ///
+    /// The session can be resumed until its ttl expired.
+    pub fn suspend(&mut self, token: &SessionToken) {
+        if let Some(session) = self.sessions.get_mut(token) {
+            session.expires = Some(Instant::now() + self.ttl);
+        }
+    }
+
+    /// Removes `token` so it can never be resumed, used when the client sent [EmbMessage::Shutdown]
+    pub fn end(&mut self, token: &SessionToken) {
+        self.sessions.remove(token);
+    }
+
+    /// Removes all expired sessions
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        self.sessions.retain(|_, session| match session.expires {
+            Some(expires) => expires > now,
+            None => true,
+        });
+    }
+}
+
+/// Client side connection to Rhizome that transparently reconnects and resumes its session
+///
+/// `connect` is called for every (re)connection attempt and yields the raw stream
+/// (usually a [tokio_rustls::client::TlsStream]).
+///
+/// [EmbMessage::Room] requests are considered in flight until Rhizome answered them
+/// with [RhizMessage::NoRoute] or [RhizMessage::AcceptedRoom] and are sent again after a reconnect.
+/// Both answers name the [User] of the request they answer, which is the key of a request in flight.
+///
+/// A link created with [ResumableLink::connect_sequenced] uses sequenced framing
+/// ([Sequenced](crate::messages::Sequenced)) for all messages. The sequence numbers restart
+/// on every connection, the greeting of Rhizome included.
+pub struct ResumableLink<C, S> {
+    connect: C,
+    backoff: Backoff,
+    stream: Option<BufReader<S>>,
+    token: Option<SessionToken>,
+    in_flight: Vec<User>,
+    buf: Vec<u8>,
+    // sequence state of the current connection, `None` for plain framing
+    sequence: Option<(Sequencer, SequenceCheck)>,
+}
+
+impl<C, F, S> ResumableLink<C, S>
+where
+    C: FnMut() -> F,
+    F: Future<Output = io::Result<S>>,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Establishes the first connection, retrying according to `backoff`
+    ///
+    /// # Errors
+    /// The last error of the connection attempts once [Backoff::max_attempts] was reached
+    pub async fn connect(connect: C, backoff: Backoff) -> io::Result<Self> {
+        Self::establish(connect, backoff, None).await
+    }
+
+    /// Same as [ResumableLink::connect] but for a Rhizome that uses sequenced framing
+    ///
+    /// # Errors
+    /// The last error of the connection attempts once [Backoff::max_attempts] was reached
+    pub async fn connect_sequenced(connect: C, backoff: Backoff) -> io::Result<Self> {
+        Self::establish(connect, backoff, Some(Default::default())).await
+    }
+
+    async fn establish(
+        connect: C,
+        backoff: Backoff,
+        sequence: Option<(Sequencer, SequenceCheck)>,
+    ) -> io::Result<Self> {
+        let mut link = ResumableLink {
+            connect,
+            backoff,
+            stream: None,
+            token: None,
+            in_flight: Vec::new(),
+            buf: Vec::new(),
+            sequence,
+        };
+        link.reconnect().await?;
+        Ok(link)
+    }
+
+    /// Token of the current session
+    pub fn token(&self) -> Option<&SessionToken> {
+        self.token.as_ref()
+    }
+
+    /// [User]s with a pending [EmbMessage::Room] request, in the order they were requested
+    pub fn in_flight(&self) -> &[User] {
+        &self.in_flight
+    }
+
+    /// Sends `msg` to Rhizome, reconnecting if the connection was lost
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. If it is cancelled the connection
+    /// may be left in an inconsistent state.
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The error of the last reconnection attempt<br>
+    /// Any error returned by writing to the stream that does not indicate a lost connection
+    pub async fn send(&mut self, msg: EmbMessage) -> io::Result<()> {
+        let replayed = match &msg {
+            EmbMessage::Room(user) => {
+                if !self.in_flight.contains(user) {
+                    self.in_flight.push(user.clone());
+                }
+                true
+            }
+            _ => false,
+        };
+
+        loop {
+            let Some(stream) = self.stream.as_mut() else {
+                self.reconnect().await?;
+                if replayed {
+                    // reconnect already sent the message
+                    return Ok(());
+                }
+                continue;
+            };
+
+            match send(msg.clone(), stream, &mut self.sequence).await {
+                Ok(()) => return Ok(()),
+                Err(err) if is_connection_lost(&err) => {
+                    tracing::debug!("lost connection to rhizome while sending: {}", err);
+                    self.reconnect().await?;
+                    if replayed {
+                        // reconnect already sent the message again
+                        return Ok(());
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Receives the next [RhizMessage], reconnecting if the connection was lost
+    ///
+    /// [RhizMessage::Session] is handled internally and never returned.
+    ///
+    /// # Cancel safety
+    /// This method is not cancellation safe. Refer to [RhizMessage::recv_from].
+    ///
+    /// # Errors
+    /// This function will return:</br>
+    /// The error of the last reconnection attempt<br>
+    /// Any error returned by reading from the stream that does not indicate a lost connection
+    pub async fn recv(&mut self) -> io::Result<RhizMessage> {
+        loop {
+            let Some(stream) = self.stream.as_mut() else {
+                self.reconnect().await?;
+                continue;
+            };
+
+            match recv(stream, &mut self.buf, &mut self.sequence).await {
+                // an empty buffer means EOF, an explicit shutdown is returned below
+                Ok(RhizMessage::Shutdown()) if self.buf.is_empty() => {
+                    tracing::debug!("lost connection to rhizome: EOF");
+                    self.reconnect().await?;
+                }
+                Ok(RhizMessage::Session(token)) => self.token = Some(token),
+                Ok(msg) => {
+                    // the answer names the requested user, that is the key of the request
+                    if let RhizMessage::NoRoute(requested)
+                    | RhizMessage::AcceptedRoom(_, requested) = &msg
+                    {
+                        self.in_flight.retain(|pending| pending != requested);
+                    }
+                    return Ok(msg);
+                }
+                Err(err) if is_connection_lost(&err) => {
+                    tracing::debug!("lost connection to rhizome: {}", err);
+                    self.reconnect().await?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn reconnect(&mut self) -> io::Result<()> {
+        self.stream = None;
+
+        let mut attempt = 0;
+        loop {
+            match self.try_resume().await {
+                Ok(stream) => {
+                    self.stream = Some(stream);
+                    return Ok(());
+                }
+                Err(err) => {
+                    attempt += 1;
+                    if self.backoff.max_attempts.is_some_and(|max| attempt >= max) {
+                        return Err(err);
+                    }
+
+                    let delay = self.backoff.delay(attempt - 1);
+                    tracing::debug!(
+                        "reconnect attempt {} failed: {}, retry in {:?}",
+                        attempt,
+                        err,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    async fn try_resume(&mut self) -> io::Result<BufReader<S>> {
+        let mut stream = BufReader::new((self.connect)().await?);
+        // sequence numbers restart on every connection
+        if let Some(sequence) = &mut self.sequence {
+            *sequence = Default::default();
+        }
+
+        // Rhizome greets every connection with a fresh session
+        let fresh = match recv(&mut stream, &mut self.buf, &mut self.sequence).await? {
+            RhizMessage::Session(token) => token,
+            msg => return Err(unexpected(msg)),
+        };
+
+        let token = match &self.token {
+            Some(previous) => {
+                let resume = EmbMessage::Resume(previous.clone());
+                send(resume, &mut stream, &mut self.sequence).await?;
+
+                match recv(&mut stream, &mut self.buf, &mut self.sequence).await? {
+                    RhizMessage::Session(token) => token,
+                    RhizMessage::ServerError(ErrorCode::SessionExpired, _) => fresh,
+                    RhizMessage::ServerError(code, message) => {
+                        return Err(ServerError { code, message }.into())
+                    }
+                    msg => return Err(unexpected(msg)),
+                }
+            }
+            None => fresh,
+        };
+
+        for user in &self.in_flight {
+            send(
+                EmbMessage::Room(user.clone()),
+                &mut stream,
+                &mut self.sequence,
+            )
+            .await?;
+        }
+
+        self.token = Some(token);
+        Ok(stream)
+    }
+}
+
+async fn send<S>(
+    msg: EmbMessage,
+    stream: &mut S,
+    sequence: &mut Option<(Sequencer, SequenceCheck)>,
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    match sequence {
+        Some((sequencer, _)) => msg.send_sequenced(stream, sequencer).await,
+        None => msg.send_to(stream).await,
+    }
+}
+
+async fn recv<S>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+    sequence: &mut Option<(Sequencer, SequenceCheck)>,
+) -> io::Result<RhizMessage>
+where
+    S: AsyncBufRead + Unpin,
+{
+    match sequence {
+        Some((_, check)) => RhizMessage::recv_sequenced(stream, buf, check).await,
+        None => RhizMessage::recv_from(stream, buf).await,
+    }
+}
+
+fn is_connection_lost(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::TimedOut
+    )
+}
+
+fn unexpected(msg: RhizMessage) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("expected RhizMessage::Session, got {:?}", msg),
+    )
+}
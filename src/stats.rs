@@ -0,0 +1,217 @@
+//! Traffic statistics for vlink tunnels
+//!
+//! The payload bytes are accounted by the [crate::bridge] driver once its
+//! [SendWindow](crate::bridge::SendWindow) and [RecvGate](crate::bridge::RecvGate) carry
+//! the [TrafficStats] of the link, it also records the [Signal::Error] of a vport whose
+//! local socket failed. The [crate::datagram] drivers account and record every
+//! [Signal::Datagram] and [Signal::FlowClosed] they pass. Connects and errors of the
+//! peer are recorded by the link reader with [TrafficStats::record].
+//!
+//! A vport is live from its first traffic until it is closed, by a [Signal::Error] for a
+//! TCP vport or by the [Signal::FlowClosed] of its last flow for a UDP vport. All vports
+//! are closed by [TrafficStats::cut] when the vlink is cut. Closed vports are removed
+//! from the live vports, their traffic stays in the totals, which are never reset.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::messages::vlink::Signal;
+
+/// Traffic counters of a single vport or a whole vlink
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// [Signal::Data] and [Signal::Datagram] payload bytes received from the peer
+    pub bytes_in: u64,
+    /// [Signal::Data] and [Signal::Datagram] payload bytes sent to the peer
+    pub bytes_out: u64,
+    /// [Signal::Connect]s in both directions
+    pub connects: u64,
+    /// [Signal::Error]s and [Signal::AcceptError]s in both directions
+    pub errors: u64,
+}
+
+/// Statistics of a live vport at the time of the [StatsSnapshot]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VportStats {
+    /// Traffic since the vport was opened
+    pub counters: Counters,
+    /// Time between the opening of the vport and the snapshot
+    pub open_for: Duration,
+}
+
+/// Point in time copy of [TrafficStats]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// Counters of all vports combined
+    pub link: Counters,
+    /// Time since the [TrafficStats] were created
+    pub link_open_for: Duration,
+    /// vports that are currently open
+    pub vports: HashMap<u16, VportStats>,
+    /// Counters of every vport that was ever used, including the closed ones
+    pub vport_totals: HashMap<u16, Counters>,
+}
+
+#[derive(Debug)]
+struct Vport {
+    counters: Counters,
+    opened: Instant,
+    // open flows of a UDP vport
+    flows: HashSet<u32>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    link: Counters,
+    totals: HashMap<u16, Counters>,
+    live: HashMap<u16, Vport>,
+}
+
+/// Live traffic counters of one vlink, shared between the tasks driving it
+#[derive(Debug)]
+pub struct TrafficStats {
+    opened: Instant,
+    inner: Mutex<Inner>,
+}
+
+impl TrafficStats {
+    pub fn new() -> Self {
+        TrafficStats {
+            opened: Instant::now(),
+            inner: Mutex::new(Inner {
+                link: Counters::default(),
+                totals: HashMap::new(),
+                live: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Accounts `bytes` of [Signal::Data] or [Signal::Datagram] payload sent on `vport`
+    pub fn add_bytes_out(&self, vport: u16, bytes: u64) {
+        self.lock()
+            .update(vport, |counters| counters.bytes_out += bytes);
+    }
+
+    /// Accounts `bytes` of [Signal::Data] or [Signal::Datagram] payload received on `vport`
+    pub fn add_bytes_in(&self, vport: u16, bytes: u64) {
+        self.lock()
+            .update(vport, |counters| counters.bytes_in += bytes);
+    }
+
+    /// Accounts `bytes` of [Signal::Datagram] payload of `flow` sent on `vport`
+    ///
+    /// The flow stays open until a [Signal::FlowClosed] is recorded.
+    pub fn add_datagram_out(&self, vport: u16, flow: u32, bytes: u64) {
+        let mut inner = self.lock();
+        inner
+            .update(vport, |counters| counters.bytes_out += bytes)
+            .flows
+            .insert(flow);
+    }
+
+    /// Accounts `bytes` of [Signal::Datagram] payload of `flow` received on `vport`
+    ///
+    /// The flow stays open until a [Signal::FlowClosed] is recorded.
+    pub fn add_datagram_in(&self, vport: u16, flow: u32, bytes: u64) {
+        let mut inner = self.lock();
+        inner
+            .update(vport, |counters| counters.bytes_in += bytes)
+            .flows
+            .insert(flow);
+    }
+
+    /// Returns a copy of all counters
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let now = Instant::now();
+        let inner = self.lock();
+
+        let vports = inner
+            .live
+            .iter()
+            .map(|(vport, stats)| {
+                let stats = VportStats {
+                    counters: stats.counters,
+                    open_for: now.saturating_duration_since(stats.opened),
+                };
+                (*vport, stats)
+            })
+            .collect();
+
+        StatsSnapshot {
+            link: inner.link,
+            link_open_for: now.saturating_duration_since(self.opened),
+            vports,
+            vport_totals: inner.totals.clone(),
+        }
+    }
+
+    /// Accounts a [Signal] that was sent to or received from the peer
+    ///
+    /// [Signal::Data] and [Signal::Datagram] are ignored, their payload is accounted by
+    /// [TrafficStats::add_bytes_in], [TrafficStats::add_bytes_out] and the datagram
+    /// counterparts. [Signal::Error] closes its vport, [Signal::FlowClosed] closes the
+    /// vport together with its last flow.
+    pub fn record(&self, signal: &Signal) {
+        let mut inner = self.lock();
+        match signal {
+            Signal::Connect(vport) => {
+                inner.update(*vport, |counters| counters.connects += 1);
+            }
+            Signal::Error(vport, ..) => {
+                inner.update(*vport, |counters| counters.errors += 1);
+                inner.live.remove(vport);
+            }
+            Signal::AcceptError(..) => {
+                inner.link.errors += 1;
+            }
+            Signal::FlowClosed(vport, flow) => {
+                if let Some(stats) = inner.live.get_mut(vport) {
+                    stats.flows.remove(flow);
+                    if stats.flows.is_empty() {
+                        inner.live.remove(vport);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Accounts the `VlinkCut` of the vlink, all vports are closed
+    pub fn cut(&self) {
+        self.lock().live.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("poisoned traffic stats")
+    }
+}
+
+impl Default for TrafficStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inner {
+    /// Applies `update` to the link, the totals and the live counters of `vport`, opening it if needed
+    fn update(&mut self, vport: u16, update: impl Fn(&mut Counters)) -> &mut Vport {
+        update(&mut self.link);
+        update(self.totals.entry(vport).or_default());
+        let live = self.live.entry(vport).or_insert_with(Vport::new);
+        update(&mut live.counters);
+        live
+    }
+}
+
+impl Vport {
+    fn new() -> Self {
+        Vport {
+            counters: Counters::default(),
+            opened: Instant::now(),
+            flows: HashSet::new(),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Bookkeeping of multiple vlinks multiplexed over a single peer connection
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::messages::signal::TunnelId;
+use crate::Signal;
+
+/// A single vlink of a peer connection
+#[derive(Debug)]
+pub struct Tunnel<B> {
+    /// `vlinkid` the vlink was opened with
+    pub name: String,
+    /// true if the peer opened this vlink
+    pub remote: bool,
+    /// Local end of the vlink (usually a [vlink::TcpBridge])
+    pub bridge: B,
+}
+
+/// Side of the peer connection, decides which [TunnelId]s a peer may pick
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// The peer that sent the [EmbMessage::Room](crate::messages::EmbMessage::Room) request, opens even ids
+    Initiator,
+    /// The peer that accepted the request, opens odd ids
+    Responder,
+}
+
+impl Role {
+    fn owns(self, id: TunnelId) -> bool {
+        match self {
+            Role::Initiator => id & 1 == 0,
+            Role::Responder => id & 1 == 1,
+        }
+    }
+}
+
+/// Routes [Signal::Vlink], [Signal::VlinkOpen] and [Signal::VlinkCut] to the vlink of their [TunnelId]
+///
+/// Ids are chosen by the side that opens a vlink from the half of the id space that
+/// belongs to its [Role], so both peers can open vlinks at the same time without
+/// colliding. [Tunnels::open] never picks an id that is in use, ids sent by the peer
+/// that are in use or belong to our [Role] are rejected.
+#[derive(Debug)]
+pub struct Tunnels<B> {
+    role: Role,
+    tunnels: HashMap<TunnelId, Tunnel<B>>,
+    next_id: TunnelId,
+}
+
+impl<B> Tunnels<B> {
+    pub fn new(role: Role) -> Self {
+        let next_id = match role {
+            Role::Initiator => 0,
+            Role::Responder => 1,
+        };
+        Tunnels {
+            role,
+            tunnels: HashMap::new(),
+            next_id,
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Registers a locally opened vlink and returns the [Signal::VlinkOpen] that announces it to the peer
+    ///
+    /// # Errors
+    /// An [io::Error] if all [TunnelId]s of our [Role] are in use
+    pub fn open(&mut self, name: String, bridge: B) -> io::Result<Signal> {
+        let local = self
+            .tunnels
+            .values()
+            .filter(|tunnel| !tunnel.remote)
+            .count();
+        if local > (TunnelId::MAX / 2) as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "no free vlink id left",
+            ));
+        }
+
+        // stepping by 2 keeps the parity of our role
+        while self.tunnels.contains_key(&self.next_id) {
+            self.next_id = self.next_id.wrapping_add(2);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(2);
+
+        self.tunnels.insert(
+            id,
+            Tunnel {
+                name: name.clone(),
+                remote: false,
+                bridge,
+            },
+        );
+        Ok(Signal::VlinkOpen(id, name))
+    }
+
+    /// Registers a vlink that was opened by the peer with [Signal::VlinkOpen]
+    ///
+    /// # Errors
+    /// An [io::ErrorKind::InvalidData] if `id` belongs to our [Role]<br>
+    /// An [io::ErrorKind::AlreadyExists] if `id` is already in use
+    pub fn accept(&mut self, id: TunnelId, name: String, bridge: B) -> io::Result<()> {
+        if self.role.owns(id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("vlink id {} is reserved for our role", id),
+            ));
+        }
+        if self.tunnels.contains_key(&id) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("vlink id {} is already in use", id),
+            ));
+        }
+
+        self.tunnels.insert(
+            id,
+            Tunnel {
+                name,
+                remote: true,
+                bridge,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes the vlink of `id`, used for [Signal::VlinkCut] in both directions
+    pub fn cut(&mut self, id: TunnelId) -> Option<Tunnel<B>> {
+        self.tunnels.remove(&id)
+    }
+
+    /// Returns the vlink that a [Signal::Vlink] with `id` is adressed to
+    pub fn get_mut(&mut self, id: TunnelId) -> Option<&mut Tunnel<B>> {
+        self.tunnels.get_mut(&id)
+    }
+
+    pub fn get(&self, id: TunnelId) -> Option<&Tunnel<B>> {
+        self.tunnels.get(&id)
+    }
+
+    /// Iterates over all open vlinks
+    pub fn iter(&self) -> impl Iterator<Item = (TunnelId, &Tunnel<B>)> {
+        self.tunnels.iter().map(|(id, tunnel)| (*id, tunnel))
+    }
+
+    pub fn len(&self) -> usize {
+        self.tunnels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tunnels.is_empty()
+    }
+}
@@ -0,0 +1,85 @@
+use std::io;
+
+use smoke::messages::vlink::{Signal, SignalRef};
+use smoke::messages::{Drain, MessageStream, Source};
+
+use tokio::io::BufReader;
+use tokio_test::io::Builder;
+
+async fn serialize(quantity: u16) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut ser_buf = [0u8; 256];
+    for vport in 0..quantity {
+        SignalRef::Data(vport, &[vport as u8; 100])
+            .serialize_to(&mut bytes, &mut ser_buf)
+            .unwrap()
+            .await
+            .unwrap();
+    }
+    bytes
+}
+
+fn expected(vport: u16) -> Signal {
+    Signal::Data(vport, vec![vport as u8; 100])
+}
+
+#[tokio::test]
+async fn drains_the_buffer() {
+    let bytes = serialize(20).await;
+    let mut reader = BufReader::new(&bytes[..]);
+
+    let mut messages = Vec::new();
+    let count = reader
+        .read_messages_into::<Signal>(&mut messages)
+        .await
+        .unwrap();
+    assert_eq!(count, 20);
+    assert_eq!(messages, (0..20).map(expected).collect::<Vec<_>>());
+
+    let err = reader
+        .read_messages_into::<Signal>(&mut messages)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+}
+
+#[tokio::test]
+async fn partial_messages_are_kept() {
+    let bytes = serialize(5).await;
+    let size = bytes.len() / 5;
+    // 2.5 messages, then the rest
+    let stream = Builder::new()
+        .read(&bytes[..size * 2 + size / 2])
+        .read(&bytes[size * 2 + size / 2..])
+        .build();
+    let mut reader = BufReader::new(stream);
+
+    let mut messages = Vec::new();
+    let mut counts = Vec::new();
+    while messages.len() < 5 {
+        counts.push(
+            reader
+                .read_messages_into::<Signal>(&mut messages)
+                .await
+                .unwrap(),
+        );
+    }
+    // the message spanning both reads is completed on its own
+    assert_eq!(counts, [2, 1, 2]);
+    assert_eq!(messages, (0..5).map(expected).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn stream_returns_messages_before_error() {
+    let mut bytes = serialize(3).await;
+    // invalid variant index
+    bytes.push(0x7f);
+    let mut stream = MessageStream::<_, Signal>::new(BufReader::new(&bytes[..]));
+
+    for vport in 0..3 {
+        assert_eq!(stream.next().await.unwrap(), expected(vport));
+        assert_eq!(stream.pending(), 2 - vport as usize);
+    }
+    let err = stream.next().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+}
@@ -0,0 +1,137 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use smoke::messages::{BatchWriter, EmbMessage, FlushPolicy, RequestId, Source, MAX_BATCH_SIZE};
+use smoke::User;
+
+use tokio::io::{AsyncWrite, BufReader};
+use tokio::time::{Duration, Instant};
+
+/// Records every single write
+#[derive(Default)]
+struct Recorder {
+    writes: Vec<Vec<u8>>,
+}
+
+impl Recorder {
+    async fn decode(&self) -> Vec<EmbMessage> {
+        let bytes = self.writes.concat();
+        let mut reader = BufReader::new(&bytes[..]);
+        let mut messages = Vec::new();
+        while reader.read_messages_into(&mut messages).await.is_ok() {}
+        messages
+    }
+}
+
+impl AsyncWrite for Recorder {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writes.push(buf.to_vec());
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn messages() -> Vec<EmbMessage> {
+    vec![
+        EmbMessage::Heartbeat,
+        EmbMessage::Room(User {
+            cert_data: b"Aurelia".to_vec(),
+        }),
+        EmbMessage::Accept(RequestId(0), true),
+    ]
+}
+
+#[tokio::test]
+async fn immediate_writes_every_message() {
+    let mut writer = BatchWriter::new(Recorder::default(), FlushPolicy::Immediate);
+    for message in messages() {
+        writer.send(&message).await.unwrap();
+    }
+    assert_eq!(writer.buffered(), 0);
+    assert_eq!(writer.get_ref().writes.len(), 3);
+    assert_eq!(writer.get_ref().decode().await, messages());
+}
+
+#[tokio::test]
+async fn size_threshold_batches() {
+    let mut writer = BatchWriter::new(Recorder::default(), FlushPolicy::Size(1024));
+    for message in messages() {
+        writer.send(&message).await.unwrap();
+    }
+    assert!(writer.get_ref().writes.is_empty());
+    assert!(writer.buffered() > 0);
+
+    writer.flush().await.unwrap();
+    assert_eq!(writer.get_ref().writes.len(), 1);
+    assert_eq!(writer.get_ref().decode().await, messages());
+
+    // reaching the threshold flushes on its own
+    writer.set_policy(FlushPolicy::Size(6));
+    writer.send(&EmbMessage::Heartbeat).await.unwrap();
+    writer
+        .send(&EmbMessage::Accept(RequestId(0), false))
+        .await
+        .unwrap();
+    writer
+        .send(&EmbMessage::Accept(RequestId(0), true))
+        .await
+        .unwrap();
+    assert_eq!(writer.get_ref().writes.len(), 2);
+    assert_eq!(writer.buffered(), 0);
+}
+
+#[tokio::test]
+async fn batches_are_capped() {
+    let mut writer = BatchWriter::new(Recorder::default(), FlushPolicy::Size(usize::MAX));
+    let large = EmbMessage::Room(User {
+        cert_data: vec![1; MAX_BATCH_SIZE / 2],
+    });
+    writer.send(&large).await.unwrap();
+    assert!(writer.get_ref().writes.is_empty());
+    writer.send(&large).await.unwrap();
+    assert_eq!(writer.get_ref().writes.len(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn delay_flushes_at_deadline() {
+    let delay = Duration::from_millis(20);
+    let mut writer = BatchWriter::new(Recorder::default(), FlushPolicy::Delay(delay));
+    assert_eq!(writer.deadline(), None);
+
+    let start = Instant::now();
+    writer.send(&EmbMessage::Heartbeat).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    writer
+        .send(&EmbMessage::Accept(RequestId(0), true))
+        .await
+        .unwrap();
+    assert_eq!(writer.deadline(), Some(start + delay));
+    assert!(writer.get_ref().writes.is_empty());
+
+    writer.flush_due().await.unwrap();
+    assert_eq!(Instant::now(), start + delay);
+    assert_eq!(writer.get_ref().writes.len(), 1);
+    assert_eq!(writer.deadline(), None);
+
+    // nothing buffered, nothing due
+    let idle = tokio::time::timeout(Duration::from_secs(1), writer.flush_due()).await;
+    assert!(idle.is_err());
+
+    // a message sent after the delay passed is written right away
+    writer.send(&EmbMessage::Heartbeat).await.unwrap();
+    tokio::time::sleep(delay).await;
+    writer.send(&EmbMessage::Heartbeat).await.unwrap();
+    assert_eq!(writer.get_ref().writes.len(), 2);
+}
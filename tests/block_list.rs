@@ -0,0 +1,105 @@
+use smoke::block_list::BlockList;
+use smoke::messages::{EmbMessage, ErrorCode, RhizMessage};
+use smoke::room_requests::RoomRequests;
+
+mod common;
+use common::user;
+
+#[test]
+fn block_and_unblock() {
+    let mut list = BlockList::new(8);
+    let aurelia = user(b"Aurelia");
+    let bastian = user(b"Bastian");
+    let cassia = user(b"Cassia");
+
+    assert!(list.permits_room(&bastian, &aurelia));
+
+    let consumed = list.apply(&aurelia, &EmbMessage::Block(bastian.clone()));
+    assert_eq!(consumed, Ok(true));
+    assert!(!list.permits_room(&bastian, &aurelia));
+    assert!(list.permits_room(&cassia, &aurelia));
+    // blocking is one directional
+    assert!(list.permits_room(&aurelia, &bastian));
+
+    let consumed = list.apply(&aurelia, &EmbMessage::Unblock(bastian.clone()));
+    assert_eq!(consumed, Ok(true));
+    assert!(list.permits_room(&bastian, &aurelia));
+}
+
+#[test]
+fn do_not_disturb() {
+    let mut list = BlockList::new(8);
+    let aurelia = user(b"Aurelia");
+    let bastian = user(b"Bastian");
+
+    assert_eq!(
+        list.apply(&aurelia, &EmbMessage::DoNotDisturb(true)),
+        Ok(true)
+    );
+    assert!(list.is_dnd(&aurelia));
+    assert!(!list.permits_room(&bastian, &aurelia));
+
+    assert_eq!(
+        list.apply(&aurelia, &EmbMessage::DoNotDisturb(false)),
+        Ok(true)
+    );
+    assert!(list.permits_room(&bastian, &aurelia));
+
+    list.apply(&aurelia, &EmbMessage::DoNotDisturb(true))
+        .unwrap();
+    list.disconnect(&aurelia);
+    assert!(list.permits_room(&bastian, &aurelia));
+}
+
+#[test]
+fn other_messages_are_not_consumed() {
+    let mut list = BlockList::new(8);
+    let aurelia = user(b"Aurelia");
+
+    assert_eq!(list.apply(&aurelia, &EmbMessage::Heartbeat), Ok(false));
+    assert_eq!(
+        list.apply(&aurelia, &EmbMessage::Room(user(b"Bastian"))),
+        Ok(false)
+    );
+}
+
+#[test]
+fn block_list_is_bounded() {
+    let mut list = BlockList::new(1);
+    let aurelia = user(b"Aurelia");
+
+    list.apply(&aurelia, &EmbMessage::Block(user(b"Bastian")))
+        .unwrap();
+    // blocking the same user again does not count twice
+    list.apply(&aurelia, &EmbMessage::Block(user(b"Bastian")))
+        .unwrap();
+
+    let err = list
+        .apply(&aurelia, &EmbMessage::Block(user(b"Cassia")))
+        .expect_err("block list should be full");
+    assert_eq!(err.code, ErrorCode::ProtocolViolation);
+}
+
+#[test]
+fn room_requests_are_filtered_before_forwarding() {
+    let mut list = BlockList::new(8);
+    let mut requests = RoomRequests::default();
+    let aurelia = user(b"Aurelia");
+    let bastian = user(b"Bastian");
+    let cassia = user(b"Cassia");
+
+    list.apply(&aurelia, &EmbMessage::Block(bastian.clone()))
+        .unwrap();
+    assert_eq!(list.forward_room(&bastian, &aurelia, &mut requests), None);
+
+    let wants = list.forward_room(&cassia, &aurelia, &mut requests);
+    assert!(matches!(wants, Some(RhizMessage::WantsRoom(_, from)) if from == cassia));
+
+    list.apply(&bastian, &EmbMessage::DoNotDisturb(true))
+        .unwrap();
+    assert_eq!(list.forward_room(&cassia, &bastian, &mut requests), None);
+
+    // dropped requests are never registered
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests.incoming(&bastian).count(), 0);
+}
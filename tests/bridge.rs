@@ -0,0 +1,171 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use smoke::bridge::{self, SendWindow};
+use smoke::messages::vlink::Signal;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+const VPORT: u16 = 8080;
+const WINDOW: u32 = 16 * 1024;
+const LOCAL_BUF: usize = 4 * 1024;
+const TOTAL: usize = 512 * 1024;
+
+#[test_log::test(tokio::test(start_paused = true))]
+async fn slow_reader_stalls_sender() {
+    transfer(WINDOW, TOTAL).await;
+}
+
+#[test_log::test(tokio::test(start_paused = true))]
+async fn window_smaller_than_chunk() {
+    // less credit than a single chunk of MAX_CHUNK bytes
+    transfer(100, 16 * 1024).await;
+}
+
+async fn transfer(window: u32, total: usize) {
+    let payload: Vec<u8> = (0..total).map(|i| (i % 251) as u8).collect();
+
+    // local application on the sending side
+    let (mut app_tx, mut sender_local) = tokio::io::duplex(64 * 1024);
+    let data = payload.clone();
+    tokio::spawn(async move {
+        app_tx.write_all(&data).await.unwrap();
+        app_tx.shutdown().await.unwrap();
+    });
+
+    // the p2p link in both directions
+    let (a_to_b, mut b_from_a) = mpsc::channel::<Signal>(16);
+    let (b_to_a, mut a_from_b) = mpsc::channel::<Signal>(16);
+
+    let send_window = SendWindow::new(window);
+    let sender = {
+        let window = send_window.clone();
+        tokio::spawn(async move {
+            bridge::forward_outbound(VPORT, &mut sender_local, &window, &a_to_b).await
+        })
+    };
+
+    tokio::spawn(async move {
+        while let Some(signal) = a_from_b.recv().await {
+            if let Signal::WindowUpdate(VPORT, credit) = signal {
+                send_window.grant(credit);
+            }
+        }
+    });
+
+    let admitted = Arc::new(AtomicUsize::new(0));
+    let (gate, mut queue) = bridge::recv_window(window);
+    let dispatcher = {
+        let admitted = admitted.clone();
+        tokio::spawn(async move {
+            while let Some(signal) = b_from_a.recv().await {
+                if let Signal::Data(VPORT, data) = signal {
+                    admitted.fetch_add(data.len(), Ordering::SeqCst);
+                    gate.admit(data)?;
+                }
+            }
+            std::io::Result::Ok(())
+        })
+    };
+
+    // local application on the receiving side, reading slowly
+    let (mut receiver_local, mut app_rx) = tokio::io::duplex(LOCAL_BUF);
+    let receiver = tokio::spawn(async move {
+        bridge::forward_inbound(VPORT, &mut receiver_local, &mut queue, &b_to_a).await
+    });
+
+    let mut received = Vec::with_capacity(total);
+    let mut chunk = [0u8; 1024];
+    loop {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+
+        let in_flight = admitted.load(Ordering::SeqCst) - received.len();
+        assert!(
+            in_flight <= window as usize + LOCAL_BUF,
+            "{} bytes in flight exceed the window",
+            in_flight
+        );
+
+        let n = app_rx.read(&mut chunk).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        received.extend_from_slice(&chunk[..n]);
+    }
+
+    sender.await.unwrap().expect("sender failed");
+    dispatcher.await.unwrap().expect("peer exceeded window");
+    receiver.await.unwrap().expect("receiver failed");
+    assert_eq!(received, payload);
+}
+
+#[test]
+fn gate_rejects_data_beyond_window() {
+    let (gate, _queue) = bridge::recv_window(8);
+
+    gate.admit(vec![0; 6]).expect("within window");
+    let err = gate
+        .admit(vec![0; 3])
+        .expect_err("peer exceeded the window");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    gate.admit(vec![0; 2]).expect("within window");
+}
+
+#[test_log::test(tokio::test)]
+async fn senders_sharing_a_window_stay_within_it() {
+    const SENDERS: usize = 8;
+    const PER_SENDER: usize = 64 * 1024;
+    let window = 100;
+
+    let (link_tx, mut link_rx) = mpsc::channel::<Signal>(16);
+    let (update_tx, mut update_rx) = mpsc::channel::<Signal>(16);
+    let send_window = SendWindow::new(window);
+
+    // every sender runs on its own thread so they race for the shared credit
+    let mut senders = Vec::new();
+    for _ in 0..SENDERS {
+        let window = send_window.clone();
+        let link = link_tx.clone();
+        senders.push(std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let (mut app, mut local) = tokio::io::duplex(LOCAL_BUF);
+                tokio::spawn(async move {
+                    app.write_all(&[7u8; PER_SENDER]).await.unwrap();
+                    app.shutdown().await.unwrap();
+                });
+                bridge::forward_outbound(VPORT, &mut local, &window, &link).await
+            })
+        }));
+    }
+    drop(link_tx);
+
+    tokio::spawn(async move {
+        while let Some(Signal::WindowUpdate(VPORT, credit)) = update_rx.recv().await {
+            send_window.grant(credit);
+        }
+    });
+
+    let (gate, mut queue) = bridge::recv_window(window);
+    let receiver = tokio::spawn(async move {
+        bridge::forward_inbound(VPORT, &mut tokio::io::sink(), &mut queue, &update_tx).await
+    });
+
+    let mut received = 0;
+    while let Some(Signal::Data(VPORT, data)) = link_rx.recv().await {
+        received += data.len();
+        gate.admit(data).expect("peer exceeded window");
+    }
+    drop(gate);
+
+    for sender in senders {
+        sender.join().unwrap().expect("sender failed");
+    }
+    receiver.await.unwrap().expect("receiver failed");
+    assert_eq!(received, SENDERS * PER_SENDER);
+}
@@ -0,0 +1,23 @@
+//! Fixtures shared by the integration tests
+//!
+//! Every test binary compiles this module on its own and uses only some of it.
+#![allow(dead_code)]
+
+use smoke::identity::Identity;
+use smoke::User;
+
+/// [User] whose certificate is just `name`, for tests that never verify it
+pub fn user(name: &[u8]) -> User {
+    User {
+        cert_data: name.to_vec(),
+    }
+}
+
+/// [Identity] with a fresh self signed certificate for `name`
+pub fn identity(name: &str) -> Identity {
+    let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+    let user = User {
+        cert_data: cert.serialize_der().unwrap(),
+    };
+    Identity::from_pkcs8(user, &cert.serialize_private_key_der()).unwrap()
+}
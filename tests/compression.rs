@@ -0,0 +1,130 @@
+#![cfg(feature = "compression")]
+
+use std::io;
+
+use smoke::connection::Connection;
+use smoke::messages::compression::{self, Algorithm, Compression};
+use smoke::messages::signal::MAX_SIGNAL_BUF_SIZE;
+use smoke::messages::vlink;
+use smoke::Signal;
+
+#[test]
+fn raw_until_negotiated() {
+    let compression = Compression::default();
+    let signal = Signal::Message("a".repeat(2000));
+    assert_eq!(compression.compress(signal.clone()).unwrap(), signal);
+
+    compression.accept(&[]);
+    assert_eq!(compression.algorithm(), None);
+
+    let Signal::Compression(algorithms) = Compression::offer() else {
+        panic!("offer is not a compression signal");
+    };
+    compression.accept(&algorithms);
+    assert_eq!(compression.algorithm(), Some(Algorithm::Lz4));
+}
+
+#[test]
+fn threshold_and_roundtrip() {
+    let compression = Compression::new(256);
+    compression.accept(&[Algorithm::Lz4]);
+
+    let small = Signal::Message("hello".to_string());
+    assert_eq!(compression.compress(small.clone()).unwrap(), small);
+
+    // larger than a signal buffer before compression
+    let data: Vec<u8> = (0..3 * MAX_SIGNAL_BUF_SIZE)
+        .map(|i| (i % 16) as u8)
+        .collect();
+    let large = Signal::Vlink(1, vlink::Signal::Data(80, data));
+    let compressed = compression.compress(large.clone()).unwrap();
+    assert!(matches!(compressed, Signal::Compressed(Algorithm::Lz4, _)));
+
+    let mut ser_buf = [0u8; MAX_SIGNAL_BUF_SIZE];
+    let bytes = postcard::to_slice(&compressed, &mut ser_buf).unwrap();
+    let received: Signal = postcard::from_bytes(bytes).unwrap();
+    assert_eq!(Compression::decompress(received).unwrap(), large);
+
+    assert_eq!(Compression::decompress(small.clone()).unwrap(), small);
+}
+
+#[test]
+fn incompressible_stays_raw() {
+    let compression = Compression::new(0);
+    compression.accept(&[Algorithm::Lz4]);
+
+    // xorshift noise does not compress
+    let mut state = 0x2545_f491u32;
+    let data: Vec<u8> = (0..1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let signal = Signal::Vlink(0, vlink::Signal::Data(1, data));
+    assert_eq!(compression.compress(signal.clone()).unwrap(), signal);
+}
+
+#[test]
+fn rejects_bad_payloads() {
+    let corrupt = Signal::Compressed(Algorithm::Lz4, vec![16, 0, 0, 0, 0xff]);
+    assert_eq!(
+        Compression::decompress(corrupt).unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+
+    let mut oversized = ((compression::MAX_DECOMPRESSED_SIZE + 1) as u32)
+        .to_le_bytes()
+        .to_vec();
+    oversized.push(0);
+    assert_eq!(
+        Compression::decompress(Signal::Compressed(Algorithm::Lz4, oversized))
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidData
+    );
+
+    let inner =
+        postcard::to_extend(&Signal::Compressed(Algorithm::Lz4, vec![0; 4]), Vec::new()).unwrap();
+    let nested = Signal::Compressed(
+        Algorithm::Lz4,
+        lz4_flex::block::compress_prepend_size(&inner),
+    );
+    assert_eq!(
+        Compression::decompress(nested).unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[tokio::test]
+async fn connection_compresses_transparently() {
+    let (a, b) = tokio::io::duplex(64 * 1024);
+    let (mut a_reader, mut a_writer) =
+        compression::compressed(Connection::new(a), Compression::new(256));
+    let (mut b_reader, mut b_writer) =
+        compression::compressed(Connection::new(b), Compression::new(256));
+
+    let data = vec![7u8; 3 * MAX_SIGNAL_BUF_SIZE];
+    let large = Signal::Vlink(1, vlink::Signal::Data(80, data));
+
+    // raw until b announced what it is able to decompress
+    a_writer.send(large.clone()).await.unwrap();
+    assert_eq!(b_reader.recv().await.unwrap(), large);
+
+    b_writer.send(Compression::offer()).await.unwrap();
+    assert!(matches!(
+        a_reader.recv().await.unwrap(),
+        Signal::Compression(_)
+    ));
+
+    a_writer.send(large.clone()).await.unwrap();
+    assert_eq!(b_reader.recv().await.unwrap(), large);
+
+    // the same signal on the wire
+    a_writer.send(large.clone()).await.unwrap();
+    let raw = b_reader.into_inner().recv().await.unwrap();
+    assert!(matches!(raw, Signal::Compressed(Algorithm::Lz4, _)));
+    assert_eq!(Compression::decompress(raw).unwrap(), large);
+}
@@ -0,0 +1,103 @@
+use std::io;
+
+use smoke::connection::Connection;
+use smoke::messages::vlink::Signal;
+use smoke::messages::{EmbMessage, RequestId, RhizMessage};
+
+use tokio::io::DuplexStream;
+
+type Client = Connection<DuplexStream, RhizMessage, EmbMessage>;
+type Server = Connection<DuplexStream, EmbMessage, RhizMessage>;
+
+fn pair() -> (Client, Server) {
+    let (client, server) = tokio::io::duplex(64);
+    (Connection::new(client), Connection::new(server))
+}
+
+#[tokio::test]
+async fn request_response() {
+    let (mut client, mut server) = pair();
+
+    client.send(EmbMessage::Heartbeat).await.unwrap();
+    assert_eq!(server.recv().await.unwrap(), EmbMessage::Heartbeat);
+
+    server.send(RhizMessage::Shutdown()).await.unwrap();
+    assert_eq!(client.recv().await.unwrap(), RhizMessage::Shutdown());
+}
+
+#[tokio::test]
+async fn halves_work_concurrently() {
+    let (a, b) = tokio::io::duplex(64);
+    let a = Connection::<_, Signal, Signal>::new(a);
+    let b = Connection::<_, Signal, Signal>::new(b);
+
+    let (mut a_reader, mut a_writer) = a.into_split();
+    let (mut b_reader, mut b_writer) = b.into_split();
+
+    // both sides write more than the duplex buffer holds before reading anything
+    let a_send = tokio::spawn(async move {
+        for vport in 0..100 {
+            a_writer.send(Signal::Data(vport, vec![1; 40])).await?;
+        }
+        io::Result::Ok(a_writer)
+    });
+    let b_send = tokio::spawn(async move {
+        for vport in 0..100 {
+            b_writer.send(Signal::WindowUpdate(vport, 40)).await?;
+        }
+        io::Result::Ok(b_writer)
+    });
+
+    for vport in 0..100 {
+        assert_eq!(
+            b_reader.recv().await.unwrap(),
+            Signal::Data(vport, vec![1; 40])
+        );
+        assert_eq!(
+            a_reader.recv().await.unwrap(),
+            Signal::WindowUpdate(vport, 40)
+        );
+    }
+
+    let a_writer = a_send.await.unwrap().unwrap();
+    let b_writer = b_send.await.unwrap().unwrap();
+
+    // halves of different connections
+    let err = a_reader.reunite(b_writer).unwrap_err();
+    let (a_reader, b_writer) = (err.0, err.1);
+
+    let mut a = a_reader.reunite(a_writer).unwrap();
+    let mut b = b_reader.reunite(b_writer).unwrap();
+    a.send(Signal::Connect(7)).await.unwrap();
+    assert_eq!(b.recv().await.unwrap(), Signal::Connect(7));
+}
+
+#[tokio::test]
+async fn reunite_keeps_buffered_messages() {
+    let (mut client, server) = pair();
+    let (mut reader, writer) = server.into_split();
+
+    client
+        .send(EmbMessage::Accept(RequestId(0), true))
+        .await
+        .unwrap();
+    client
+        .send(EmbMessage::Accept(RequestId(0), false))
+        .await
+        .unwrap();
+    drop(client);
+
+    // the first read buffers both messages
+    assert_eq!(
+        reader.recv().await.unwrap(),
+        EmbMessage::Accept(RequestId(0), true)
+    );
+    let mut server = reader.reunite(writer).unwrap();
+    assert_eq!(
+        server.recv().await.unwrap(),
+        EmbMessage::Accept(RequestId(0), false)
+    );
+
+    let err = server.recv().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+}
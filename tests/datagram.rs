@@ -0,0 +1,214 @@
+use std::net::SocketAddr;
+
+use smoke::datagram;
+use smoke::messages::vlink::Signal;
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+
+const VPORT: u16 = 27015;
+
+async fn echo_service() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..n], peer).await.unwrap();
+        }
+    });
+    addr
+}
+
+/// Connects both ends of a UDP tunnel, returns the address local applications use
+async fn tunnel(idle_timeout: Duration) -> (SocketAddr, mpsc::Receiver<Signal>) {
+    let service = echo_service().await;
+    let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (listener_out, mut service_in) = mpsc::channel(16);
+    let (service_out, mut listener_in) = mpsc::channel(16);
+    // observes all signals sent by the listening side
+    let (observed_tx, observed_rx) = mpsc::channel(16);
+    let (forward_tx, mut forward_rx) = mpsc::channel::<Signal>(16);
+
+    tokio::spawn(async move {
+        datagram::forward_listener(
+            VPORT,
+            &listener,
+            idle_timeout,
+            &mut listener_in,
+            &forward_tx,
+            None,
+        )
+        .await
+    });
+    tokio::spawn(async move {
+        while let Some(signal) = forward_rx.recv().await {
+            let _ = observed_tx.send(signal.clone()).await;
+            if listener_out.send(signal).await.is_err() {
+                return;
+            }
+        }
+    });
+    tokio::spawn(async move {
+        datagram::forward_service(
+            VPORT,
+            service,
+            idle_timeout,
+            &mut service_in,
+            &service_out,
+            None,
+        )
+        .await
+    });
+
+    (addr, observed_rx)
+}
+
+#[test_log::test(tokio::test)]
+async fn datagrams_are_forwarded_per_flow() {
+    let (addr, _observed) = tunnel(datagram::DEFAULT_IDLE_TIMEOUT).await;
+
+    let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    first.connect(addr).await.unwrap();
+    second.connect(addr).await.unwrap();
+
+    let mut buf = [0u8; 64];
+    for round in 0..3u8 {
+        first.send(&[1, round]).await.unwrap();
+        second.send(&[2, round]).await.unwrap();
+
+        let n = timeout(Duration::from_secs(5), first.recv(&mut buf))
+            .await
+            .expect("no answer for first flow")
+            .unwrap();
+        assert_eq!(&buf[..n], &[1, round]);
+
+        let n = timeout(Duration::from_secs(5), second.recv(&mut buf))
+            .await
+            .expect("no answer for second flow")
+            .unwrap();
+        assert_eq!(&buf[..n], &[2, round]);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn idle_flows_are_closed() {
+    let (addr, mut observed) = tunnel(Duration::from_millis(100)).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(addr).await.unwrap();
+    client.send(b"ping").await.unwrap();
+
+    let mut buf = [0u8; 64];
+    let n = timeout(Duration::from_secs(5), client.recv(&mut buf))
+        .await
+        .expect("no answer")
+        .unwrap();
+    assert_eq!(&buf[..n], b"ping");
+
+    let signal = observed.recv().await.unwrap();
+    let Signal::Datagram(VPORT, flow, _) = signal else {
+        panic!("expected datagram, got {:?}", signal);
+    };
+
+    let signal = timeout(Duration::from_secs(5), observed.recv())
+        .await
+        .expect("flow was not closed")
+        .unwrap();
+    assert_eq!(signal, Signal::FlowClosed(VPORT, flow));
+
+    // a new datagram opens a new flow
+    client.send(b"ping").await.unwrap();
+    let signal = observed.recv().await.unwrap();
+    assert!(matches!(signal, Signal::Datagram(VPORT, new_flow, _) if new_flow != flow));
+}
+
+#[test_log::test(tokio::test)]
+async fn unreachable_service_closes_only_its_flow() {
+    // nothing listens on the address of a dropped socket
+    let target = UdpSocket::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let (link_out, mut service_in) = mpsc::channel(16);
+    let (service_out, mut observed) = mpsc::channel(16);
+    let service = tokio::spawn(async move {
+        datagram::forward_service(
+            VPORT,
+            target,
+            datagram::DEFAULT_IDLE_TIMEOUT,
+            &mut service_in,
+            &service_out,
+            None,
+        )
+        .await
+    });
+
+    for flow in 0..2 {
+        link_out
+            .send(Signal::Datagram(VPORT, flow, b"ping".to_vec()))
+            .await
+            .unwrap();
+        let signal = timeout(Duration::from_secs(5), observed.recv())
+            .await
+            .expect("flow was not closed")
+            .unwrap();
+        assert_eq!(signal, Signal::FlowClosed(VPORT, flow));
+    }
+    assert!(!service.is_finished());
+
+    drop(link_out);
+    service.await.unwrap().unwrap();
+}
+
+#[test_log::test(tokio::test)]
+async fn flows_beyond_the_limit_are_refused() {
+    let service = echo_service().await;
+    let (link_out, mut service_in) = mpsc::channel(16);
+    let (service_out, mut observed) = mpsc::channel(16);
+    tokio::spawn(async move {
+        datagram::forward_service(
+            VPORT,
+            service,
+            datagram::DEFAULT_IDLE_TIMEOUT,
+            &mut service_in,
+            &service_out,
+            None,
+        )
+        .await
+    });
+
+    let flows = datagram::MAX_FLOWS as u32;
+    tokio::spawn(async move {
+        for flow in 0..=flows {
+            link_out
+                .send(Signal::Datagram(VPORT, flow, b"ping".to_vec()))
+                .await
+                .unwrap();
+        }
+        // keeps the service running
+        std::future::pending::<()>().await;
+    });
+
+    let mut answered = 0;
+    let mut refused = Vec::new();
+    while answered < flows || refused.is_empty() {
+        let signal = timeout(Duration::from_secs(5), observed.recv())
+            .await
+            .expect("not all flows were handled")
+            .unwrap();
+        match signal {
+            Signal::Datagram(VPORT, _, _) => answered += 1,
+            Signal::FlowClosed(VPORT, flow) => refused.push(flow),
+            signal => panic!("unexpected {:?}", signal),
+        }
+    }
+    assert_eq!(refused, [flows]);
+}
@@ -0,0 +1,214 @@
+use smoke::device_registry::{DeviceRegistry, MAX_REVOKED_DEVICES};
+use smoke::identity::RootKey;
+use smoke::messages::{EmbMessage, ErrorCode, EMB_MESSAGE_BUF_SIZE};
+use smoke::User;
+
+mod common;
+use common::user;
+
+fn root_key() -> RootKey {
+    let key = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+    RootKey::from_pkcs8(&key.serialize_der()).unwrap()
+}
+
+#[test]
+fn room_reaches_all_devices() {
+    let mut registry = DeviceRegistry::new(4);
+    let root = root_key();
+    let (phone, laptop, other) = (user(b"phone"), user(b"laptop"), user(b"other"));
+
+    for device in [&phone, &laptop] {
+        let msg = EmbMessage::PublishDevice(root.certify(device.clone()).unwrap());
+        assert_eq!(registry.apply(device, &msg), Ok(true));
+    }
+
+    let mut devices = registry.devices_of(&phone);
+    devices.sort_by(|a, b| a.cert_data.cmp(&b.cert_data));
+    assert_eq!(devices, [&laptop, &phone]);
+    assert_eq!(registry.person_of(&laptop), Some(&root.person()));
+
+    // users without a device certificate are routed as before
+    assert_eq!(registry.devices_of(&other), [&other]);
+    assert_eq!(registry.apply(&other, &EmbMessage::Heartbeat), Ok(false));
+}
+
+#[test]
+fn rotation_revokes_old_device() {
+    let mut registry = DeviceRegistry::new(4);
+    let root = root_key();
+    let (old, new) = (user(b"old key"), user(b"new key"));
+
+    let old_cert = root.certify(old.clone()).unwrap();
+    registry
+        .apply(&old, &EmbMessage::PublishDevice(old_cert.clone()))
+        .unwrap();
+    registry
+        .apply(
+            &new,
+            &EmbMessage::PublishDevice(root.certify(new.clone()).unwrap()),
+        )
+        .unwrap();
+
+    let revocation = root.revoke(old.clone()).unwrap();
+    assert_eq!(
+        registry.apply(&new, &EmbMessage::RevokeDevice(revocation)),
+        Ok(true)
+    );
+    assert_eq!(registry.devices_of(&new), [&new]);
+    assert!(registry.is_revoked(&root.person(), &old));
+
+    // the old certificate stays invalid
+    let err = registry
+        .apply(&old, &EmbMessage::PublishDevice(old_cert))
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ProtocolViolation);
+}
+
+#[test]
+fn revocation_before_publishing() {
+    let mut registry = DeviceRegistry::new(4);
+    let root = root_key();
+    let (stolen, phone) = (user(b"stolen"), user(b"phone"));
+    registry
+        .apply(
+            &phone,
+            &EmbMessage::PublishDevice(root.certify(phone.clone()).unwrap()),
+        )
+        .unwrap();
+
+    // the stolen device is offline while it is revoked
+    let cert = root.certify(stolen.clone()).unwrap();
+    let revocation = root.revoke(stolen.clone()).unwrap();
+    assert_eq!(
+        registry.apply(&phone, &EmbMessage::RevokeDevice(revocation)),
+        Ok(true)
+    );
+    assert!(registry.is_revoked(&root.person(), &stolen));
+
+    let err = registry
+        .apply(&stolen, &EmbMessage::PublishDevice(cert))
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ProtocolViolation);
+    assert_eq!(registry.person_of(&stolen), None);
+}
+
+#[test]
+fn revocations_are_bounded_per_person() {
+    let mut registry = DeviceRegistry::new(4);
+    let (root, other_root) = (root_key(), root_key());
+    let (phone, laptop) = (user(b"phone"), user(b"laptop"));
+    registry
+        .apply(
+            &phone,
+            &EmbMessage::PublishDevice(root.certify(phone.clone()).unwrap()),
+        )
+        .unwrap();
+    registry
+        .apply(
+            &laptop,
+            &EmbMessage::PublishDevice(other_root.certify(laptop.clone()).unwrap()),
+        )
+        .unwrap();
+
+    for i in 0..MAX_REVOKED_DEVICES {
+        let revocation = root.revoke(user(&i.to_be_bytes())).unwrap();
+        registry
+            .apply(&phone, &EmbMessage::RevokeDevice(revocation))
+            .unwrap();
+    }
+
+    let revocation = root.revoke(user(b"one too many")).unwrap();
+    let err = registry
+        .apply(&phone, &EmbMessage::RevokeDevice(revocation))
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ProtocolViolation);
+
+    // repeated revocations and other persons are not affected
+    let revocation = root.revoke(user(&0usize.to_be_bytes())).unwrap();
+    assert!(registry
+        .apply(&phone, &EmbMessage::RevokeDevice(revocation))
+        .is_ok());
+    let revocation = other_root.revoke(user(b"tablet")).unwrap();
+    assert!(registry
+        .apply(&laptop, &EmbMessage::RevokeDevice(revocation))
+        .is_ok());
+}
+
+#[test]
+fn revocations_of_unknown_persons_are_rejected() {
+    let mut registry = DeviceRegistry::new(4);
+    let (root, fresh_root) = (root_key(), root_key());
+    let phone = user(b"phone");
+    registry
+        .apply(
+            &phone,
+            &EmbMessage::PublishDevice(root.certify(phone.clone()).unwrap()),
+        )
+        .unwrap();
+
+    // a freshly minted root key without devices cannot store revocations
+    let revocation = fresh_root.revoke(user(b"device")).unwrap();
+    let err = registry
+        .apply(&phone, &EmbMessage::RevokeDevice(revocation))
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ProtocolViolation);
+    assert!(!registry.is_revoked(&fresh_root.person(), &user(b"device")));
+
+    // neither can users without a published device
+    let revocation = root.revoke(phone.clone()).unwrap();
+    assert!(registry
+        .apply(&user(b"stranger"), &EmbMessage::RevokeDevice(revocation))
+        .is_err());
+    assert_eq!(registry.person_of(&phone), Some(&root.person()));
+}
+
+#[test]
+fn invalid_certificates_are_rejected() {
+    let mut registry = DeviceRegistry::new(1);
+    let (root, other_root) = (root_key(), root_key());
+    let (phone, laptop) = (user(b"phone"), user(b"laptop"));
+
+    // certificate of another device
+    let cert = root.certify(laptop.clone()).unwrap();
+    let err = registry
+        .apply(&phone, &EmbMessage::PublishDevice(cert))
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ProtocolViolation);
+
+    // forged signature
+    let mut cert = root.certify(phone.clone()).unwrap();
+    cert.person = other_root.person();
+    assert!(registry
+        .apply(&phone, &EmbMessage::PublishDevice(cert))
+        .is_err());
+
+    // revocation by a different person
+    let cert = root.certify(phone.clone()).unwrap();
+    registry
+        .apply(&phone, &EmbMessage::PublishDevice(cert))
+        .unwrap();
+    let revocation = other_root.revoke(phone.clone()).unwrap();
+    assert!(registry
+        .apply(&laptop, &EmbMessage::RevokeDevice(revocation))
+        .is_err());
+    assert_eq!(registry.person_of(&phone), Some(&root.person()));
+
+    // device limit
+    let cert = root.certify(laptop.clone()).unwrap();
+    let err = registry
+        .apply(&laptop, &EmbMessage::PublishDevice(cert))
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ProtocolViolation);
+}
+
+#[test]
+fn device_cert_fits_emb_message() {
+    let root = root_key();
+    let device = rcgen::generate_simple_self_signed(vec!["device".to_string()]).unwrap();
+    let device = User {
+        cert_data: device.serialize_der().unwrap(),
+    };
+
+    let msg = EmbMessage::PublishDevice(root.certify(device).unwrap());
+    assert!(postcard::to_vec_cobs::<_, EMB_MESSAGE_BUF_SIZE>(&msg).is_ok());
+}
@@ -0,0 +1,147 @@
+use std::io;
+
+use smoke::e2e::{Handshake, Sealed, SecureChannel};
+use smoke::identity::Identity;
+use smoke::messages::vlink::Signal;
+use smoke::User;
+
+use tokio::io::BufReader;
+
+mod common;
+use common::identity;
+
+fn ed25519_identity(name: &str) -> Identity {
+    let mut params = rcgen::CertificateParams::new(vec![name.to_string()]);
+    params.alg = &rcgen::PKCS_ED25519;
+    let cert = rcgen::Certificate::from_params(params).unwrap();
+    let user = User {
+        cert_data: cert.serialize_der().unwrap(),
+    };
+    Identity::from_pkcs8(user, &cert.serialize_private_key_der()).unwrap()
+}
+
+fn channels(a: &Identity, b: &Identity) -> (SecureChannel, SecureChannel) {
+    let (a_hs, a_hello) = Handshake::new(a).unwrap();
+    let (b_hs, b_hello) = Handshake::new(b).unwrap();
+
+    let (mut a_chan, a_auth) = a_hs.finish(b_hello, b.user()).unwrap();
+    let (mut b_chan, b_auth) = b_hs.finish(a_hello, a.user()).unwrap();
+    a_chan.authenticate(b_auth).unwrap();
+    b_chan.authenticate(a_auth).unwrap();
+    (a_chan, b_chan)
+}
+
+#[test]
+fn key_must_match_certificate() {
+    let a = rcgen::generate_simple_self_signed(vec!["a".to_string()]).unwrap();
+    let b = rcgen::generate_simple_self_signed(vec!["b".to_string()]).unwrap();
+    let user = User {
+        cert_data: a.serialize_der().unwrap(),
+    };
+
+    let err = Identity::from_pkcs8(user, &b.serialize_private_key_der()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn seal_open_both_directions() {
+    let (a, b) = (identity("a"), ed25519_identity("b"));
+    let (mut a_chan, mut b_chan) = channels(&a, &b);
+
+    let frame = a_chan.seal(&Signal::Data(3, vec![7; 100])).unwrap();
+    assert!(!frame.data.windows(100).any(|w| w == [7; 100]));
+    assert_eq!(
+        b_chan.open::<Signal>(frame).unwrap(),
+        Signal::Data(3, vec![7; 100])
+    );
+
+    let frame = b_chan.seal(&Signal::Connect(3)).unwrap();
+    assert_eq!(a_chan.open::<Signal>(frame).unwrap(), Signal::Connect(3));
+}
+
+#[test]
+fn tampered_frame_is_rejected() {
+    let (a, b) = (identity("a"), identity("b"));
+    let (mut a_chan, mut b_chan) = channels(&a, &b);
+
+    let mut frame = a_chan.seal(&Signal::Connect(1)).unwrap();
+    frame.data[0] ^= 1;
+    let err = b_chan.open::<Signal>(frame).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // a rejected frame does not break the channel
+    let frame = a_chan.seal(&Signal::Connect(2)).unwrap();
+    assert_eq!(b_chan.open::<Signal>(frame).unwrap(), Signal::Connect(2));
+}
+
+#[test]
+fn replayed_frame_is_rejected_lost_frame_is_not() {
+    let (a, b) = (identity("a"), identity("b"));
+    let (mut a_chan, mut b_chan) = channels(&a, &b);
+
+    let first = a_chan.seal(&Signal::Connect(1)).unwrap();
+    let _lost = a_chan.seal(&Signal::Connect(2)).unwrap();
+    let third = a_chan.seal(&Signal::Connect(3)).unwrap();
+
+    b_chan.open::<Signal>(first.clone()).unwrap();
+    assert_eq!(b_chan.open::<Signal>(third).unwrap(), Signal::Connect(3));
+
+    let err = b_chan.open::<Signal>(first).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn wrong_peer_certificate_is_rejected() {
+    let (a, b, mallory) = (identity("a"), identity("b"), identity("mallory"));
+
+    // `a` expects `b`, but talks to `mallory`
+    let (a_hs, a_hello) = Handshake::new(&a).unwrap();
+    let (m_hs, m_hello) = Handshake::new(&mallory).unwrap();
+    let (mut a_chan, _) = a_hs.finish(m_hello, b.user()).unwrap();
+    let (_, m_auth) = m_hs.finish(a_hello, a.user()).unwrap();
+
+    assert!(a_chan.authenticate(m_auth).is_err());
+    assert!(!a_chan.is_authenticated());
+
+    let frame = Sealed {
+        counter: 1,
+        data: vec![0; 32],
+    };
+    let err = a_chan.open::<Signal>(frame).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn reflected_hello_is_rejected() {
+    let (a, b) = (identity("a"), identity("b"));
+    let (a_hs, a_hello) = Handshake::new(&a).unwrap();
+
+    let err = a_hs.finish(a_hello, b.user()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn handshake_over_stream() {
+    let (a, b) = (identity("a"), identity("b"));
+    let (a_user, b_user) = (a.user().clone(), b.user().clone());
+    let (a_stream, b_stream) = tokio::io::duplex(64);
+    let mut a_stream = BufReader::new(a_stream);
+    let mut b_stream = BufReader::new(b_stream);
+
+    let b_task = tokio::spawn(async move {
+        let mut chan = SecureChannel::handshake(&mut b_stream, &b, &a_user).await?;
+        let signal: Signal = chan.recv(&mut b_stream).await?;
+        chan.send(&mut b_stream, &signal).await?;
+        io::Result::Ok(())
+    });
+
+    let mut chan = SecureChannel::handshake(&mut a_stream, &a, &b_user)
+        .await
+        .unwrap();
+    assert_eq!(chan.peer(), &b_user);
+
+    let signal = Signal::Data(9, vec![1; 300]);
+    chan.send(&mut a_stream, &signal).await.unwrap();
+    assert_eq!(chan.recv::<Signal, _>(&mut a_stream).await.unwrap(), signal);
+    b_task.await.unwrap().unwrap();
+}
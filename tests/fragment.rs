@@ -0,0 +1,160 @@
+use std::io;
+
+use smoke::messages::fragment::{self, Reassembler, DEFAULT_FRAGMENT_SIZE};
+use smoke::messages::{Drain, MessageTooLarge, RhizMessage, RoomId, Source};
+
+use tokio::io::BufReader;
+
+#[tokio::test]
+async fn roundtrip_large_message() {
+    let (mut tx, rx) = tokio::io::duplex(512);
+    let mut rx = BufReader::new(rx);
+
+    let payload: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = payload.clone();
+    let writer = tokio::spawn(async move {
+        fragment::write_fragmented(&mut tx, &payload, DEFAULT_FRAGMENT_SIZE).await?;
+        fragment::write_fragmented(&mut tx, &RhizMessage::Shutdown(), 1).await?;
+        fragment::write_fragmented(&mut tx, &(), 16).await
+    });
+
+    let mut reassembler = Reassembler::default();
+    let received: Vec<u8> = reassembler.read_message(&mut rx).await.unwrap();
+    assert_eq!(received, expected);
+
+    let received: RhizMessage = reassembler.read_message(&mut rx).await.unwrap();
+    assert_eq!(received, RhizMessage::Shutdown());
+
+    reassembler.read_message::<(), _>(&mut rx).await.unwrap();
+    writer.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn oversized_message_is_skipped() {
+    let (mut tx, rx) = tokio::io::duplex(4096);
+    let mut rx = BufReader::new(rx);
+
+    let writer = tokio::spawn(async move {
+        fragment::write_fragmented(&mut tx, &vec![7u8; 1000], 64).await?;
+        fragment::write_fragmented(&mut tx, &RoomId([3; 32]), 8).await
+    });
+
+    let mut reassembler = Reassembler::new(256);
+    let err = reassembler
+        .read_message::<Vec<u8>, _>(&mut rx)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // the rest of the oversized message does not corrupt the next one
+    let room: RoomId = reassembler.read_message(&mut rx).await.unwrap();
+    assert_eq!(room, RoomId([3; 32]));
+    writer.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn zero_fragment_size_is_rejected() {
+    let mut sink = tokio::io::sink();
+    let err = fragment::write_fragmented(&mut sink, &1u8, 0)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn drain_and_source_fragment_transparently() {
+    let (mut tx, rx) = tokio::io::duplex(64);
+    let mut rx = BufReader::new(rx);
+
+    let cert_data = vec![9u8; 4096];
+    let expected = cert_data.clone();
+    let writer = tokio::spawn(async move {
+        cert_data.serialize_fragmented_to(&mut tx, 100)?.await?;
+        RoomId([5; 32]).serialize_fragmented_to(&mut tx, 100)?.await
+    });
+
+    let mut reassembler = Reassembler::default().fragment_size(100);
+    let received: Vec<u8> = rx.read_message_fragmented(&mut reassembler).await.unwrap();
+    assert_eq!(received, expected);
+    let room: RoomId = rx.read_message_fragmented(&mut reassembler).await.unwrap();
+    assert_eq!(room, RoomId([5; 32]));
+    writer.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn oversized_fragment_is_rejected() {
+    let (mut tx, rx) = tokio::io::duplex(4096);
+    let mut rx = BufReader::new(rx);
+
+    let writer = tokio::spawn(async move {
+        fragment::write_fragmented(&mut tx, &vec![7u8; 1000], 512).await?;
+        fragment::write_fragmented(&mut tx, &RoomId([3; 32]), 64).await
+    });
+
+    // the message fits, but its fragments are larger than configured
+    let mut reassembler = Reassembler::default().fragment_size(64);
+    let err = reassembler
+        .read_message::<Vec<u8>, _>(&mut rx)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<MessageTooLarge>()),
+        Some(&MessageTooLarge { max_size: 64 })
+    );
+
+    let room: RoomId = reassembler.read_message(&mut rx).await.unwrap();
+    assert_eq!(room, RoomId([3; 32]));
+    writer.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn cancelled_read_keeps_partial_message() {
+    let (mut tx, rx) = tokio::io::duplex(4096);
+    let mut rx = BufReader::new(rx);
+
+    let mut bytes = Vec::new();
+    fragment::write_fragmented(&mut bytes, &vec![1u8; 300], 64)
+        .await
+        .unwrap();
+    let (first, rest) = bytes.split_at(150);
+
+    let mut reassembler = Reassembler::default().fragment_size(64);
+    tokio::io::AsyncWriteExt::write_all(&mut tx, first)
+        .await
+        .unwrap();
+    let read = rx.read_message_fragmented::<Vec<u8>>(&mut reassembler);
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(10), read)
+            .await
+            .is_err()
+    );
+
+    tokio::io::AsyncWriteExt::write_all(&mut tx, rest)
+        .await
+        .unwrap();
+    let received: Vec<u8> = rx.read_message_fragmented(&mut reassembler).await.unwrap();
+    assert_eq!(received, vec![1u8; 300]);
+}
+
+#[tokio::test]
+async fn corrupt_header_drops_partial_message() {
+    let mut stream = Vec::new();
+    // the first fragment of a message, followed by a header with an invalid `last`
+    stream.extend_from_slice(&[0, 3, b'a', b'b', b'c', 5]);
+    fragment::write_fragmented(&mut stream, &"hello".to_string(), 2)
+        .await
+        .unwrap();
+    let mut rx = BufReader::new(stream.as_slice());
+
+    let mut reassembler = Reassembler::default();
+    let err = reassembler
+        .read_message::<String, _>(&mut rx)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let received: String = reassembler.read_message(&mut rx).await.unwrap();
+    assert_eq!(received, "hello");
+}
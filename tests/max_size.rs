@@ -0,0 +1,101 @@
+use std::io;
+
+use smoke::messages::vlink::SignalRef;
+use smoke::messages::{EmbMessage, MessageTooLarge, Source, DEFAULT_MAX_MESSAGE_SIZE};
+use smoke::User;
+
+use tokio::io::{AsyncWriteExt, BufReader, DuplexStream};
+
+/// `EmbMessage::Room`, the length of `User::cert_data` follows
+const ROOM: &[u8] = &[0];
+/// `SignalRef::Data` of vport 1, the length of the payload follows
+const DATA: &[u8] = &[1, 1];
+
+/// Sends `prefix` and announces a byte field of 256 MiB, then sends zeros until the reader hangs up
+fn malicious_peer(prefix: &'static [u8]) -> DuplexStream {
+    let (mut tx, rx) = tokio::io::duplex(4096);
+    tokio::spawn(async move {
+        let mut header = prefix.to_vec();
+        let mut len = 256u32 * 1024 * 1024;
+        while len >= 0x80 {
+            header.push(len as u8 | 0x80);
+            len >>= 7;
+        }
+        header.push(len as u8);
+
+        tx.write_all(&header).await?;
+        let zeros = [0u8; 1024];
+        loop {
+            tx.write_all(&zeros).await?;
+        }
+        #[allow(unreachable_code)]
+        io::Result::Ok(())
+    });
+    rx
+}
+
+fn assert_too_large(err: io::Error, max_size: usize) {
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let inner = err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<MessageTooLarge>())
+        .expect("not a MessageTooLarge");
+    assert_eq!(inner.max_size, max_size);
+}
+
+#[tokio::test]
+async fn read_message_is_bounded() {
+    let mut reader = BufReader::new(malicious_peer(ROOM));
+    let err = reader.read_message::<EmbMessage>().await.unwrap_err();
+    assert_too_large(err, DEFAULT_MAX_MESSAGE_SIZE);
+}
+
+#[tokio::test]
+async fn read_message_cancelable_is_bounded() {
+    let mut reader = BufReader::new(malicious_peer(ROOM));
+    let mut agg = Vec::new();
+    let err = reader
+        .read_message_cancelable::<EmbMessage>(&mut agg)
+        .max_size(8 * 1024)
+        .await
+        .unwrap_err();
+    assert_too_large(err, 8 * 1024);
+    assert!(agg.is_empty());
+}
+
+#[tokio::test]
+async fn read_message_ref_is_bounded() {
+    let mut reader = BufReader::new(malicious_peer(DATA));
+    let mut buf = Vec::new();
+    let err = reader
+        .read_message_ref::<SignalRef>(&mut buf)
+        .max_size(2048)
+        .await
+        .unwrap_err();
+    assert_too_large(err, 2048);
+}
+
+#[tokio::test]
+async fn complete_message_over_limit() {
+    let msg = EmbMessage::Room(User {
+        cert_data: vec![7; 600],
+    });
+    let mut ser_buf = [0u8; 1024];
+    let bytes = postcard::to_slice(&msg, &mut ser_buf).unwrap().to_vec();
+
+    let mut reader = BufReader::new(&bytes[..]);
+    let err = reader
+        .read_message::<EmbMessage>()
+        .max_size(512)
+        .await
+        .unwrap_err();
+    assert_too_large(err, 512);
+
+    let mut reader = BufReader::new(&bytes[..]);
+    let received = reader
+        .read_message::<EmbMessage>()
+        .max_size(1024)
+        .await
+        .unwrap();
+    assert_eq!(received, msg);
+}
@@ -0,0 +1,100 @@
+use std::io;
+
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use smoke::messages::vlink::Signal;
+use smoke::messages::{EmbMessage, MessageReader, MessageWriter, RequestId};
+use smoke::User;
+
+use tokio::io::BufReader;
+use tokio_test::io::Builder;
+
+fn messages() -> Vec<EmbMessage> {
+    vec![
+        EmbMessage::Heartbeat,
+        EmbMessage::Room(User {
+            cert_data: vec![42; 10_000],
+        }),
+        EmbMessage::Accept(RequestId(0), false),
+    ]
+}
+
+#[tokio::test]
+async fn sink_to_stream() {
+    let (tx, rx) = tokio::io::duplex(256);
+
+    let writer = tokio::spawn(async move {
+        let mut sink = MessageWriter::new(tx);
+        for message in messages() {
+            sink.send(message).await?;
+        }
+        sink.close().await
+    });
+
+    let reader = MessageReader::<_, EmbMessage>::new(BufReader::new(rx));
+    let received: Vec<EmbMessage> = reader.try_collect().await.unwrap();
+    assert_eq!(received, messages());
+    writer.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn forward_between_adapters() {
+    let (a_tx, a_rx) = tokio::io::duplex(64);
+    let (b_tx, b_rx) = tokio::io::duplex(64);
+
+    tokio::spawn(async move {
+        let mut sink = MessageWriter::new(a_tx);
+        for vport in 0..50 {
+            sink.feed(Signal::Data(vport, vec![vport as u8; 30]))
+                .await?;
+        }
+        sink.close().await
+    });
+
+    // relays everything read from `a` to `b`
+    tokio::spawn(async move {
+        let source = MessageReader::<_, Signal>::new(BufReader::new(a_rx));
+        source.forward(MessageWriter::new(b_tx)).await
+    });
+
+    let received: Vec<Signal> = MessageReader::new(BufReader::new(b_rx))
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(received.len(), 50);
+    assert_eq!(received[49], Signal::Data(49, vec![49; 30]));
+}
+
+#[tokio::test]
+async fn truncated_message_is_an_error() {
+    let stream = Builder::new().read(&[0, 5, 1, 2]).build();
+    let mut reader = MessageReader::<_, EmbMessage>::new(BufReader::new(stream));
+
+    let err = reader.next().await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    assert!(reader.next().await.is_none());
+}
+
+#[tokio::test]
+async fn dropped_next_keeps_progress() {
+    let mut bytes = Vec::new();
+    let mut sink = MessageWriter::new(&mut bytes);
+    sink.send(EmbMessage::Accept(RequestId(0), true))
+        .await
+        .unwrap();
+    drop(sink);
+
+    let stream = Builder::new()
+        .read(&bytes[..1])
+        .wait(std::time::Duration::from_millis(50))
+        .read(&bytes[1..])
+        .build();
+    let mut reader = MessageReader::<_, EmbMessage>::new(BufReader::new(stream));
+
+    // give up on the first attempt after the first byte was aggregated
+    let first = tokio::time::timeout(std::time::Duration::from_millis(10), reader.next()).await;
+    assert!(first.is_err());
+
+    let message = reader.next().await.unwrap().unwrap();
+    assert_eq!(message, EmbMessage::Accept(RequestId(0), true));
+    assert!(reader.next().await.is_none());
+}
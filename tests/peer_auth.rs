@@ -0,0 +1,118 @@
+#![cfg(feature = "client")]
+
+use std::io;
+
+use smoke::connection::Connection;
+use smoke::messages::profile::{Profile, SignedProfile};
+use smoke::messages::RoomId;
+use smoke::peer_auth::{self, PeerAuth, Received};
+use smoke::Signal;
+
+mod common;
+use common::identity;
+
+fn reply(received: Received) -> Signal {
+    match received {
+        Received::Reply(signal) => signal,
+        other => panic!("expected a reply, got {:?}", other),
+    }
+}
+
+#[test]
+fn mutual_authentication() {
+    let (a, b) = (identity("a"), identity("b"));
+    let room = RoomId([1; 32]);
+
+    let (mut a_auth, a_challenge) = PeerAuth::new(&a, room.clone(), b.user().clone()).unwrap();
+    let (mut b_auth, b_challenge) = PeerAuth::new(&b, room, a.user().clone()).unwrap();
+
+    let a_response = reply(a_auth.receive(b_challenge).unwrap());
+    let b_response = reply(b_auth.receive(a_challenge).unwrap());
+
+    assert_eq!(a_auth.receive(b_response).unwrap(), Received::Verified);
+    assert_eq!(b_auth.receive(a_response).unwrap(), Received::Verified);
+
+    let message = Signal::Message("b".to_string());
+    assert_eq!(
+        a_auth.receive(message.clone()).unwrap(),
+        Received::Signal(message)
+    );
+}
+
+#[test]
+fn signals_before_verification_are_rejected() {
+    let (a, b) = (identity("a"), identity("b"));
+    let (mut a_auth, _) = PeerAuth::new(&a, RoomId([1; 32]), b.user().clone()).unwrap();
+
+    assert_eq!(
+        a_auth.receive(Signal::Kap).unwrap(),
+        Received::Signal(Signal::Kap)
+    );
+    let profile = SignedProfile::sign(Profile::default(), &b).unwrap();
+    for signal in [Signal::Profile(profile), Signal::Message("hi".to_string())] {
+        let err = a_auth.receive(signal).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
+
+#[test]
+fn impostor_is_rejected() {
+    let (a, b, mallory) = (identity("a"), identity("b"), identity("mallory"));
+    let room = RoomId([1; 32]);
+
+    // `a` was told to expect `b` in the room, but `mallory` answers
+    let (mut a_auth, a_challenge) = PeerAuth::new(&a, room.clone(), b.user().clone()).unwrap();
+    let (mut m_auth, _) = PeerAuth::new(&mallory, room, a.user().clone()).unwrap();
+
+    let m_response = reply(m_auth.receive(a_challenge).unwrap());
+    let err = a_auth.receive(m_response).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(!a_auth.is_verified());
+}
+
+#[test]
+fn response_is_bound_to_room() {
+    let (a, b) = (identity("a"), identity("b"));
+
+    let (mut a_auth, a_challenge) = PeerAuth::new(&a, RoomId([1; 32]), b.user().clone()).unwrap();
+    let (mut b_auth, _) = PeerAuth::new(&b, RoomId([2; 32]), a.user().clone()).unwrap();
+
+    let b_response = reply(b_auth.receive(a_challenge).unwrap());
+    let err = a_auth.receive(b_response).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn reflected_challenge_is_rejected() {
+    let (a, b) = (identity("a"), identity("b"));
+    let (mut a_auth, a_challenge) = PeerAuth::new(&a, RoomId([1; 32]), b.user().clone()).unwrap();
+
+    let err = a_auth.receive(a_challenge).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn authenticate_over_connection() {
+    let (a, b) = (identity("a"), identity("b"));
+    let (a_user, b_user) = (a.user().clone(), b.user().clone());
+    let room = RoomId([3; 32]);
+    let (a_stream, b_stream) = tokio::io::duplex(1024);
+    let mut a_conn = Connection::<_, Signal, Signal>::new(a_stream);
+    let mut b_conn = Connection::<_, Signal, Signal>::new(b_stream);
+
+    let b_room = room.clone();
+    let b_task = tokio::spawn(async move {
+        peer_auth::authenticate(&mut b_conn, &b, b_room, a_user).await?;
+        b_conn.send(Signal::Message("b".to_string())).await?;
+        io::Result::Ok(b_conn)
+    });
+
+    peer_auth::authenticate(&mut a_conn, &a, room, b_user)
+        .await
+        .unwrap();
+    assert_eq!(
+        a_conn.recv().await.unwrap(),
+        Signal::Message("b".to_string())
+    );
+    b_task.await.unwrap().unwrap();
+}
@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use smoke::bridge;
+use smoke::messages::vlink::{ErrorKindCode, Signal};
+use smoke::policy::{Admission, PolicyEnforcer, TunnelPolicy};
+
+use tokio::sync::{mpsc, oneshot};
+
+use tokio::time::{Duration, Instant};
+
+fn denied<T: std::fmt::Debug>(signal: Result<T, Signal>) -> ErrorKindCode {
+    match signal {
+        Err(Signal::Error(_, kind, _)) => kind,
+        other => panic!("expected denial, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn default_policy_allows_everything() {
+    let mut enforcer = PolicyEnforcer::new("http".to_string(), Arc::default());
+
+    assert!(enforcer.admit(&Signal::Connect(80)).is_ok());
+    assert!(enforcer.admit(&Signal::Connect(443)).is_ok());
+    assert!(enforcer.admit(&Signal::Data(80, b"GET /".to_vec())).is_ok());
+}
+
+#[tokio::test]
+async fn only_allowed_vports() {
+    let policy = TunnelPolicy {
+        allowed_vports: Some(HashSet::from([80])),
+        ..Default::default()
+    };
+    let mut enforcer = PolicyEnforcer::new("http".to_string(), Arc::new(policy));
+
+    assert!(enforcer.admit(&Signal::Connect(80)).is_ok());
+    assert_eq!(
+        denied(enforcer.admit(&Signal::Connect(22))),
+        ErrorKindCode::PermissionDenied
+    );
+    // data of a rejected connection is never forwarded
+    assert_eq!(
+        denied(enforcer.admit(&Signal::Data(22, b"ssh".to_vec()))),
+        ErrorKindCode::NotConnected
+    );
+}
+
+#[tokio::test]
+async fn max_connections() {
+    let policy = TunnelPolicy {
+        max_connections: Some(1),
+        ..Default::default()
+    };
+    let mut enforcer = PolicyEnforcer::new("http".to_string(), Arc::new(policy));
+
+    assert!(enforcer.admit(&Signal::Connect(80)).is_ok());
+    assert!(enforcer.admit(&Signal::Connect(81)).is_err());
+
+    let closed = Signal::Error(80, ErrorKindCode::ConnectionReset, "reset".to_string());
+    assert!(enforcer.admit(&closed).is_ok());
+    assert!(enforcer.admit(&Signal::Connect(81)).is_ok());
+
+    enforcer.disconnect(81);
+    assert!(enforcer.active().is_empty());
+}
+
+#[tokio::test]
+async fn approval_callback() {
+    let policy = TunnelPolicy {
+        approval: Some(Box::new(|name, vport| {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                name == "http" && vport == 8080
+            })
+        })),
+        ..Default::default()
+    };
+    let policy = Arc::new(policy);
+
+    let mut enforcer = PolicyEnforcer::new("http".to_string(), policy.clone());
+    assert_eq!(enforcer.admit(&Signal::Connect(8080)), Ok(Admission::Held));
+    assert_eq!(enforcer.decided().await, Ok(vec![Signal::Connect(8080)]));
+    assert!(enforcer.active().contains(&8080));
+
+    assert_eq!(enforcer.admit(&Signal::Connect(8081)), Ok(Admission::Held));
+    assert_eq!(
+        denied(enforcer.decided().await),
+        ErrorKindCode::PermissionDenied
+    );
+
+    let mut enforcer = PolicyEnforcer::new("game".to_string(), policy);
+    assert_eq!(enforcer.admit(&Signal::Connect(8080)), Ok(Admission::Held));
+    assert!(enforcer.decided().await.is_err());
+}
+
+#[tokio::test]
+async fn pending_approval_does_not_block_other_signals() {
+    let (answer_tx, answer_rx) = oneshot::channel();
+    let answer_rx = std::sync::Mutex::new(Some(answer_rx));
+    let policy = TunnelPolicy {
+        approval: Some(Box::new(move |_, vport| {
+            let answer = answer_rx.lock().unwrap().take();
+            Box::pin(async move {
+                match answer {
+                    Some(answer) => answer.await.unwrap_or(false),
+                    None => vport == 80,
+                }
+            })
+        })),
+        ..Default::default()
+    };
+    let mut enforcer = PolicyEnforcer::new("http".to_string(), Arc::new(policy));
+
+    // the user has not answered the prompt for vport 8080 yet
+    assert_eq!(enforcer.admit(&Signal::Connect(8080)), Ok(Admission::Held));
+    let data = Signal::Data(8080, b"early".to_vec());
+    assert_eq!(enforcer.admit(&data), Ok(Admission::Held));
+
+    // other vports and flow control are still handled
+    assert_eq!(enforcer.admit(&Signal::Connect(80)), Ok(Admission::Held));
+    assert_eq!(enforcer.decided().await, Ok(vec![Signal::Connect(80)]));
+    assert_eq!(
+        enforcer.admit(&Signal::Data(80, b"GET /".to_vec())),
+        Ok(Admission::Forward)
+    );
+    assert_eq!(
+        enforcer.admit(&Signal::WindowUpdate(80, 10)),
+        Ok(Admission::Forward)
+    );
+
+    answer_tx.send(true).unwrap();
+    assert_eq!(
+        enforcer.decided().await,
+        Ok(vec![Signal::Connect(8080), data])
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn bandwidth_cap_delays_window_credit() {
+    let policy = TunnelPolicy {
+        bandwidth: Some(1000),
+        ..Default::default()
+    };
+    let mut enforcer = PolicyEnforcer::new("http".to_string(), Arc::new(policy));
+    enforcer.admit(&Signal::Connect(80)).unwrap();
+
+    let (gate, queue) = bridge::recv_window(3000);
+    let mut queue = queue.with_throttle(enforcer.throttle().unwrap());
+
+    // admitting data never waits, so the link reader is not stalled
+    let start = Instant::now();
+    for _ in 0..30 {
+        enforcer.admit(&Signal::Data(80, vec![0; 100])).unwrap();
+        gate.admit(vec![0; 100]).unwrap();
+    }
+    assert_eq!(start.elapsed(), Duration::ZERO);
+    drop(gate);
+
+    let (link, mut updates) = mpsc::channel(16);
+    let writer = tokio::spawn(async move {
+        bridge::forward_inbound(80, &mut tokio::io::sink(), &mut queue, &link).await
+    });
+
+    let mut credit = 0;
+    while let Some(signal) = updates.recv().await {
+        let Signal::WindowUpdate(80, granted) = signal else {
+            panic!("expected window update, got {:?}", signal);
+        };
+        credit += granted;
+    }
+    writer.await.unwrap().unwrap();
+    assert_eq!(credit, 3000);
+
+    // the first second of traffic is allowed as burst
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_secs(2), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(2100), "{:?}", elapsed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn bandwidth_cap_is_shared_by_all_vports() {
+    let policy = TunnelPolicy {
+        bandwidth: Some(1000),
+        ..Default::default()
+    };
+    let enforcer = PolicyEnforcer::new("http".to_string(), Arc::new(policy));
+    let (link, mut updates) = mpsc::channel(16);
+
+    let start = Instant::now();
+    let mut writers = Vec::new();
+    for vport in [80, 81] {
+        let (gate, queue) = bridge::recv_window(3000);
+        let mut queue = queue.with_throttle(enforcer.throttle().unwrap());
+        gate.admit(vec![0; 3000]).unwrap();
+        drop(gate);
+
+        let link = link.clone();
+        writers.push(tokio::spawn(async move {
+            bridge::forward_inbound(vport, &mut tokio::io::sink(), &mut queue, &link).await
+        }));
+    }
+    drop(link);
+
+    let mut credit = 0;
+    while let Some(Signal::WindowUpdate(_, granted)) = updates.recv().await {
+        credit += granted;
+    }
+    for writer in writers {
+        writer.await.unwrap().unwrap();
+    }
+    assert_eq!(credit, 6000);
+
+    // 6000 bytes at 1000 bytes per second with a burst of 1000 bytes
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_secs(5), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(5100), "{:?}", elapsed);
+}
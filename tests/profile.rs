@@ -0,0 +1,96 @@
+#![cfg(feature = "client")]
+
+use std::io;
+
+use smoke::messages::profile::{Profile, SignedProfile, MAX_DISPLAY_NAME_LEN};
+use smoke::Signal;
+
+mod common;
+use common::identity;
+
+fn profile(name: &str) -> Profile {
+    Profile {
+        display_name: name.to_string(),
+        avatar_hash: Some([7; 32]),
+        status: "away".to_string(),
+        version: 1,
+    }
+}
+
+#[test]
+fn signed_profile_roundtrip() {
+    let alice = identity("alice");
+    let signal = Signal::Profile(SignedProfile::sign(profile("alice"), &alice).unwrap());
+
+    let bytes = postcard::to_extend(&signal, Vec::new()).unwrap();
+    let Signal::Profile(signed) = postcard::from_bytes::<Signal>(&bytes).unwrap() else {
+        panic!("not a profile signal");
+    };
+    assert_eq!(signed.verify(alice.user()).unwrap(), &profile("alice"));
+}
+
+#[test]
+fn profile_of_other_user_is_rejected() {
+    let (alice, mallory) = (identity("alice"), identity("mallory"));
+
+    // mallory claims to be alice with its own valid signature
+    let signed = SignedProfile::sign(profile("alice"), &mallory).unwrap();
+    let err = signed.verify(alice.user()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn modified_profile_is_rejected() {
+    let alice = identity("alice");
+    let signed = SignedProfile::sign(profile("alice"), &alice).unwrap();
+
+    let mut bytes = postcard::to_extend(&signed, Vec::new()).unwrap();
+    // the display name follows its length prefix
+    bytes[1] = b'A';
+    let modified: SignedProfile = postcard::from_bytes(&bytes).unwrap();
+    assert_eq!(modified.unverified().display_name, "Alice");
+
+    let err = modified.verify(alice.user()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn oversized_profile_is_rejected() {
+    let alice = identity("alice");
+    let name = "a".repeat(MAX_DISPLAY_NAME_LEN + 1);
+
+    let err = SignedProfile::sign(profile(&name), &alice).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn older_profile_is_rejected() {
+    let alice = identity("alice");
+    let old = SignedProfile::sign(profile("alice"), &alice).unwrap();
+    let new = Profile {
+        status: "busy".to_string(),
+        version: 2,
+        ..profile("alice")
+    };
+    let new = SignedProfile::sign(new, &alice).unwrap();
+
+    let current = new.verify_update(alice.user(), None).unwrap().clone();
+    // a replay of the old profile must not replace the new one
+    let err = old.verify_update(alice.user(), Some(&current)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    // resending the current profile is fine
+    assert_eq!(
+        new.verify_update(alice.user(), Some(&current)).unwrap(),
+        &current
+    );
+
+    let conflicting = Profile {
+        status: "gone".to_string(),
+        ..current.clone()
+    };
+    let conflicting = SignedProfile::sign(conflicting, &alice).unwrap();
+    let err = conflicting
+        .verify_update(alice.user(), Some(&current))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,147 @@
+use smoke::identity::RootKey;
+use smoke::messages::{EmbMessage, ErrorCode, RequestId, RhizMessage};
+use smoke::rate_limit::{Quota, RateLimiter, RequestClass};
+
+use tokio::io::BufReader;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+mod common;
+use common::user;
+
+fn limiter() -> RateLimiter {
+    RateLimiter::new(
+        Quota::new(2, Duration::from_secs(10)),
+        Quota::new(1, Duration::from_secs(1)),
+        Quota::new(3, Duration::from_secs(60)),
+    )
+}
+
+#[tokio::test(start_paused = true)]
+async fn room_quota_exhausts_and_refills() {
+    let mut limiter = limiter();
+    let sender = user(b"Aurelia");
+    let room = EmbMessage::Room(user(b"Bastian"));
+
+    assert!(limiter.check(&sender, &room).is_ok());
+    assert!(limiter.check(&sender, &room).is_ok());
+
+    let err = limiter
+        .check(&sender, &room)
+        .expect_err("third room request should be limited");
+    assert_eq!(err.code, ErrorCode::RateLimited);
+
+    tokio::time::advance(Duration::from_secs(9)).await;
+    assert!(limiter.check(&sender, &room).is_err());
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert!(limiter.check(&sender, &room).is_ok());
+    assert!(limiter.check(&sender, &room).is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn quotas_are_per_class() {
+    let mut limiter = limiter();
+    let sender = user(b"Aurelia");
+    let room = EmbMessage::Room(user(b"Bastian"));
+
+    assert!(limiter.check(&sender, &EmbMessage::Heartbeat).is_ok());
+    assert!(limiter.check(&sender, &EmbMessage::Heartbeat).is_err());
+
+    assert!(limiter.check(&sender, &room).is_ok());
+
+    // unlimited messages are never throttled
+    for _ in 0..10 {
+        assert!(limiter
+            .check(&sender, &EmbMessage::Accept(RequestId(0), true))
+            .is_ok());
+    }
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert!(limiter.check(&sender, &EmbMessage::Heartbeat).is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn device_messages_are_limited() {
+    let mut limiter = limiter();
+    let sender = user(b"Aurelia");
+    let key = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+    let root = RootKey::from_pkcs8(&key.serialize_der()).unwrap();
+
+    let publish = EmbMessage::PublishDevice(root.certify(sender.clone()).unwrap());
+    let revoke = EmbMessage::RevokeDevice(root.revoke(user(b"Bastian")).unwrap());
+    assert_eq!(RequestClass::of(&publish), Some(RequestClass::Device));
+    assert_eq!(RequestClass::of(&revoke), Some(RequestClass::Device));
+
+    // publishing and revoking share one quota
+    assert!(limiter.check(&sender, &publish).is_ok());
+    assert!(limiter.check(&sender, &revoke).is_ok());
+    assert!(limiter.check(&sender, &revoke).is_ok());
+    let err = limiter.check(&sender, &publish).unwrap_err();
+    assert_eq!(err.code, ErrorCode::RateLimited);
+
+    assert!(limiter.check(&sender, &EmbMessage::Heartbeat).is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn quotas_are_per_user() {
+    let mut limiter = limiter();
+    let first = user(b"Aurelia");
+    let second = user(b"Bastian");
+
+    assert!(limiter.check(&first, &EmbMessage::Heartbeat).is_ok());
+    assert!(limiter.check(&first, &EmbMessage::Heartbeat).is_err());
+    assert!(limiter.check(&second, &EmbMessage::Heartbeat).is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn refill_does_not_exceed_capacity() {
+    let mut limiter = limiter();
+    let sender = user(b"Aurelia");
+    let room = EmbMessage::Room(user(b"Bastian"));
+
+    tokio::time::advance(Duration::from_secs(3600)).await;
+    limiter.prune();
+
+    assert!(limiter.check(&sender, &room).is_ok());
+    assert!(limiter.check(&sender, &room).is_ok());
+    assert!(limiter.check(&sender, &room).is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn throttled_requests_are_answered() {
+    let limiter = Mutex::new(limiter());
+    let sender = user(b"Aurelia");
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = BufReader::new(client);
+    let mut server = BufReader::new(server);
+
+    for _ in 0..2 {
+        EmbMessage::Heartbeat.send_to(&mut client).await.unwrap();
+    }
+    EmbMessage::Accept(RequestId(3), true)
+        .send_to(&mut client)
+        .await
+        .unwrap();
+
+    let mut buf = Vec::new();
+    let msg = RateLimiter::recv_from(&limiter, &sender, &mut server, &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(msg, EmbMessage::Heartbeat);
+    // the second heartbeat is answered and dropped
+    let msg = RateLimiter::recv_from(&limiter, &sender, &mut server, &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(msg, EmbMessage::Accept(RequestId(3), true));
+
+    let mut client_buf = Vec::new();
+    let answer = RhizMessage::recv_from(&mut client, &mut client_buf)
+        .await
+        .unwrap();
+    let err = answer.into_server_error().expect("expected a ServerError");
+    assert_eq!(err.code, ErrorCode::RateLimited);
+
+    // the limiter is not locked between requests
+    assert!(limiter.try_lock().is_ok());
+}
@@ -0,0 +1,113 @@
+use smoke::messages::{ErrorCode, RequestId, RhizMessage, RoomId};
+use smoke::room_requests::RoomRequests;
+
+use tokio::time::Duration;
+
+mod common;
+use common::user;
+
+fn request_id(msg: RhizMessage) -> RequestId {
+    match msg {
+        RhizMessage::WantsRoom(id, _) => id,
+        other => panic!("expected WantsRoom, got {:?}", other),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn concurrent_requests_are_matched() {
+    let mut requests = RoomRequests::default();
+    let aurelia = user(b"Aurelia");
+    let bastian = user(b"Bastian");
+    let cassia = user(b"Cassia");
+
+    // Bastian and Cassia want a room with Aurelia at nearly the same time
+    let from_bastian = request_id(requests.request(&bastian, &aurelia));
+    let from_cassia = request_id(requests.request(&cassia, &aurelia));
+    // while Aurelia has an outgoing request to Bastian
+    let from_aurelia = request_id(requests.request(&aurelia, &bastian));
+    assert_ne!(from_bastian, from_cassia);
+    assert_eq!(requests.incoming(&aurelia).count(), 2);
+
+    assert_eq!(requests.resolve(&aurelia, from_cassia), Ok(cassia));
+    assert_eq!(
+        requests.resolve(&aurelia, from_bastian),
+        Ok(bastian.clone())
+    );
+    assert_eq!(requests.resolve(&bastian, from_aurelia), Ok(aurelia));
+    assert!(requests.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn wrong_or_repeated_answers_are_rejected() {
+    let mut requests = RoomRequests::default();
+    let aurelia = user(b"Aurelia");
+    let bastian = user(b"Bastian");
+
+    let id = request_id(requests.request(&bastian, &aurelia));
+    // only the target may answer
+    let err = requests.resolve(&bastian, id).unwrap_err();
+    assert_eq!(err.code, ErrorCode::RoomExpired);
+
+    requests.resolve(&aurelia, id).unwrap();
+    let err = requests.resolve(&aurelia, id).unwrap_err();
+    assert_eq!(err.code, ErrorCode::RoomExpired);
+}
+
+#[tokio::test(start_paused = true)]
+async fn replayed_request_keeps_its_id() {
+    let mut requests = RoomRequests::new(Duration::from_secs(10));
+    let aurelia = user(b"Aurelia");
+    let bastian = user(b"Bastian");
+
+    let first = request_id(requests.request(&bastian, &aurelia));
+    tokio::time::advance(Duration::from_secs(8)).await;
+    let replayed = request_id(requests.request(&bastian, &aurelia));
+    assert_eq!(first, replayed);
+    assert_eq!(requests.len(), 1);
+
+    // the replay extended the deadline
+    tokio::time::advance(Duration::from_secs(8)).await;
+    requests.prune();
+    assert_eq!(requests.resolve(&aurelia, first), Ok(bastian));
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_expire() {
+    let mut requests = RoomRequests::new(Duration::from_secs(10));
+    let aurelia = user(b"Aurelia");
+    let bastian = user(b"Bastian");
+
+    let id = request_id(requests.request(&bastian, &aurelia));
+    tokio::time::advance(Duration::from_secs(10)).await;
+    let err = requests.resolve(&aurelia, id).unwrap_err();
+    assert_eq!(err.code, ErrorCode::RoomExpired);
+
+    request_id(requests.request(&bastian, &aurelia));
+    tokio::time::advance(Duration::from_secs(10)).await;
+    requests.prune();
+    assert!(requests.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn answers_name_the_requested_user() {
+    let mut requests = RoomRequests::default();
+    let aurelia = user(b"Aurelia");
+    let bastian = user(b"Bastian");
+    let cassia = user(b"Cassia");
+
+    // Aurelia has requests to Bastian and Cassia in flight, they answer in reverse order
+    let to_bastian = request_id(requests.request(&aurelia, &bastian));
+    let to_cassia = request_id(requests.request(&aurelia, &cassia));
+
+    let (requester, answer) = requests.answer(&cassia, to_cassia, None).unwrap();
+    assert_eq!(requester, aurelia);
+    assert_eq!(answer, RhizMessage::AcceptedRoom(None, cassia.clone()));
+
+    let room = Some(RoomId([7; 32]));
+    let (requester, answer) = requests.answer(&bastian, to_bastian, room.clone()).unwrap();
+    assert_eq!(requester, aurelia);
+    assert_eq!(answer, RhizMessage::AcceptedRoom(room, bastian));
+
+    let err = requests.answer(&aurelia, to_bastian, None).unwrap_err();
+    assert_eq!(err.code, ErrorCode::RoomExpired);
+}
@@ -0,0 +1,152 @@
+#![cfg(feature = "client")]
+
+use std::io;
+
+use smoke::connection::Connection;
+use smoke::messages::compression::{self, Compression};
+use smoke::messages::vlink;
+use smoke::messages::MessageReader;
+use smoke::scheduler::{self, Priority};
+use smoke::Signal;
+
+use tokio::io::BufReader;
+
+fn data(vport: u16) -> Signal {
+    Signal::Vlink(0, vlink::Signal::Data(vport, vec![0; 1024]))
+}
+
+fn window_update(vport: u16) -> Signal {
+    Signal::Vlink(0, vlink::Signal::WindowUpdate(vport, 10))
+}
+
+#[test]
+fn classification() {
+    assert_eq!(Priority::of(&Signal::Kap), Priority::Control);
+    assert_eq!(
+        Priority::of(&Signal::Vlink(0, vlink::Signal::WindowUpdate(1, 10))),
+        Priority::Control
+    );
+    assert_eq!(
+        Priority::of(&Signal::Message("hi".to_string())),
+        Priority::Interactive
+    );
+    assert_eq!(Priority::of(&data(1)), Priority::Bulk);
+    assert_eq!(
+        Priority::of(&Signal::Vlink(0, vlink::Signal::FlowClosed(1, 2))),
+        Priority::Bulk
+    );
+    assert_eq!(Priority::of(&Signal::VlinkCut(0)), Priority::Bulk);
+}
+
+#[tokio::test]
+async fn closing_signals_do_not_overtake_data() {
+    let (sender, mut receiver) = scheduler::priority_queue(16);
+    let datagram = Signal::Vlink(0, vlink::Signal::Datagram(1, 2, vec![0; 512]));
+    let flow_closed = Signal::Vlink(0, vlink::Signal::FlowClosed(1, 2));
+
+    sender.send(data(1)).await.unwrap();
+    sender.send(datagram.clone()).await.unwrap();
+    sender.send(flow_closed.clone()).await.unwrap();
+    sender
+        .send(Signal::Vlink(
+            0,
+            vlink::Signal::Error(1, vlink::ErrorKindCode::ConnectionReset, String::new()),
+        ))
+        .await
+        .unwrap();
+    sender.send(Signal::VlinkCut(0)).await.unwrap();
+    sender.send(Signal::Kap).await.unwrap();
+    drop(sender);
+
+    assert_eq!(receiver.recv().await, Some(Signal::Kap));
+    assert_eq!(receiver.recv().await, Some(data(1)));
+    assert_eq!(receiver.recv().await, Some(datagram));
+    assert_eq!(receiver.recv().await, Some(flow_closed));
+    assert!(matches!(
+        receiver.recv().await,
+        Some(Signal::Vlink(0, vlink::Signal::Error(1, ..)))
+    ));
+    assert_eq!(receiver.recv().await, Some(Signal::VlinkCut(0)));
+    assert_eq!(receiver.recv().await, None);
+}
+
+#[tokio::test]
+async fn control_overtakes_bulk() {
+    let (sender, mut receiver) = scheduler::priority_queue(16);
+
+    for vport in 0..10 {
+        sender.send(data(vport)).await.unwrap();
+    }
+    sender
+        .send(Signal::Message("hello".to_string()))
+        .await
+        .unwrap();
+    sender.send(Signal::Kap).await.unwrap();
+    sender.send(window_update(1)).await.unwrap();
+    drop(sender);
+
+    assert_eq!(receiver.recv().await, Some(Signal::Kap));
+    assert_eq!(receiver.recv().await, Some(window_update(1)));
+    assert_eq!(
+        receiver.recv().await,
+        Some(Signal::Message("hello".to_string()))
+    );
+    for vport in 0..10 {
+        assert_eq!(receiver.recv().await, Some(data(vport)));
+    }
+    assert_eq!(receiver.recv().await, None);
+}
+
+#[tokio::test]
+async fn full_bulk_queue_does_not_block_control() {
+    let (sender, mut receiver) = scheduler::priority_queue(2);
+    let (tx, rx) = tokio::io::duplex(4096);
+
+    // keeps the bulk queue full while the writer is stalled by the peer
+    let bulk = {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            for vport in 0..100 {
+                sender.send(data(vport)).await?;
+            }
+            io::Result::Ok(())
+        })
+    };
+    tokio::task::yield_now().await;
+    sender.send(Signal::Kap).await.unwrap();
+    drop(sender);
+
+    let writer = tokio::spawn(async move {
+        let mut tx = tx;
+        receiver.forward_to(&mut tx).await
+    });
+
+    let signals: Vec<Signal> =
+        futures::TryStreamExt::try_collect(MessageReader::new(BufReader::new(rx)))
+            .await
+            .unwrap();
+
+    // only the bulk signals that were queued before can precede the keepalive
+    let kap = signals.iter().position(|s| *s == Signal::Kap).unwrap();
+    assert!(kap <= 2, "keepalive was sent after {} signals", kap);
+    assert_eq!(signals.len(), 101);
+
+    bulk.await.unwrap().unwrap();
+    writer.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn forward_compressed_sends_through_the_connection() {
+    let (sender, mut receiver) = scheduler::priority_queue(16);
+    let (a, b) = tokio::io::duplex(64 * 1024);
+    let (_, mut writer) = compression::compressed(Connection::new(a), Compression::default());
+    let (mut reader, _) = compression::compressed(Connection::new(b), Compression::default());
+
+    sender.send(data(1)).await.unwrap();
+    sender.send(Signal::Kap).await.unwrap();
+    drop(sender);
+    receiver.forward_compressed(&mut writer).await.unwrap();
+
+    assert_eq!(reader.recv().await.unwrap(), Signal::Kap);
+    assert_eq!(reader.recv().await.unwrap(), data(1));
+}
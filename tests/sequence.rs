@@ -0,0 +1,180 @@
+use std::io;
+
+use smoke::messages::{
+    EmbMessage, ErrorCode, RequestId, RhizMessage, SequenceCheck, SequenceError, Sequenced,
+    Sequencer, ServerError,
+};
+use smoke::User;
+
+use tokio::io::BufReader;
+
+fn room() -> EmbMessage {
+    EmbMessage::Room(User {
+        cert_data: b"Aurelia".to_vec(),
+    })
+}
+
+#[test]
+fn in_order_messages_are_accepted() {
+    let mut sequencer = Sequencer::new();
+    let mut check = SequenceCheck::new();
+
+    for msg in [
+        room(),
+        EmbMessage::Accept(RequestId(0), true),
+        EmbMessage::Heartbeat,
+    ] {
+        let sequenced = sequencer.wrap(msg.clone());
+        assert_eq!(check.accept(sequenced), Ok(msg));
+    }
+    assert_eq!(sequencer.next_seq(), 3);
+    assert_eq!(check.expected(), 3);
+}
+
+#[test]
+fn duplicate_and_gap_are_detected() {
+    let mut sequencer = Sequencer::new();
+    let mut check = SequenceCheck::new();
+
+    let accept = sequencer.wrap(EmbMessage::Accept(RequestId(0), true));
+    check.accept(accept.clone()).unwrap();
+    assert_eq!(
+        check.accept(accept),
+        Err(SequenceError::Duplicate {
+            expected: 1,
+            received: 0
+        })
+    );
+
+    let _lost = sequencer.wrap(room());
+    let err = check.accept(sequencer.wrap(room())).unwrap_err();
+    assert_eq!(
+        err,
+        SequenceError::Gap {
+            expected: 1,
+            received: 2
+        }
+    );
+    assert_eq!(err.to_string(), "lost messages 1 to 1");
+
+    // rejected messages do not move the expected sequence number
+    assert_eq!(check.expected(), 1);
+}
+
+#[test]
+fn sequence_error_conversions() {
+    let err = SequenceError::Duplicate {
+        expected: 5,
+        received: 2,
+    };
+
+    let server_error = ServerError::from(err);
+    assert_eq!(server_error.code, ErrorCode::ProtocolViolation);
+
+    let io_err = io::Error::from(err);
+    assert_eq!(io_err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        io_err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<SequenceError>()),
+        Some(&err)
+    );
+}
+
+#[tokio::test]
+async fn replayed_accept_over_stream() {
+    let mut sequencer = Sequencer::new();
+    let mut bytes = Vec::new();
+    EmbMessage::Accept(RequestId(0), true)
+        .send_sequenced(&mut bytes, &mut sequencer)
+        .await
+        .unwrap();
+    // a middlebox duplicates the frame
+    let frame = bytes.clone();
+    bytes.extend_from_slice(&frame);
+
+    let mut reader = BufReader::new(bytes.as_slice());
+    let mut check = SequenceCheck::new();
+    let mut buf = Vec::new();
+
+    let msg = EmbMessage::recv_sequenced(&mut reader, &mut buf, &mut check).await;
+    assert_eq!(msg.unwrap(), EmbMessage::Accept(RequestId(0), true));
+
+    let err = EmbMessage::recv_sequenced(&mut reader, &mut buf, &mut check)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // EOF is a regular shutdown
+    let msg = EmbMessage::recv_sequenced(&mut reader, &mut buf, &mut check).await;
+    assert_eq!(msg.unwrap(), EmbMessage::Shutdown);
+}
+
+#[tokio::test]
+async fn rhiz_messages_roundtrip() {
+    let mut sequencer = Sequencer::new();
+    let mut bytes = Vec::new();
+    let msgs = [
+        RhizMessage::WantsRoom(
+            RequestId(1),
+            User {
+                cert_data: b"Bastian".to_vec(),
+            },
+        ),
+        RhizMessage::AcceptedRoom(None, User { cert_data: vec![] }),
+    ];
+    for msg in msgs.clone() {
+        msg.send_sequenced(&mut bytes, &mut sequencer)
+            .await
+            .unwrap();
+    }
+
+    let mut reader = BufReader::new(bytes.as_slice());
+    let mut check = SequenceCheck::new();
+    let mut buf = Vec::new();
+    for msg in msgs {
+        let received = RhizMessage::recv_sequenced(&mut reader, &mut buf, &mut check).await;
+        assert_eq!(received.unwrap(), msg);
+    }
+}
+
+#[test]
+fn sequenced_serialization() {
+    let sequenced = Sequenced {
+        seq: 300,
+        message: EmbMessage::Heartbeat,
+    };
+    let bytes = postcard::to_extend(&sequenced, Vec::new()).unwrap();
+    assert_eq!(
+        postcard::from_bytes::<Sequenced<EmbMessage>>(&bytes),
+        Ok(sequenced)
+    );
+}
+
+#[tokio::test]
+async fn failed_serialization_keeps_sequence_number() {
+    let mut sequencer = Sequencer::new();
+    let mut bytes = Vec::new();
+
+    let oversized = EmbMessage::Room(User {
+        cert_data: vec![0; 2048],
+    });
+    oversized
+        .send_sequenced(&mut bytes, &mut sequencer)
+        .await
+        .expect_err("message exceeds the buffer");
+    assert_eq!(sequencer.next_seq(), 0);
+    assert!(bytes.is_empty());
+
+    EmbMessage::Heartbeat
+        .send_sequenced(&mut bytes, &mut sequencer)
+        .await
+        .unwrap();
+    assert_eq!(sequencer.next_seq(), 1);
+
+    let mut reader = BufReader::new(bytes.as_slice());
+    let mut check = SequenceCheck::new();
+    let mut buf = Vec::new();
+    let msg = EmbMessage::recv_sequenced(&mut reader, &mut buf, &mut check).await;
+    assert_eq!(msg.unwrap(), EmbMessage::Heartbeat);
+}
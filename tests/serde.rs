@@ -1,5 +1,8 @@
+use smoke::messages::vlink::{ErrorKindCode, Signal, SignalRef};
 use smoke::messages::Drain;
 use smoke::messages::EmbMessage;
+use smoke::messages::ErrorCode;
+use smoke::messages::RhizMessage;
 use smoke::messages::Source;
 use smoke::User;
 use tokio::io::BufReader;
@@ -188,4 +191,106 @@ async fn stream_test_fragmented_multi_hybrid() {
     assert_eq!(signal.unwrap(), msg);
 }
 
+#[test_log::test(tokio::test)]
+async fn stream_test_server_error() {
+    let msg = RhizMessage::server_error(ErrorCode::RateLimited, "slow down");
+    let mut msg_bytes = Vec::<u8>::new();
+    let mut ser_buf = [0u8; smoke::messages::rhiz_message::MAX_MESSAGE_BUF_SIZE];
+    msg.clone()
+        .serialize_to(&mut msg_bytes, &mut ser_buf)
+        .expect("could not serialize")
+        .await
+        .unwrap();
+
+    let stream = Builder::new().read(&msg_bytes).build();
+    let mut reader = BufReader::new(stream);
+
+    let signal = reader.read_message::<RhizMessage>().await;
+    assert!(signal.is_ok());
+    let signal = signal.unwrap();
+    assert_eq!(signal, msg);
+
+    let err = signal.into_server_error().expect("not a server error");
+    assert_eq!(err.code, ErrorCode::RateLimited);
+    assert_eq!(err.message, "slow down");
+    assert!(err.is_retryable());
+    assert_eq!(
+        std::io::Error::from(err).kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn stream_test_signal_ref() {
+    let msgs = [
+        Signal::Connect(80),
+        Signal::Data(80, b"Aurelia".repeat(64)),
+        Signal::Error(
+            80,
+            ErrorKindCode::ConnectionRefused,
+            "connection refused".to_string(),
+        ),
+        Signal::WindowUpdate(80, 4096),
+    ];
+    let mut msg_bytes = Vec::<u8>::new();
+    let mut ser_buf = [0u8; 1024];
+    for msg in &msgs {
+        msg.serialize_to(&mut msg_bytes, &mut ser_buf)
+            .expect("could not serialize")
+            .await
+            .unwrap();
+    }
+    let (first, second) = msg_bytes.split_at(msg_bytes.len() / 3);
+
+    let stream = Builder::new().read(first).read(second).build();
+    let mut reader = BufReader::new(stream);
+
+    let mut buf = Vec::new();
+    for msg in &msgs {
+        let signal = reader.read_message_ref::<SignalRef>(&mut buf).await;
+        assert!(signal.is_ok(), "{:?}", signal.unwrap_err());
+        let signal = signal.unwrap();
+        assert_eq!(signal, msg.as_signal_ref());
+        assert_eq!(&signal.to_signal(), msg);
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn stream_test_signal_ref_to_owned() {
+    let data = b"Aurelia".repeat(64);
+    let msg = SignalRef::Data(80, &data);
+    let mut msg_bytes = Vec::<u8>::new();
+    let mut ser_buf = [0u8; 1024];
+    msg.serialize_to(&mut msg_bytes, &mut ser_buf)
+        .expect("could not serialize")
+        .await
+        .unwrap();
+
+    let stream = Builder::new().read(&msg_bytes).build();
+    let mut reader = BufReader::new(stream);
+
+    let signal = reader.read_message::<Signal>().await;
+    assert!(signal.is_ok());
+    assert_eq!(signal.unwrap(), Signal::Data(80, data));
+}
+
+#[test]
+fn vlink_error_kind_roundtrip() {
+    let err = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+    let signal = Signal::from_vlink(&vlink::Action::Error(80, err));
+    assert_eq!(
+        signal,
+        Signal::Error(80, ErrorKindCode::ConnectionRefused, "refused".to_string())
+    );
+
+    let mut ser_buf = [0u8; 64];
+    let bytes = postcard::to_slice(&signal, &mut ser_buf).unwrap();
+    let signal: Signal = postcard::from_bytes(bytes).unwrap();
+    let Some(vlink::Action::Error(80, err)) = signal.as_vlink() else {
+        panic!("expected Action::Error");
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    assert_eq!(err.to_string(), "refused");
+}
+
 //TODO Read Error tests
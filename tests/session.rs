@@ -0,0 +1,276 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use smoke::messages::{EmbMessage, ErrorCode, RhizMessage, SequenceCheck, Sequencer, SessionToken};
+use smoke::session::{Backoff, ResumableLink, SessionStore};
+
+use tokio::io::{BufReader, DuplexStream};
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+mod common;
+use common::user;
+
+/// Server side framing of [serve], `None` for plain framing
+type Sequence = Option<(Sequencer, SequenceCheck)>;
+
+async fn answer(
+    msg: RhizMessage,
+    stream: &mut BufReader<DuplexStream>,
+    sequence: &mut Sequence,
+) -> std::io::Result<()> {
+    match sequence {
+        Some((sequencer, _)) => msg.send_sequenced(stream, sequencer).await,
+        None => msg.send_to(stream).await,
+    }
+}
+
+async fn request(
+    stream: &mut BufReader<DuplexStream>,
+    buf: &mut Vec<u8>,
+    sequence: &mut Sequence,
+) -> std::io::Result<EmbMessage> {
+    match sequence {
+        Some((_, check)) => EmbMessage::recv_sequenced(stream, buf, check).await,
+        None => EmbMessage::recv_from(stream, buf).await,
+    }
+}
+
+/// Minimal in process Rhizome that drops its first connection after receiving a request
+async fn serve(
+    stream: DuplexStream,
+    store: Arc<Mutex<SessionStore>>,
+    connection: usize,
+    mut sequence: Sequence,
+) -> std::io::Result<()> {
+    let client = user(b"Aurelia");
+    let mut stream = BufReader::new(stream);
+    let mut buf = Vec::new();
+
+    let mut token = store.lock().await.issue(client.clone())?;
+    answer(
+        RhizMessage::Session(token.clone()),
+        &mut stream,
+        &mut sequence,
+    )
+    .await?;
+
+    loop {
+        match request(&mut stream, &mut buf, &mut sequence).await? {
+            EmbMessage::Resume(previous) => {
+                let msg = match store.lock().await.resume(&token, previous, &client) {
+                    Ok(resumed) => {
+                        token = resumed;
+                        RhizMessage::Session(token.clone())
+                    }
+                    Err(err) => err.into(),
+                };
+                answer(msg, &mut stream, &mut sequence).await?;
+            }
+            EmbMessage::Room(_) if connection == 0 => {
+                // simulate a lost connection before the request is answered
+                store.lock().await.suspend(&token);
+                return Ok(());
+            }
+            EmbMessage::Room(target) => {
+                answer(RhizMessage::NoRoute(target), &mut stream, &mut sequence).await?;
+            }
+            EmbMessage::Shutdown => {
+                store.lock().await.end(&token);
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn resume_and_replay(sequenced: bool) {
+    let store = Arc::new(Mutex::new(SessionStore::new(Duration::from_secs(60))));
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let connect = {
+        let store = store.clone();
+        let connections = connections.clone();
+        move || {
+            let (client, server) = tokio::io::duplex(1024);
+            let connection = connections.fetch_add(1, Ordering::SeqCst);
+            let sequence = sequenced.then(Default::default);
+            tokio::spawn(serve(server, store.clone(), connection, sequence));
+            async move { Ok(client) }
+        }
+    };
+
+    let backoff = Backoff {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(10),
+        max_attempts: Some(3),
+    };
+    let link = if sequenced {
+        ResumableLink::connect_sequenced(connect, backoff).await
+    } else {
+        ResumableLink::connect(connect, backoff).await
+    };
+    let mut link = link.expect("could not connect");
+    let first_token = link.token().cloned().expect("no session token");
+
+    let target = user(b"Bastian");
+    link.send(EmbMessage::Room(target.clone()))
+        .await
+        .expect("could not send");
+    assert_eq!(link.in_flight(), std::slice::from_ref(&target));
+
+    // the first connection is dropped, the request has to be replayed on the second one
+    let msg = link.recv().await.expect("could not receive");
+    assert_eq!(msg, RhizMessage::NoRoute(target));
+    assert!(link.in_flight().is_empty());
+
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    assert_eq!(link.token(), Some(&first_token));
+}
+
+#[test_log::test(tokio::test)]
+async fn reconnect_resumes_session_and_replays_requests() {
+    resume_and_replay(false).await;
+}
+
+#[test_log::test(tokio::test)]
+async fn sequenced_link_restarts_sequence_on_reconnect() {
+    resume_and_replay(true).await;
+}
+
+#[tokio::test]
+async fn answers_clear_their_own_request() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = Some(client);
+    let connect = move || {
+        let stream = client
+            .take()
+            .ok_or_else(|| std::io::ErrorKind::NotConnected.into());
+        async move { stream }
+    };
+
+    let (bastian, cassia) = (user(b"Bastian"), user(b"Cassia"));
+    let rhizome = {
+        let (bastian, cassia) = (bastian.clone(), cassia.clone());
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(server);
+            let mut buf = Vec::new();
+            RhizMessage::Session(SessionToken([1; 32]))
+                .send_to(&mut stream)
+                .await?;
+            for _ in 0..2 {
+                EmbMessage::recv_from(&mut stream, &mut buf).await?;
+            }
+            // answer the second request first
+            RhizMessage::AcceptedRoom(None, cassia)
+                .send_to(&mut stream)
+                .await?;
+            RhizMessage::NoRoute(bastian).send_to(&mut stream).await?;
+            std::io::Result::Ok(stream)
+        })
+    };
+
+    let backoff = Backoff {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(1),
+        max_attempts: Some(1),
+    };
+    let mut link = ResumableLink::connect(connect, backoff)
+        .await
+        .expect("could not connect");
+    link.send(EmbMessage::Room(bastian.clone())).await.unwrap();
+    link.send(EmbMessage::Room(cassia.clone())).await.unwrap();
+
+    let msg = link.recv().await.unwrap();
+    assert_eq!(msg, RhizMessage::AcceptedRoom(None, cassia));
+    assert_eq!(link.in_flight(), std::slice::from_ref(&bastian));
+
+    let msg = link.recv().await.unwrap();
+    assert_eq!(msg, RhizMessage::NoRoute(bastian));
+    assert!(link.in_flight().is_empty());
+
+    let _stream = rhizome.await.unwrap().unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn session_expires() {
+    let mut store = SessionStore::new(Duration::from_secs(60));
+    let client = user(b"Aurelia");
+
+    let old = store.issue(client.clone()).unwrap();
+    let current = store.issue(client.clone()).unwrap();
+
+    // attached sessions cannot be taken over
+    let err = store
+        .resume(&current, old.clone(), &client)
+        .expect_err("attached session should not be resumable");
+    assert_eq!(err.code, ErrorCode::SessionExpired);
+
+    store.suspend(&old);
+    tokio::time::advance(Duration::from_secs(61)).await;
+    store.prune();
+
+    let err = store
+        .resume(&current, old, &client)
+        .expect_err("expired session should not be resumable");
+    assert_eq!(err.code, ErrorCode::SessionExpired);
+}
+
+#[tokio::test(start_paused = true)]
+async fn session_belongs_to_user() {
+    let mut store = SessionStore::new(Duration::from_secs(60));
+    let client = user(b"Aurelia");
+
+    let old = store.issue(client.clone()).unwrap();
+    store.suspend(&old);
+    let other = store.issue(user(b"Bastian")).unwrap();
+    let current = store.issue(client.clone()).unwrap();
+
+    assert!(store
+        .resume(&other, old.clone(), &user(b"Bastian"))
+        .is_err());
+    assert_eq!(store.resume(&current, old.clone(), &client), Ok(old));
+}
+
+#[tokio::test(start_paused = true)]
+async fn foreign_current_session_is_kept() {
+    let mut store = SessionStore::new(Duration::from_secs(60));
+    let (victim, attacker) = (user(b"Aurelia"), user(b"Bastian"));
+
+    let victim_session = store.issue(victim.clone()).unwrap();
+    let old = store.issue(attacker.clone()).unwrap();
+    store.suspend(&old);
+
+    // resuming with the current token of someone else must not end that session
+    let err = store
+        .resume(&victim_session, old.clone(), &attacker)
+        .unwrap_err();
+    assert_eq!(err.code, ErrorCode::ProtocolViolation);
+    store.suspend(&victim_session);
+    assert_eq!(
+        store.resume(&victim_session, victim_session.clone(), &victim),
+        Ok(victim_session)
+    );
+}
+
+#[test]
+fn token_is_not_printed() {
+    let token = SessionToken([0xab; 32]);
+    assert_eq!(format!("{:?}", token), "SessionToken(<redacted>)");
+    assert!(!format!("{:?}", RhizMessage::Session(token)).contains("171"));
+}
+
+#[test]
+fn backoff_is_exponential_and_bounded() {
+    let backoff = Backoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+        max_attempts: None,
+    };
+
+    assert_eq!(backoff.delay(0), Duration::from_millis(100));
+    assert_eq!(backoff.delay(1), Duration::from_millis(200));
+    assert_eq!(backoff.delay(3), Duration::from_millis(800));
+    assert_eq!(backoff.delay(4), Duration::from_secs(1));
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+}
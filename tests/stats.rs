@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use smoke::bridge::{self, SendWindow};
+use smoke::datagram;
+use smoke::messages::vlink::{ErrorKindCode, Signal};
+use smoke::stats::TrafficStats;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+
+const VPORT: u16 = 8080;
+
+#[tokio::test(start_paused = true)]
+async fn bridge_accounts_bytes() {
+    let stats = Arc::new(TrafficStats::new());
+    stats.record(&Signal::Connect(VPORT));
+
+    let (mut app, mut local) = tokio::io::duplex(1024);
+    app.write_all(&[7u8; 300]).await.unwrap();
+    app.shutdown().await.unwrap();
+
+    let (link_tx, mut link_rx) = mpsc::channel(16);
+    let window = SendWindow::new(bridge::DEFAULT_WINDOW).with_stats(stats.clone());
+    bridge::forward_outbound(VPORT, &mut local, &window, &link_tx)
+        .await
+        .unwrap();
+    drop(link_tx);
+
+    let (gate, _queue) = bridge::recv_window(bridge::DEFAULT_WINDOW);
+    let gate = gate.with_stats(VPORT, stats.clone());
+    while let Some(Signal::Data(_, data)) = link_rx.recv().await {
+        gate.admit(data).unwrap();
+    }
+    gate.admit(vec![0u8; 20]).unwrap();
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.link.bytes_out, 300);
+    assert_eq!(snapshot.link.bytes_in, 320);
+    assert_eq!(snapshot.link.connects, 1);
+    assert_eq!(snapshot.link_open_for, Duration::from_secs(5));
+
+    let vport = snapshot.vports[&VPORT];
+    assert_eq!(vport.counters, snapshot.link);
+    assert_eq!(snapshot.vport_totals[&VPORT], snapshot.link);
+    assert_eq!(vport.open_for, Duration::from_secs(5));
+}
+
+#[tokio::test(start_paused = true)]
+async fn error_closes_vport() {
+    let stats = TrafficStats::new();
+    stats.record(&Signal::Connect(1));
+    stats.record(&Signal::Connect(2));
+    stats.record(&Signal::AcceptError(ErrorKindCode::Other, "accept".into()));
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    stats.record(&Signal::Error(
+        1,
+        ErrorKindCode::ConnectionReset,
+        "reset".into(),
+    ));
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.link.connects, 2);
+    assert_eq!(snapshot.link.errors, 2);
+
+    // the closed vport is pruned, its traffic stays in the totals
+    assert!(!snapshot.vports.contains_key(&1));
+    assert_eq!(snapshot.vport_totals[&1].connects, 1);
+    assert_eq!(snapshot.vport_totals[&1].errors, 1);
+
+    let open = snapshot.vports[&2];
+    assert_eq!(open.open_for, Duration::from_secs(5));
+
+    // reconnecting starts the vport over
+    stats.record(&Signal::Connect(1));
+    let snapshot = stats.snapshot();
+    let reopened = snapshot.vports[&1];
+    assert_eq!(reopened.counters.connects, 1);
+    assert_eq!(reopened.counters.errors, 0);
+    assert_eq!(reopened.open_for, Duration::ZERO);
+    assert_eq!(snapshot.vport_totals[&1].connects, 2);
+}
+
+#[tokio::test]
+async fn bridge_records_local_errors() {
+    let stats = Arc::new(TrafficStats::new());
+    stats.record(&Signal::Connect(VPORT));
+
+    let (gate, queue) = bridge::recv_window(bridge::DEFAULT_WINDOW);
+    let mut queue = queue.with_stats(stats.clone());
+    gate.admit(b"hello".to_vec()).unwrap();
+
+    // the local application already went away
+    let (mut local, app) = tokio::io::duplex(1024);
+    drop(app);
+    let (link_tx, _link_rx) = mpsc::channel(16);
+    let err = bridge::forward_inbound(VPORT, &mut local, &mut queue, &link_tx)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.link.errors, 1);
+    assert!(!snapshot.vports.contains_key(&VPORT));
+    assert_eq!(snapshot.vport_totals[&VPORT].errors, 1);
+}
+
+#[test_log::test(tokio::test)]
+async fn datagram_flows_are_accounted_and_pruned() {
+    let stats = Arc::new(TrafficStats::new());
+    let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (link_tx, mut link_out) = mpsc::channel(16);
+    let (link_in, mut link_rx) = mpsc::channel(16);
+    let driver = {
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            datagram::forward_listener(
+                VPORT,
+                &listener,
+                datagram::DEFAULT_IDLE_TIMEOUT,
+                &mut link_rx,
+                &link_tx,
+                Some(stats),
+            )
+            .await
+        })
+    };
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(addr).await.unwrap();
+    client.send(b"ping").await.unwrap();
+    let signal = timeout(Duration::from_secs(5), link_out.recv())
+        .await
+        .expect("datagram was not forwarded")
+        .unwrap();
+    let Signal::Datagram(VPORT, flow, _) = signal else {
+        panic!("expected datagram, got {:?}", signal);
+    };
+
+    link_in
+        .send(Signal::Datagram(VPORT, flow, b"pong!".to_vec()))
+        .await
+        .unwrap();
+    let mut buf = [0u8; 64];
+    let n = timeout(Duration::from_secs(5), client.recv(&mut buf))
+        .await
+        .expect("no answer")
+        .unwrap();
+    assert_eq!(&buf[..n], b"pong!");
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.link.bytes_out, 4);
+    assert_eq!(snapshot.link.bytes_in, 5);
+    assert!(snapshot.vports.contains_key(&VPORT));
+
+    // closing the last flow closes the vport, its traffic stays in the totals
+    link_in.send(Signal::FlowClosed(VPORT, flow)).await.unwrap();
+    drop(link_in);
+    driver.await.unwrap().unwrap();
+
+    let snapshot = stats.snapshot();
+    assert!(snapshot.vports.is_empty());
+    assert_eq!(snapshot.link.bytes_in, 5);
+    assert_eq!(snapshot.vport_totals[&VPORT].bytes_out, 4);
+    assert_eq!(snapshot.vport_totals[&VPORT].bytes_in, 5);
+}
+
+#[tokio::test]
+async fn cut_removes_all_vports() {
+    let stats = TrafficStats::new();
+    stats.record(&Signal::Connect(1));
+    stats.add_bytes_in(1, 10);
+    stats.add_datagram_out(2, 0, 10);
+
+    stats.cut();
+
+    let snapshot = stats.snapshot();
+    assert!(snapshot.vports.is_empty());
+    assert_eq!(snapshot.link.connects, 1);
+    assert_eq!(snapshot.link.bytes_in, 10);
+    assert_eq!(snapshot.link.bytes_out, 10);
+    assert_eq!(snapshot.vport_totals[&1].bytes_in, 10);
+    assert_eq!(snapshot.vport_totals[&2].bytes_out, 10);
+}
@@ -0,0 +1,118 @@
+#![cfg(feature = "client")]
+
+use smoke::messages::vlink;
+use smoke::messages::Drain;
+use smoke::messages::Source;
+use smoke::tunnels::{Role, Tunnels};
+use smoke::Signal;
+
+use tokio::io::BufReader;
+use tokio_test::io::Builder;
+
+#[test]
+fn open_allocates_unique_ids() {
+    let mut tunnels = Tunnels::new(Role::Initiator);
+
+    let first = tunnels.open("game".to_string(), ()).unwrap();
+    let second = tunnels.open("http".to_string(), ()).unwrap();
+    assert_eq!(first, Signal::VlinkOpen(0, "game".to_string()));
+    assert_eq!(second, Signal::VlinkOpen(2, "http".to_string()));
+
+    tunnels.accept(1, "voice".to_string(), ()).unwrap();
+    let third = tunnels.open("preview".to_string(), ()).unwrap();
+    assert_eq!(third, Signal::VlinkOpen(4, "preview".to_string()));
+
+    assert_eq!(tunnels.len(), 4);
+    assert!(tunnels.get(1).unwrap().remote);
+    assert!(!tunnels.get(4).unwrap().remote);
+}
+
+#[test]
+fn accept_rejects_used_ids() {
+    let mut tunnels = Tunnels::new(Role::Responder);
+    tunnels.accept(0, "game".to_string(), ()).unwrap();
+
+    let err = tunnels
+        .accept(0, "http".to_string(), ())
+        .expect_err("id 0 is already in use");
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+
+    // odd ids belong to the responder itself
+    let err = tunnels
+        .accept(1, "http".to_string(), ())
+        .expect_err("id 1 belongs to the responder");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn simultaneous_open_does_not_collide() {
+    let mut initiator = Tunnels::new(Role::Initiator);
+    let mut responder = Tunnels::new(Role::Responder);
+
+    // both peers open a vlink before seeing the VlinkOpen of the other
+    let Signal::VlinkOpen(initiator_id, initiator_name) =
+        initiator.open("game".to_string(), ()).unwrap()
+    else {
+        panic!("expected VlinkOpen");
+    };
+    let Signal::VlinkOpen(responder_id, responder_name) =
+        responder.open("http".to_string(), ()).unwrap()
+    else {
+        panic!("expected VlinkOpen");
+    };
+    assert_ne!(initiator_id, responder_id);
+
+    initiator
+        .accept(responder_id, responder_name, ())
+        .expect("responder id is free on the initiator");
+    responder
+        .accept(initiator_id, initiator_name, ())
+        .expect("initiator id is free on the responder");
+
+    assert!(!initiator.get(initiator_id).unwrap().remote);
+    assert!(initiator.get(responder_id).unwrap().remote);
+    assert!(responder.get(initiator_id).unwrap().remote);
+    assert!(!responder.get(responder_id).unwrap().remote);
+}
+
+#[test]
+fn cut_removes_only_its_tunnel() {
+    let mut tunnels = Tunnels::new(Role::Initiator);
+    tunnels.open("game".to_string(), 1u32).unwrap();
+    tunnels.open("http".to_string(), 2u32).unwrap();
+
+    let cut = tunnels.cut(0).expect("tunnel 0 should exist");
+    assert_eq!(cut.name, "game");
+    assert_eq!(cut.bridge, 1);
+
+    assert!(tunnels.get_mut(0).is_none());
+    assert_eq!(tunnels.get_mut(2).unwrap().bridge, 2);
+}
+
+#[test_log::test(tokio::test)]
+async fn signals_carry_tunnel_id() {
+    let signals = [
+        Signal::VlinkOpen(7, "http".to_string()),
+        Signal::Vlink(7, vlink::Signal::Connect(80)),
+        Signal::Vlink(7, vlink::Signal::Data(80, b"GET /".to_vec())),
+        Signal::VlinkCut(7),
+    ];
+    let mut msg_bytes = Vec::<u8>::new();
+    let mut ser_buf = [0u8; smoke::messages::signal::MAX_SIGNAL_BUF_SIZE];
+    for signal in &signals {
+        signal
+            .serialize_to(&mut msg_bytes, &mut ser_buf)
+            .expect("could not serialize")
+            .await
+            .unwrap();
+    }
+
+    let stream = Builder::new().read(&msg_bytes).build();
+    let mut reader = BufReader::new(stream);
+
+    for expected in signals {
+        let signal = reader.read_message::<Signal>().await;
+        assert!(signal.is_ok());
+        assert_eq!(signal.unwrap(), expected);
+    }
+}